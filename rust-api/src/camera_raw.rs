@@ -0,0 +1,126 @@
+// Camera RAW files (CR2, NEF, ARW, DNG, ORF, RW2, RAF). These are read-only
+// inputs: rather than demosaicing the sensor data, the largest JPEG preview
+// the camera embedded is decoded, which is full size on most bodies.
+
+// Recognize a RAW container from its header. Nikon, Sony and DNG files use a
+// plain TIFF header, so those are told apart by the IFD0 tags.
+pub fn is_camera_raw(bytes: &[u8]) -> bool {
+    match bytes {
+        // Canon CR2: TIFF header followed by "CR" and the major version
+        [b'I', b'I', 0x2A, 0x00, _, _, _, _, b'C', b'R', 0x02, ..] => true,
+        // Olympus ORF
+        [b'I', b'I', b'R', b'O', ..] | [b'I', b'I', b'R', b'S', ..] | [b'M', b'M', b'O', b'R', ..] => true,
+        // Panasonic RW2
+        [b'I', b'I', b'U', 0x00, ..] => true,
+        _ if bytes.starts_with(b"FUJIFILMCCD-RAW") => true,
+        _ => tiff_is_camera_raw(bytes),
+    }
+}
+
+fn tiff_is_camera_raw(bytes: &[u8]) -> bool {
+    let little_endian = match bytes.get(..4) {
+        Some(b"II*\0") => true,
+        Some(b"MM\0*") => false,
+        _ => return false,
+    };
+    let u16_at = |pos: usize| {
+        let b: [u8; 2] = bytes.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) })
+    };
+    let u32_at = |pos: usize| {
+        let b: [u8; 4] = bytes.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) } as usize)
+    };
+
+    let Some(ifd) = u32_at(4) else { return false };
+    let Some(count) = u16_at(ifd) else { return false };
+    for i in 0..count as usize {
+        let entry = ifd + 2 + 12 * i;
+        match u16_at(entry) {
+            // DNGVersion
+            Some(0xC612) => return true,
+            // Make, an ASCII string stored inline when it fits in 4 bytes
+            Some(0x010F) => {
+                let Some(length) = u32_at(entry + 4) else { return false };
+                let offset = if length <= 4 { Some(entry + 8) } else { u32_at(entry + 8) };
+                let make = offset.and_then(|offset| bytes.get(offset..offset.checked_add(length)?));
+                return make.is_some_and(|make| make.starts_with(b"NIKON") || make.starts_with(b"SONY"));
+            }
+            Some(_) => {}
+            None => return false,
+        }
+    }
+    false
+}
+
+// The largest baseline or progressive JPEG embedded in a RAW file, found by
+// scanning for SOI markers and reading each candidate's frame header.
+// Lossless JPEG (SOF3) streams hold the sensor data itself and are skipped.
+pub fn embedded_preview(data: &[u8]) -> Option<&[u8]> {
+    let mut best: Option<(u64, usize)> = None;
+    let mut pos = 0;
+    while let Some(found) = data[pos..].windows(3).position(|w| w == [0xFF, 0xD8, 0xFF]) {
+        let start = pos + found;
+        if let Some((width, height)) = jpeg_frame_size(&data[start..]) {
+            let area = width as u64 * height as u64;
+            if best.is_none_or(|(best_area, _)| area > best_area) {
+                best = Some((area, start));
+            }
+        }
+        pos = start + 2;
+    }
+    best.map(|(_, start)| &data[start..])
+}
+
+// Width and height from the SOF0/1/2 segment of a JPEG stream
+fn jpeg_frame_size(jpeg: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        let marker = jpeg.get(pos..pos + 2)?;
+        if marker[0] != 0xFF {
+            return None;
+        }
+        let length = u16::from_be_bytes(jpeg.get(pos + 2..pos + 4)?.try_into().ok()?) as usize;
+        match marker[1] {
+            0xC0..=0xC2 => {
+                let header = jpeg.get(pos + 5..pos + 9)?;
+                let height = u16::from_be_bytes([header[0], header[1]]) as u32;
+                let width = u16::from_be_bytes([header[2], header[3]]) as u32;
+                return (width > 0 && height > 0).then_some((width, height));
+            }
+            // Any other frame type, or image data before a frame header
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF | 0xDA => return None,
+            _ => pos += 2 + length,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_utils::{get_image_dimensions, load_image_from_bytes};
+    use crate::test_support::encode;
+    use crate::types::ImageFormat;
+    use image::{DynamicImage, GenericImageView};
+
+    // A CR2-shaped file: TIFF header with the "CR" marker and an empty IFD0,
+    // then a small thumbnail and a bigger preview, as cameras lay them out
+    fn sample_cr2() -> Vec<u8> {
+        let mut cr2 = b"II*\0\x10\0\0\0CR\x02\0\0\0\0\0".to_vec();
+        cr2.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        cr2.extend(encode(&DynamicImage::new_rgb8(160, 120), ImageFormat::Jpeg));
+        cr2.extend(encode(&DynamicImage::new_rgb8(600, 400), ImageFormat::Jpeg));
+        cr2
+    }
+
+    #[test]
+    fn raw_sample_reports_its_preview_dimensions() {
+        let cr2 = sample_cr2();
+        assert!(is_camera_raw(&cr2));
+        assert_eq!(ImageFormat::from_magic_bytes(&cr2), Some(ImageFormat::Raw));
+
+        let info = get_image_dimensions(&cr2, "IMG_0001.CR2").unwrap();
+        assert_eq!((info.width, info.height, info.format.as_str()), (600, 400, "RAW"));
+        assert_eq!(load_image_from_bytes(&cr2).unwrap().dimensions(), (600, 400));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::image_utils::{lab_to_rgb, rgb_to_lab};
+use crate::types::AppError;
+use image::{imageops, DynamicImage, GenericImageView, RgbaImage};
+use ort::{session::Session, value::Tensor};
+use std::{env, path::Path, sync::{Mutex, OnceLock}};
+
+// Default location of the bundled colorization model (override with COLORIZE_MODEL_PATH).
+// Expects the Zhang et al. layout: L channel in, ab channels out, in CIE Lab units.
+const DEFAULT_MODEL_PATH: &str = "assets/colorization.onnx";
+
+// The model predicts color at this size; the full-resolution lightness is
+// kept from the input, so memory stays bounded however large the image is
+const MODEL_SIZE: u32 = 256;
+
+// Rows recombined per chunk when upsampling the predicted color
+const CHUNK_ROWS: u32 = 256;
+
+static SESSION: OnceLock<Option<Mutex<Session>>> = OnceLock::new();
+
+fn session() -> Result<Option<&'static Mutex<Session>>, AppError> {
+    let path = env::var("COLORIZE_MODEL_PATH").unwrap_or_else(|_| DEFAULT_MODEL_PATH.to_string());
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+
+    if let Some(session) = SESSION.get() {
+        return Ok(session.as_ref());
+    }
+
+    let session = Session::builder()
+        .and_then(|builder| builder.commit_from_file(&path))
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to load colorization model: {}", e)))?;
+
+    Ok(SESSION.get_or_init(|| Some(Mutex::new(session))).as_ref())
+}
+
+// Predict color for a grayscale image. Returns None when the model file is
+// not available.
+pub fn colorize(img: &DynamicImage) -> Result<Option<DynamicImage>, AppError> {
+    let Some(session) = session()? else {
+        return Ok(None);
+    };
+    let mut session = session
+        .lock()
+        .map_err(|_| AppError::ImageProcessingError("Colorization session poisoned".to_string()))?;
+
+    // Predict ab at the model's size from the lightness alone
+    let small = img.resize_exact(MODEL_SIZE, MODEL_SIZE, imageops::FilterType::Triangle).to_rgb8();
+    let plane = (MODEL_SIZE * MODEL_SIZE) as usize;
+    let input: Vec<f32> = small.pixels().map(|p| rgb_to_lab(p.0)[0]).collect();
+
+    let tensor = Tensor::from_array(([1usize, 1, MODEL_SIZE as usize, MODEL_SIZE as usize], input))
+        .map_err(|e| AppError::ImageProcessingError(format!("Colorization input error: {}", e)))?;
+    let outputs = session
+        .run(ort::inputs![tensor])
+        .map_err(|e| AppError::ImageProcessingError(format!("Colorization failed: {}", e)))?;
+    let (shape, data) = outputs[0]
+        .try_extract_tensor::<f32>()
+        .map_err(|e| AppError::ImageProcessingError(format!("Colorization output error: {}", e)))?;
+
+    let (ab_h, ab_w) = (shape[2] as u32, shape[3] as u32);
+    let ab_plane = (ab_w * ab_h) as usize;
+    if data.len() < ab_plane * 2 || plane == 0 {
+        return Err(AppError::ImageProcessingError("Unexpected colorization output shape".to_string()));
+    }
+
+    // Combine the full-resolution L with bilinearly upsampled ab, a band of
+    // rows at a time
+    let (width, height) = img.dimensions();
+    let source = img.to_rgba8();
+    let mut output = RgbaImage::new(width, height);
+    let sample = |channel: usize, x: f32, y: f32| {
+        let x = x.clamp(0.0, (ab_w - 1) as f32);
+        let y = y.clamp(0.0, (ab_h - 1) as f32);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(ab_w as usize - 1), (y0 + 1).min(ab_h as usize - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let at = |x: usize, y: usize| data[channel * ab_plane + y * ab_w as usize + x];
+        let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+        let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    };
+
+    for band in (0..height).step_by(CHUNK_ROWS as usize) {
+        for y in band..(band + CHUNK_ROWS).min(height) {
+            let ab_y = (y as f32 + 0.5) * ab_h as f32 / height as f32 - 0.5;
+            for x in 0..width {
+                let ab_x = (x as f32 + 0.5) * ab_w as f32 / width as f32 - 0.5;
+                let pixel = source.get_pixel(x, y);
+                let lightness = rgb_to_lab([pixel[0], pixel[1], pixel[2]])[0];
+                let [r, g, b] = lab_to_rgb([lightness, sample(0, ab_x, ab_y), sample(1, ab_x, ab_y)]);
+                output.put_pixel(x, y, image::Rgba([r, g, b, pixel[3]]));
+            }
+        }
+    }
+
+    Ok(Some(DynamicImage::ImageRgba8(output)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colorized_output_has_chroma_the_input_lacks() {
+        let gray = DynamicImage::ImageLuma8(image::GrayImage::from_fn(64, 48, |x, y| image::Luma([(x * 3 + y) as u8])));
+        let chroma_variance = |img: &DynamicImage| {
+            let chroma: Vec<f32> = img.to_rgb8().pixels().map(|p| {
+                let [_, a, b] = rgb_to_lab(p.0);
+                (a * a + b * b).sqrt()
+            }).collect();
+            let mean = chroma.iter().sum::<f32>() / chroma.len() as f32;
+            chroma.iter().map(|c| (c - mean).powi(2)).sum::<f32>() / chroma.len() as f32
+        };
+        assert!(chroma_variance(&gray) < 1.0);
+
+        // Without the model file there is nothing more to check
+        if let Some(colorized) = colorize(&gray).unwrap() {
+            assert_eq!(colorized.dimensions(), gray.dimensions());
+            assert!(chroma_variance(&colorized) > chroma_variance(&gray));
+        }
+    }
+}
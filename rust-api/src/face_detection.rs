@@ -0,0 +1,149 @@
+use crate::types::{AppError, FaceBox};
+use image::{imageops, DynamicImage, GenericImageView};
+use ort::{session::Session, value::Tensor};
+use std::{env, path::Path, sync::{Mutex, OnceLock}};
+
+// Default location of the bundled face detection model (override with FACE_MODEL_PATH).
+// Expects the Ultra-Light-Fast-Generic-Face-Detector (RFB-320) layout: RGB in,
+// per-anchor [background, face] scores and normalized x1,y1,x2,y2 boxes out.
+const DEFAULT_MODEL_PATH: &str = "assets/face-detector-320.onnx";
+
+const INPUT_WIDTH: u32 = 320;
+const INPUT_HEIGHT: u32 = 240;
+
+// Anchors scoring below this aren't faces
+const SCORE_THRESHOLD: f32 = 0.7;
+// Overlapping detections of the same face are merged past this IoU
+const NMS_IOU: f32 = 0.3;
+
+static SESSION: OnceLock<Option<Mutex<Session>>> = OnceLock::new();
+
+fn session() -> Result<Option<&'static Mutex<Session>>, AppError> {
+    let path = env::var("FACE_MODEL_PATH").unwrap_or_else(|_| DEFAULT_MODEL_PATH.to_string());
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+
+    if let Some(session) = SESSION.get() {
+        return Ok(session.as_ref());
+    }
+
+    let session = Session::builder()
+        .and_then(|builder| builder.commit_from_file(&path))
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to load face detection model: {}", e)))?;
+
+    Ok(SESSION.get_or_init(|| Some(Mutex::new(session))).as_ref())
+}
+
+// Find faces in the image, most confident first, in source pixel
+// coordinates. Returns None when the model file is not available.
+pub fn detect_faces(img: &DynamicImage) -> Result<Option<Vec<FaceBox>>, AppError> {
+    let Some(session) = session()? else {
+        return Ok(None);
+    };
+    let mut session = session
+        .lock()
+        .map_err(|_| AppError::ImageProcessingError("Face detection session poisoned".to_string()))?;
+
+    // The model takes a fixed size; boxes come back normalized, so the
+    // stretch doesn't matter when mapping them back
+    let small = img.resize_exact(INPUT_WIDTH, INPUT_HEIGHT, imageops::FilterType::Triangle).to_rgb8();
+    let plane = (INPUT_WIDTH * INPUT_HEIGHT) as usize;
+    let mut input = vec![0f32; plane * 3];
+    for (i, pixel) in small.pixels().enumerate() {
+        for c in 0..3 {
+            input[c * plane + i] = (pixel[c] as f32 - 127.0) / 128.0;
+        }
+    }
+
+    let tensor = Tensor::from_array(([1usize, 3, INPUT_HEIGHT as usize, INPUT_WIDTH as usize], input))
+        .map_err(|e| AppError::ImageProcessingError(format!("Face detection input error: {}", e)))?;
+    let outputs = session
+        .run(ort::inputs![tensor])
+        .map_err(|e| AppError::ImageProcessingError(format!("Face detection failed: {}", e)))?;
+    let (_, scores) = outputs[0]
+        .try_extract_tensor::<f32>()
+        .map_err(|e| AppError::ImageProcessingError(format!("Face detection output error: {}", e)))?;
+    let (_, boxes) = outputs[1]
+        .try_extract_tensor::<f32>()
+        .map_err(|e| AppError::ImageProcessingError(format!("Face detection output error: {}", e)))?;
+    if scores.len() / 2 != boxes.len() / 4 {
+        return Err(AppError::ImageProcessingError("Unexpected face detection output shape".to_string()));
+    }
+
+    let (width, height) = img.dimensions();
+    Ok(Some(faces_from_outputs(scores, boxes, width, height)))
+}
+
+// Turn the model's per-anchor scores and normalized boxes into faces in
+// `width` x `height` pixel coordinates: drop weak anchors, then merge
+// overlapping detections of the same face
+fn faces_from_outputs(scores: &[f32], boxes: &[f32], width: u32, height: u32) -> Vec<FaceBox> {
+    let mut candidates: Vec<(f32, [f32; 4])> = scores
+        .chunks_exact(2)
+        .zip(boxes.chunks_exact(4))
+        .filter(|(score, _)| score[1] >= SCORE_THRESHOLD)
+        .map(|(score, b)| (score[1], [b[0].clamp(0.0, 1.0), b[1].clamp(0.0, 1.0), b[2].clamp(0.0, 1.0), b[3].clamp(0.0, 1.0)]))
+        .collect();
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    // Greedy non-maximum suppression
+    let mut kept: Vec<(f32, [f32; 4])> = Vec::new();
+    for candidate in candidates {
+        if kept.iter().all(|k| iou(&k.1, &candidate.1) <= NMS_IOU) {
+            kept.push(candidate);
+        }
+    }
+
+    kept.into_iter()
+        .map(|(confidence, [x1, y1, x2, y2])| {
+            let x = (x1 * width as f32).round() as u32;
+            let y = (y1 * height as f32).round() as u32;
+            FaceBox {
+                x,
+                y,
+                width: ((x2 * width as f32).round() as u32).saturating_sub(x).max(1),
+                height: ((y2 * height as f32).round() as u32).saturating_sub(y).max(1),
+                confidence,
+            }
+        })
+        .collect()
+}
+
+fn iou(a: &[f32; 4], b: &[f32; 4]) -> f32 {
+    let overlap_w = (a[2].min(b[2]) - a[0].max(b[0])).max(0.0);
+    let overlap_h = (a[3].min(b[3]) - a[1].max(b[1])).max(0.0);
+    let overlap = overlap_w * overlap_h;
+    let area = |r: &[f32; 4]| (r[2] - r[0]) * (r[3] - r[1]);
+    let union = area(a) + area(b) - overlap;
+    if union > 0.0 { overlap / union } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_photo_detections_merge_into_one_box_per_face() {
+        // Three faces, each hit by a few overlapping anchors, plus weak
+        // background anchors, as the model reports a group photo
+        let anchors: &[(f32, [f32; 4])] = &[
+            (0.98, [0.10, 0.20, 0.25, 0.45]),
+            (0.91, [0.11, 0.21, 0.26, 0.46]),
+            (0.95, [0.40, 0.18, 0.55, 0.44]),
+            (0.88, [0.39, 0.19, 0.54, 0.43]),
+            (0.80, [0.41, 0.17, 0.56, 0.45]),
+            (0.93, [0.70, 0.22, 0.85, 0.47]),
+            (0.30, [0.00, 0.60, 0.20, 0.90]),
+            (0.65, [0.60, 0.60, 0.80, 0.90]),
+        ];
+        let scores: Vec<f32> = anchors.iter().flat_map(|(score, _)| [1.0 - score, *score]).collect();
+        let boxes: Vec<f32> = anchors.iter().flat_map(|(_, b)| *b).collect();
+
+        let faces = faces_from_outputs(&scores, &boxes, 1000, 500);
+        assert_eq!(faces.len(), 3);
+        assert!(faces.windows(2).all(|pair| pair[0].confidence >= pair[1].confidence));
+        let first = &faces[0];
+        assert_eq!((first.x, first.y, first.width, first.height), (100, 100, 150, 125));
+    }
+}
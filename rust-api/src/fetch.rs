@@ -0,0 +1,134 @@
+use crate::{types::AppError, validation::MAX_UPLOAD_SIZE};
+use bytes::Bytes;
+use reqwest::{redirect::Policy, Url};
+use std::{env, net::IpAddr, time::Duration};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Download an image for processing in place of a multipart upload.
+// Private and loopback addresses are refused unless ALLOW_PRIVATE_URLS=true.
+pub async fn fetch_image_url(url: &str) -> Result<Bytes, AppError> {
+    let url = Url::parse(url)
+        .map_err(|_| AppError::InvalidFieldValue(format!("Invalid URL: {}", url)))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(AppError::InvalidFieldValue("URL must use http or https".to_string()));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| AppError::InvalidFieldValue("URL has no host".to_string()))?
+        .to_string();
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let allow_private = env::var("ALLOW_PRIVATE_URLS").map(|v| v == "true").unwrap_or(false);
+    let addr = tokio::net::lookup_host((host.trim_matches(|c| c == '[' || c == ']'), port))
+        .await
+        .map_err(|e| AppError::InvalidFieldValue(format!("Could not resolve host {}: {}", host, e)))?
+        .next()
+        .ok_or_else(|| AppError::InvalidFieldValue(format!("Could not resolve host {}", host)))?;
+    if !allow_private && !is_public_ip(addr.ip()) {
+        return Err(AppError::InvalidFieldValue("URL resolves to a private address".to_string()));
+    }
+
+    // Pin the checked address so a second DNS lookup can't rebind it, and
+    // don't follow redirects that could point somewhere private
+    let client = reqwest::Client::builder()
+        .resolve(&host, addr)
+        .redirect(Policy::none())
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to build HTTP client: {}", e)))?;
+
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| AppError::InvalidFieldValue(format!("Failed to fetch URL: {}", e)))?;
+    if !response.status().is_success() {
+        return Err(AppError::InvalidFieldValue(format!(
+            "Failed to fetch URL: server returned {}",
+            response.status()
+        )));
+    }
+    if response.content_length().is_some_and(|len| len > MAX_UPLOAD_SIZE as u64) {
+        return Err(AppError::FileTooLarge);
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| AppError::InvalidFieldValue(format!("Failed to fetch URL: {}", e)))?
+    {
+        if data.len() + chunk.len() > MAX_UPLOAD_SIZE {
+            return Err(AppError::FileTooLarge);
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(Bytes::from(data))
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                // 100.64.0.0/10 carrier-grade NAT
+                || (v4.octets()[0] == 100 && (v4.octets()[1] & 0xC0) == 64))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(v4));
+            }
+            let segments = v6.segments();
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // fc00::/7 unique local, fe80::/10 link local
+                || (segments[0] & 0xFE00) == 0xFC00
+                || (segments[0] & 0xFFC0) == 0xFE80)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_and_loopback_addresses_are_not_public() {
+        for ip in ["127.0.0.1", "10.1.2.3", "192.168.0.10", "172.16.5.5", "169.254.169.254", "100.64.0.1", "::1", "fd00::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{} treated as public", ip);
+        }
+        for ip in ["93.184.216.34", "2606:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{} treated as private", ip);
+        }
+    }
+
+    // One test, since ALLOW_PRIVATE_URLS is process-wide
+    #[tokio::test]
+    async fn fetch_refuses_private_urls_unless_allowed() {
+        let png = b"\x89PNG\r\n\x1a\n not really, but bytes are bytes";
+        let app = axum::Router::new().route("/cat.png", axum::routing::get(|| async { png.as_slice() }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/cat.png", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        for private in [url.as_str(), "http://10.0.0.1/a.png", "http://[::1]/a.png"] {
+            match fetch_image_url(private).await {
+                Err(AppError::InvalidFieldValue(message)) => assert!(message.contains("private"), "{}", message),
+                other => panic!("{} was not refused: {:?}", private, other.map(|b| b.len())),
+            }
+        }
+
+        env::set_var("ALLOW_PRIVATE_URLS", "true");
+        let fetched = fetch_image_url(&url).await;
+        env::remove_var("ALLOW_PRIVATE_URLS");
+        assert_eq!(fetched.unwrap().as_ref(), png.as_slice());
+    }
+}
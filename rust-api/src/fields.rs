@@ -0,0 +1,234 @@
+use axum_extra::extract::{multipart::Field, Multipart};
+use bytes::Bytes;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::types::{AppError, EncodeOptions};
+use crate::validation::{env_limit, parse_finite_f32, validate_upload, MAX_UPLOAD_SIZE};
+
+// Forms with more parts than this (MAX_FORM_FIELDS, default 32) are
+// rejected outright instead of being parsed to the end
+static MAX_FORM_FIELDS: OnceLock<usize> = OnceLock::new();
+
+fn max_form_fields() -> usize {
+    *MAX_FORM_FIELDS.get_or_init(|| env_limit("MAX_FORM_FIELDS", 32))
+}
+
+// Field names that carry file data rather than text
+const UPLOAD_FIELDS: &[&str] = &["file", "before", "after", "source", "reference"];
+
+// One uploaded file, kept in form order so repeated `file` parts survive
+#[derive(Debug)]
+pub struct Upload {
+    pub field: String,
+    pub file_name: Option<String>,
+    pub data: Bytes,
+}
+
+// Multipart form contents: the validated uploads (the last `file` also as
+// `file`/`file_name`) plus every other requested field as text
+#[derive(Debug, Default)]
+pub struct FieldMap {
+    pub file: Option<Bytes>,
+    pub file_name: Option<String>,
+    uploads: Vec<Upload>,
+    // Text fields in form order; a repeated name reads as its last value
+    values: Vec<(String, String)>,
+}
+
+// Read the whole form once. Only `names` are kept; anything else is skipped,
+// as the hand-written handler loops do.
+pub async fn collect_fields(multipart: Multipart, names: &[&str]) -> Result<FieldMap, AppError> {
+    collect(multipart, |name| names.contains(&name), true).await
+}
+
+// Keep every part, for forms whose field names are open-ended (exif-edit
+// takes one field per tag) and checked by the handler
+pub async fn collect_all_fields(multipart: Multipart) -> Result<FieldMap, AppError> {
+    collect(multipart, |_| true, true).await
+}
+
+// Like `collect_fields`, but uploads are only size-checked, for detect-format,
+// which reports on files the other endpoints refuse
+pub async fn collect_unvalidated_fields(multipart: Multipart, names: &[&str]) -> Result<FieldMap, AppError> {
+    collect(multipart, |name| names.contains(&name), false).await
+}
+
+// Multipart reader that enforces MAX_FORM_FIELDS. Every form goes through
+// it, whether collected here or walked part by part in a handler.
+pub struct FormReader {
+    multipart: Multipart,
+    count: usize,
+}
+
+impl FormReader {
+    pub fn new(multipart: Multipart) -> Self {
+        FormReader { multipart, count: 0 }
+    }
+
+    pub async fn next_field(&mut self) -> Result<Option<Field>, AppError> {
+        let field = self.multipart.next_field().await
+            .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))?;
+        if field.is_some() {
+            self.count += 1;
+            if self.count > max_form_fields() {
+                return Err(AppError::InvalidFieldValue(format!(
+                    "Too many form fields (max {})",
+                    max_form_fields()
+                )));
+            }
+        }
+        Ok(field)
+    }
+}
+
+async fn collect(multipart: Multipart, keep: impl Fn(&str) -> bool, validate: bool) -> Result<FieldMap, AppError> {
+    let mut fields = FieldMap::default();
+    let mut form = FormReader::new(multipart);
+
+    while let Some(field) = form.next_field().await? {
+        let name = field.name().unwrap_or("").to_string();
+        if !keep(&name) {
+            continue;
+        }
+
+        if UPLOAD_FIELDS.contains(&name.as_str()) {
+            let file_name = field.file_name().map(str::to_string);
+            let data = field.bytes().await
+                .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
+            if validate {
+                validate_upload(&data)?;
+            } else if data.len() > MAX_UPLOAD_SIZE {
+                return Err(AppError::FileTooLarge);
+            }
+            if name == "file" {
+                fields.file_name = file_name.clone();
+                fields.file = Some(data.clone());
+            }
+            fields.uploads.push(Upload { field: name, file_name, data });
+        } else if let Ok(text) = field.text().await {
+            fields.values.push((name, text));
+        }
+    }
+
+    Ok(fields)
+}
+
+impl FieldMap {
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.values.iter().rev().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<String> {
+        self.get_str(name).map(str::to_string)
+    }
+
+    // Parse a field if present; a value that doesn't parse is a client error
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<Option<T>, AppError> {
+        self.get_str(name)
+            .map(|value| {
+                value.trim().parse::<T>().map_err(|_| {
+                    AppError::InvalidFieldValue(format!("Invalid {}: {}", name, value))
+                })
+            })
+            .transpose()
+    }
+
+    // For the request enums, whose FromStr already reports a field error
+    pub fn get_parsed<T: FromStr<Err = AppError>>(&self, name: &str) -> Result<Option<T>, AppError> {
+        self.get_str(name).map(str::parse).transpose()
+    }
+
+    pub fn get_u32(&self, name: &str) -> Result<Option<u32>, AppError> {
+        self.get(name)
+    }
+
+    // Finite values only; NaN and inf are rejected
+    pub fn get_f32(&self, name: &str) -> Result<Option<f32>, AppError> {
+        self.get_str(name).map(|value| parse_finite_f32(name, value, ..)).transpose()
+    }
+
+    pub fn get_bool(&self, name: &str) -> Result<bool, AppError> {
+        Ok(self.get(name)?.unwrap_or(false))
+    }
+
+    pub fn require_str(&self, name: &str) -> Result<&str, AppError> {
+        self.get_str(name).ok_or_else(|| AppError::MissingField(name.to_string()))
+    }
+
+    // Every text field, in form order
+    pub fn text_fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    // Every upload sent under `name`, in form order
+    pub fn files<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Upload> + 'a {
+        self.uploads.iter().filter(move |upload| upload.field == name)
+    }
+
+    // The last upload sent under `name`, like `file`
+    pub fn require_upload(&self, name: &str) -> Result<&Upload, AppError> {
+        self.uploads
+            .iter()
+            .rfind(|upload| upload.field == name)
+            .ok_or_else(|| AppError::MissingField(name.to_string()))
+    }
+
+    // Encoder settings from whichever EncodeOptions::FIELDS were collected
+    pub fn encode_options(&self) -> Result<EncodeOptions, AppError> {
+        let mut options = EncodeOptions::default();
+        for name in EncodeOptions::FIELDS {
+            if let Some(value) = self.get_str(name) {
+                options.set_field(name, value)?;
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{form, post, Part};
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn typed_getters_parse_present_fields_and_report_missing_ones() {
+        let multipart = form(&[
+            Part::Text("quality", " 70 "),
+            Part::Text("opacity", "0.5"),
+            Part::Text("grayscale", "true"),
+            Part::Text("width", "wide"),
+            Part::Text("ignored", "1"),
+        ]).await;
+        let fields = collect_fields(multipart, &["quality", "opacity", "grayscale", "width", "height"]).await.unwrap();
+
+        assert_eq!(fields.get_u32("quality").unwrap(), Some(70));
+        assert_eq!(fields.get_f32("opacity").unwrap(), Some(0.5));
+        assert!(fields.get_bool("grayscale").unwrap());
+        assert!(matches!(fields.get_u32("width"), Err(AppError::InvalidFieldValue(_))));
+
+        // Absent fields are None or false; only require_* makes them an error
+        assert_eq!(fields.get_u32("height").unwrap(), None);
+        assert_eq!(fields.get_str("ignored"), None);
+        assert!(!fields.get_bool("height").unwrap());
+        assert!(matches!(fields.require_str("height"), Err(AppError::MissingField(name)) if name == "height"));
+        assert!(matches!(fields.require_upload("file"), Err(AppError::MissingField(_))));
+    }
+
+    #[tokio::test]
+    async fn a_flood_of_junk_fields_is_rejected() {
+        let names: Vec<String> = (0..1000).map(|i| format!("junk{}", i)).collect();
+        let parts: Vec<Part> = names.iter().map(|name| Part::Text(name, "x")).collect();
+
+        let result = collect_fields(form(&parts).await, &["file", "quality"]).await;
+        assert!(matches!(result, Err(AppError::InvalidFieldValue(message)) if message.contains("Too many form fields")));
+
+        // Handlers that walk the form themselves are capped the same way
+        let (status, _, body) = post(crate::handlers::convert_handler, &parts).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(String::from_utf8_lossy(&body).contains("Too many form fields"));
+        let (status, _, _) = post(crate::handlers::edit_handler, &parts).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
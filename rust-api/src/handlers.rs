@@ -1,118 +1,229 @@
 use axum::{
-    http::{header, HeaderMap, StatusCode},
+    body::Body,
+    http::{header, HeaderMap, HeaderValue},
     response::{IntoResponse, Response},
     Json,
 };
 use axum_extra::extract::Multipart;
+use bytes::Bytes;
 use image::{DynamicImage, GenericImageView};
 use serde_json::json;
+use std::ops::Bound;
 
 use crate::{
+    fetch::fetch_image_url,
+    fields::{collect_all_fields, collect_fields, collect_unvalidated_fields, FormReader},
     image_utils::*,
+    metadata::*,
     types::*,
     validation::*,
 };
 
-// Font data for future text rendering implementation
-// const FONT_DATA: &[u8] = include_bytes!("../assets/DejaVuSans.ttf");
+// The image comes from the uploaded `file` field, or is fetched from `url`
+async fn resolve_image(image_data: Option<Bytes>, image_url: Option<String>) -> Result<Bytes, AppError> {
+    match (image_data, image_url) {
+        (Some(data), _) => Ok(data),
+        (None, Some(url)) => {
+            let data = fetch_image_url(&url).await?;
+            validate_upload(&data)?;
+            Ok(data)
+        }
+        (None, None) => Err(AppError::MissingField("file".to_string())),
+    }
+}
 
-pub async fn compress_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
-    let mut image_data = None;
-    let mut quality = 80;
+// Geometry operations decode only the first frame; refuse animations
+// rather than hand back a still the caller didn't ask for
+fn reject_animated(data: &[u8], operation: &str) -> Result<(), AppError> {
+    if is_animated(data) {
+        return Err(AppError::InvalidFieldValue(format!(
+            "{} works on a single frame, but the input is animated. Convert it to a still image first",
+            operation
+        )));
+    }
+    Ok(())
+}
+
+// Last path segment of a URL, ignoring any query or fragment
+fn url_file_name(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    path.rsplit('/').next().filter(|name| !name.is_empty())
+}
+
+// Sanitized base name of the input, from the upload or else the URL
+fn source_stem(file_name: Option<&str>, image_url: Option<&str>) -> Option<String> {
+    file_name
+        .or_else(|| image_url.and_then(url_file_name))
+        .and_then(sanitize_file_stem)
+}
+
+// `cat-resized.png` for an input named `cat.jpeg`, or just `resized.png`
+fn output_filename(source: Option<&str>, operation: &str, extension: &str) -> String {
+    match source {
+        Some(stem) => format!("{}-{}.{}", stem, operation, extension),
+        None => format!("{}.{}", operation, extension),
+    }
+}
+
+// Content-Type and Content-Disposition headers for a processed file
+fn file_headers(mime_type: &str, filename: &str, disposition: Disposition) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, mime_type.parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("{}; filename=\"{}\"", disposition.as_str(), filename).parse().unwrap(),
+    );
+    headers
+}
+
+// Let clients warn that transparency was lost during encoding
+fn add_flatten_headers(headers: &mut HeaderMap, encoded: &EncodedImage) {
+    if let Some([r, g, b]) = encoded.alpha_flattened {
+        headers.insert("X-Alpha-Flattened", "true".parse().unwrap());
+        headers.insert("X-Alpha-Background", format!("#{:02x}{:02x}{:02x}", r, g, b).parse().unwrap());
+    }
+}
+
+// Sanitized stem of an uploaded file name, suffixed with -2, -3, ... when an
+// earlier upload in the same request already used it
+fn unique_stem(used: &mut std::collections::HashSet<String>, file_name: &str) -> String {
+    let stem = sanitize_file_stem(file_name).unwrap_or_else(|| "image".to_string());
+    let mut unique = stem.clone();
+    let mut suffix = 1;
+    while !used.insert(unique.clone()) {
+        suffix += 1;
+        unique = format!("{}-{}", stem, suffix);
+    }
+    unique
+}
+
+// Pack named files into a deflated ZIP archive
+fn write_zip(entries: &[(String, Vec<u8>)]) -> Result<Vec<u8>, AppError> {
+    let zip_error = |e: zip::result::ZipError| AppError::ImageProcessingError(format!("Failed to write ZIP: {}", e));
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default();
+
+    for (name, data) in entries {
+        archive.start_file(name.as_str(), options).map_err(zip_error)?;
+        std::io::Write::write_all(&mut archive, data).map_err(|e| AppError::IoError(e.to_string()))?;
+    }
+
+    Ok(archive.finish().map_err(zip_error)?.into_inner())
+}
+
+// Re-encoding drops every metadata block. With `preserve_metadata`, carry
+// the input's EXIF, ICC profile and XMP over where the output format allows.
+fn with_metadata(preserve: bool, source: &[u8], output: Vec<u8>, oriented: bool) -> Result<Vec<u8>, AppError> {
+    if preserve {
+        copy_metadata(source, output, oriented)
+    } else {
+        Ok(output)
+    }
+}
 
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
+pub async fn compress_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let mut image_data = None;
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut encode_options = EncodeOptions::default();
+    let mut palette = false;
+    let mut colors: Option<u32> = None;
+    let mut dither = Dither::None;
+    let mut orient = true;
+    let mut preserve_metadata = false;
+    let mut disposition = Disposition::Attachment;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
         
         let name = field.name().unwrap_or("");
         match name {
             "file" => {
+                file_name = field.file_name().map(str::to_string);
                 let data = field.bytes().await
                     .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
                 validate_upload(&data)?;
                 image_data = Some(data);
             }
-            "quality" => {
+            "url" => image_url = field.text().await.ok(),
+            name if EncodeOptions::FIELDS.contains(&name) => {
+                let name = name.to_string();
                 if let Ok(text) = field.text().await {
-                    quality = text.parse().unwrap_or(80).clamp(1, 100);
+                    encode_options.set_field(&name, &text)?;
                 }
             }
+            "palette" => if let Ok(text) = field.text().await { palette = text.parse().unwrap_or(false); },
+            "colors" => if let Ok(text) = field.text().await { colors = Some(parse_palette_colors(&text)?); },
+            "dither" => if let Ok(text) = field.text().await { dither = text.parse()?; },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "preserve_metadata" => if let Ok(text) = field.text().await { preserve_metadata = text.parse().unwrap_or(false); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
             _ => {}
         }
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let img = load_image_from_bytes(&data)?;
-    
-    // For compression, we'll save as JPEG with the specified quality
-    let temp_path = create_temp_file("jpg");
-    
-    // Convert to RGB if it has alpha channel
-    let img = if img.color().has_alpha() {
-        DynamicImage::ImageRgb8(img.to_rgb8())
-    } else {
-        img
-    };
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    let img = load_oriented_image(&data, orient)?;
 
-    // Save with quality (this is a simplified approach)
-    save_image(&img, &temp_path, ImageFormat::Jpeg)?;
+    // Paletted PNG output is much smaller than JPEG for flat graphics
+    if palette || colors.is_some() {
+        let result_data = with_metadata(preserve_metadata, &data, encode_indexed_png(&img, colors.unwrap_or(256), dither)?, orient)?;
+
+        let filename = output_filename(source.as_deref(), "compressed", "png");
+        return Ok((file_headers("image/png", &filename, disposition), result_data));
+    }
     
-    let compressed_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
+    // For compression, we'll save as JPEG with the specified quality
+    let encoded = output_image(&img, ImageFormat::Jpeg, &encode_options)?;
+    let filename = output_filename(source.as_deref(), "compressed", "jpg");
+    let mut headers = file_headers("image/jpeg", &filename, disposition);
+    headers.insert("X-Output-Quality", u16::from(encode_options.quality).into());
+    add_flatten_headers(&mut headers, &encoded);
 
-    Ok((
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, "image/jpeg"),
-            (header::CONTENT_DISPOSITION, "attachment; filename=\"compressed.jpg\""),
-        ],
-        compressed_data,
-    ))
+    Ok((headers, with_metadata(preserve_metadata, &data, encoded.data, orient)?))
 }
 
-pub async fn resize_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
-    let mut image_data = None;
-    let mut width: Option<u32> = None;
-    let mut height: Option<u32> = None;
-    let mut percentage: Option<f32> = None;
-    let mut max_size: Option<u32> = None;
-
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
-        
-        let name = field.name().unwrap_or("");
-        match name {
-            "file" => {
-                let data = field.bytes().await
-                    .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
-                validate_upload(&data)?;
-                image_data = Some(data);
-            }
-            "width" => {
-                if let Ok(text) = field.text().await {
-                    width = text.parse().ok();
-                }
-            }
-            "height" => {
-                if let Ok(text) = field.text().await {
-                    height = text.parse().ok();
-                }
-            }
-            "percentage" => {
-                if let Ok(text) = field.text().await {
-                    percentage = text.parse().ok();
-                }
-            }
-            "max_size" => {
-                if let Ok(text) = field.text().await {
-                    max_size = text.parse().ok();
-                }
-            }
-            _ => {}
+pub async fn resize_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "url", "width", "height", "percentage", "max_size", "lock_aspect", "premultiply", "filter",
+        "downscale_filter", "upscale_filter", "pad_mode", "pad_color", "orient", "preserve_metadata", "disposition",
+    ]).await?;
+    let width = fields.get_u32("width")?;
+    let height = fields.get_u32("height")?;
+    let percentage = fields.get_f32("percentage")?;
+    let max_size = fields.get_u32("max_size")?;
+    let lock_aspect = fields.get_bool("lock_aspect")?;
+    let premultiply = fields.get("premultiply")?.unwrap_or(true);
+    // `filter` sets both directions; the specific fields override it
+    let filter = fields.get_parsed("filter")?.unwrap_or_else(default_resize_filter);
+    let downscale_filter = fields.get_parsed("downscale_filter")?.unwrap_or(filter);
+    let upscale_filter = fields.get_parsed("upscale_filter")?.unwrap_or(filter);
+    let pad_mode: Option<PadMode> = fields.get_parsed("pad_mode")?;
+    let pad_color = parse_color(fields.get_str("pad_color").unwrap_or("black"))?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let preserve_metadata = fields.get_bool("preserve_metadata")?;
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if width == Some(0) || height == Some(0) || max_size == Some(0) {
+        return Err(AppError::InvalidFieldValue("Dimensions must be at least 1 pixel".to_string()));
+    }
+    // Letterboxing fits the image inside the box, then pads it out to size
+    let pad_box = match (pad_mode, width, height) {
+        (None, _, _) => None,
+        (Some(mode), Some(w), Some(h)) if percentage.is_none() && max_size.is_none() => Some((mode, w, h)),
+        _ => return Err(AppError::InvalidFieldValue("pad_mode needs both width and height".to_string())),
+    };
+    if let Some(pct) = percentage {
+        if !pct.is_finite() || pct <= 0.0 {
+            return Err(AppError::InvalidFieldValue(format!("Invalid percentage: {}", pct)));
         }
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let img = load_image_from_bytes(&data)?;
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    reject_animated(&data, "resize")?;
     let (orig_width, orig_height) = img.dimensions();
 
     let (new_width, new_height) = if let Some(pct) = percentage {
@@ -135,513 +246,1878 @@ pub async fn resize_handler(mut multipart: Multipart) -> Result<impl IntoRespons
         } else if height.is_some() && width.is_none() {
             let ratio = orig_width as f32 / orig_height as f32;
             ((h as f32 * ratio) as u32, h)
+        } else if lock_aspect || pad_box.is_some() {
+            // Fit inside the box instead of stretching
+            let scale = (w as f32 / orig_width as f32).min(h as f32 / orig_height as f32);
+            ((orig_width as f32 * scale).round() as u32, (orig_height as f32 * scale).round() as u32)
         } else {
             (w, h)
         }
     };
 
-    let resized_img = resize_image_fast(&img, new_width, new_height)?;
-    
-    let temp_path = create_temp_file("png");
-    save_image(&resized_img, &temp_path, ImageFormat::Png)?;
-    
-    let result_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
+    let (mut resized_img, horizontal_filter, vertical_filter) =
+        resize_directional(&img, new_width, new_height, downscale_filter, upscale_filter, premultiply)?;
+    if let Some((mode, w, h)) = pad_box {
+        resized_img = pad_to_size(&resized_img, w, h, mode, pad_color);
+    }
 
-    Ok((
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, "image/png"),
-            (header::CONTENT_DISPOSITION, "attachment; filename=\"resized.png\""),
-        ],
-        result_data,
-    ))
-}
+    let result_data = output_image(&resized_img, ImageFormat::Png, &EncodeOptions::default())?.data;
+    let result_data = with_metadata(preserve_metadata, &data, result_data, orient)?;
 
-pub async fn crop_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
-    let mut image_data = None;
-    let mut x: Option<u32> = None;
-    let mut y: Option<u32> = None;
-    let mut width: Option<u32> = None;
-    let mut height: Option<u32> = None;
-    let mut ratio: Option<String> = None;
-
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
-        
-        let name = field.name().unwrap_or("");
-        match name {
-            "file" => {
-                let data = field.bytes().await
-                    .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
-                validate_upload(&data)?;
-                image_data = Some(data);
-            }
-            "x" => if let Ok(text) = field.text().await { x = text.parse().ok(); },
-            "y" => if let Ok(text) = field.text().await { y = text.parse().ok(); },
-            "width" => if let Ok(text) = field.text().await { width = text.parse().ok(); },
-            "height" => if let Ok(text) = field.text().await { height = text.parse().ok(); },
-            "ratio" => ratio = field.text().await.ok(),
-            _ => {}
-        }
-    }
+    let filename = output_filename(source.as_deref(), "resized", "png");
+    let mut headers = file_headers("image/png", &filename, disposition);
+    // One name, or "width,height" when the axes used different filters
+    let filter_header = if horizontal_filter == vertical_filter {
+        HeaderValue::from_static(horizontal_filter.as_str())
+    } else {
+        format!("{},{}", horizontal_filter.as_str(), vertical_filter.as_str()).parse().unwrap()
+    };
+    headers.insert("X-Resize-Filter", filter_header);
+    Ok((headers, result_data))
+}
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let img = load_image_from_bytes(&data)?;
+pub async fn crop_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "url", "x", "y", "width", "height", "ratio", "preview", "orient", "preserve_metadata", "disposition",
+    ]).await?;
+    let x = fields.get_u32("x")?;
+    let y = fields.get_u32("y")?;
+    let width = fields.get_u32("width")?;
+    let height = fields.get_u32("height")?;
+    let ratio = fields.get_string("ratio");
+    let preview = fields.get_bool("preview")?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let preserve_metadata = fields.get_bool("preserve_metadata")?;
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    reject_animated(&data, "crop")?;
     let (img_width, img_height) = img.dimensions();
 
-    let cropped_img = if let Some(ratio_str) = ratio {
+    let (crop_x, crop_y, crop_width, crop_height) = if let Some(ratio_str) = ratio {
         let (ratio_w, ratio_h) = parse_crop_ratio(&ratio_str)?;
         
         // Calculate crop dimensions maintaining aspect ratio
         let target_ratio = ratio_w as f32 / ratio_h as f32;
         let img_ratio = img_width as f32 / img_height as f32;
         
+        // Extreme ratios on thin images would round down to nothing
         let (crop_width, crop_height) = if img_ratio > target_ratio {
             // Image is wider than target ratio
-            let crop_width = (img_height as f32 * target_ratio) as u32;
+            let crop_width = ((img_height as f32 * target_ratio) as u32).max(1);
             (crop_width, img_height)
         } else {
             // Image is taller than target ratio
-            let crop_height = (img_width as f32 / target_ratio) as u32;
+            let crop_height = ((img_width as f32 / target_ratio) as u32).max(1);
             (img_width, crop_height)
         };
         
         let crop_x = (img_width - crop_width) / 2;
         let crop_y = (img_height - crop_height) / 2;
         
-        img.crop_imm(crop_x, crop_y, crop_width, crop_height)
+        (crop_x, crop_y, crop_width, crop_height)
     } else {
         let crop_x = x.unwrap_or(0);
         let crop_y = y.unwrap_or(0);
+        if crop_x >= img_width || crop_y >= img_height {
+            return Err(AppError::InvalidFieldValue("Crop area exceeds image bounds".to_string()));
+        }
         let crop_width = width.unwrap_or(img_width - crop_x);
         let crop_height = height.unwrap_or(img_height - crop_y);
         
         // Validate crop bounds
-        if crop_x + crop_width > img_width || crop_y + crop_height > img_height {
+        if crop_width == 0 || crop_height == 0 {
+            return Err(AppError::InvalidFieldValue("Crop dimensions must be at least 1 pixel".to_string()));
+        }
+        if crop_width > img_width - crop_x || crop_height > img_height - crop_y {
             return Err(AppError::InvalidFieldValue("Crop area exceeds image bounds".to_string()));
         }
         
-        img.crop_imm(crop_x, crop_y, crop_width, crop_height)
+        (crop_x, crop_y, crop_width, crop_height)
     };
 
-    let temp_path = create_temp_file("png");
-    save_image(&cropped_img, &temp_path, ImageFormat::Png)?;
-    
-    let result_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
-
-    Ok((
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, "image/png"),
-            (header::CONTENT_DISPOSITION, "attachment; filename=\"cropped.png\""),
-        ],
-        result_data,
-    ))
-}
-
-pub async fn rotate_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
-    let mut image_data = None;
-    let mut degrees: Option<f32> = None;
-    let mut auto_rotate = false;
-
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
-        
-        let name = field.name().unwrap_or("");
-        match name {
-            "file" => {
-                let data = field.bytes().await
-                    .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
-                validate_upload(&data)?;
-                image_data = Some(data);
-            }
-            "degrees" => if let Ok(text) = field.text().await { degrees = text.parse().ok(); },
-            "auto" => if let Ok(text) = field.text().await { auto_rotate = text.parse().unwrap_or(false); },
-            _ => {}
-        }
+    // Let clients draw the crop rectangle before committing to it
+    if preview {
+        return Ok(Json(json!({
+            "x": crop_x,
+            "y": crop_y,
+            "width": crop_width,
+            "height": crop_height,
+        })).into_response());
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let img = load_image_from_bytes(&data)?;
-
-    let rotated_img = if auto_rotate {
-        // Try to auto-rotate based on EXIF orientation
-        // For now, just return the original image
+    // Cropping to the whole image is a no-op; skip the copy
+    let cropped_img = if (crop_x, crop_y, crop_width, crop_height) == (0, 0, img_width, img_height) {
         img
-    } else if let Some(deg) = degrees {
-        // Rotate by specified degrees
-        let radians = deg.to_radians();
-        let (width, height) = img.dimensions();
-        let center_x = width as f32 / 2.0;
-        let center_y = height as f32 / 2.0;
-        
-        // For simplicity, use basic rotation for common angles
-        match deg as i32 {
-            90 | -270 => img.rotate90(),
-            180 | -180 => img.rotate180(),
-            270 | -90 => img.rotate270(),
-            _ => {
-                // For arbitrary angles, return the original for now
-                // In a full implementation, you'd use geometric_transformations::rotate
-                img
-            }
-        }
     } else {
-        return Err(AppError::MissingField("degrees or auto".to_string()));
+        img.crop_imm(crop_x, crop_y, crop_width, crop_height)
     };
+    let result_data = encode_png_like_source(&cropped_img, &data, &EncodeOptions::default())?;
+    let result_data = with_metadata(preserve_metadata, &data, result_data, orient)?;
 
-    let temp_path = create_temp_file("png");
-    save_image(&rotated_img, &temp_path, ImageFormat::Png)?;
-    
-    let result_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
-
-    Ok((
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, "image/png"),
-            (header::CONTENT_DISPOSITION, "attachment; filename=\"rotated.png\""),
-        ],
-        result_data,
-    ))
+    let filename = output_filename(source.as_deref(), "cropped", "png");
+    let mut headers = file_headers("image/png", &filename, disposition);
+    headers.insert(
+        "X-Crop-Applied",
+        format!("{},{},{},{}", crop_x, crop_y, crop_width, crop_height).parse().unwrap(),
+    );
+    Ok((headers, result_data).into_response())
 }
 
-pub async fn convert_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+pub async fn dominant_crop_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
     let mut image_data = None;
-    let mut format: Option<String> = None;
-
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut ratio = "1:1".to_string();
+    let mut scale = 1.0f32;
+    let mut orient = true;
+    let mut disposition = Disposition::Attachment;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
         
         let name = field.name().unwrap_or("");
         match name {
             "file" => {
+                file_name = field.file_name().map(str::to_string);
                 let data = field.bytes().await
                     .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
                 validate_upload(&data)?;
                 image_data = Some(data);
             }
-            "format" => format = field.text().await.ok(),
+            "url" => image_url = field.text().await.ok(),
+            "ratio" => ratio = field.text().await.unwrap_or("1:1".to_string()),
+            "scale" => if let Ok(text) = field.text().await { scale = parse_finite_f32("scale", &text, ..)?; },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
             _ => {}
         }
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let format_str = format.ok_or(AppError::MissingField("format".to_string()))?;
-    
-    let target_format: ImageFormat = format_str.parse()?;
-    let img = load_image_from_bytes(&data)?;
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let (img_width, img_height) = img.dimensions();
 
-    let temp_path = create_temp_file(target_format.extension());
-    save_image(&img, &temp_path, target_format)?;
-    
-    let result_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
+    let (ratio_w, ratio_h) = parse_crop_ratio(&ratio)?;
+    if !(scale > 0.0 && scale <= 1.0) {
+        return Err(AppError::InvalidFieldValue("Scale must be between 0 and 1".to_string()));
+    }
 
-    let mut headers = HeaderMap::new();
-    headers.insert(header::CONTENT_TYPE, target_format.mime_type().parse().unwrap());
+    // Largest window of the requested ratio that fits, shrunk by `scale`
+    let target_ratio = ratio_w as f32 / ratio_h as f32;
+    let (max_width, max_height) = if img_width as f32 / img_height as f32 > target_ratio {
+        (img_height as f32 * target_ratio, img_height as f32)
+    } else {
+        (img_width as f32, img_width as f32 / target_ratio)
+    };
+    let crop_width = ((max_width * scale) as u32).clamp(1, img_width);
+    let crop_height = ((max_height * scale) as u32).clamp(1, img_height);
+
+    let (crop_x, crop_y) = find_most_colorful_region(&img, crop_width, crop_height);
+    let cropped_img = img.crop_imm(crop_x, crop_y, crop_width, crop_height);
+    let result_data = encode_png_like_source(&cropped_img, &data, &EncodeOptions::default())?;
+
+    let filename = output_filename(source.as_deref(), "dominant-crop", "png");
+    let mut headers = file_headers("image/png", &filename, disposition);
     headers.insert(
-        header::CONTENT_DISPOSITION,
-        format!("attachment; filename=\"converted.{}\"", target_format.extension()).parse().unwrap(),
+        "X-Crop-Region",
+        format!("{},{},{},{}", crop_x, crop_y, crop_width, crop_height).parse().unwrap(),
     );
 
     Ok((headers, result_data).into_response())
 }
 
-pub async fn info_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
-    let mut image_data = None;
-    let mut filename = "unknown".to_string();
+pub async fn face_crop_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "padding", "orient", "disposition"]).await?;
+    let padding = fields.get_f32("padding")?.unwrap_or(0.25);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
 
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
-        
-        let name = field.name().unwrap_or("");
-        if name == "file" {
-            if let Some(file_name) = field.file_name() {
-                filename = file_name.to_string();
-            }
-            let data = field.bytes().await
-                .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
-            validate_upload(&data)?;
-            image_data = Some(data);
-        }
+    if !(0.0..=5.0).contains(&padding) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid padding: {} (expected 0 to 5)", padding)));
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let img = load_image_from_bytes(&data)?;
-    
-    let info = get_image_info(&img, &filename, data.len());
-    
-    Ok(Json(info))
-}
-
-pub async fn metadata_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
-    let mut image_data = None;
-    let mut strip = false;
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    reject_animated(&data, "face-crop")?;
+    let img = load_oriented_image(&data, orient)?;
+    let (img_width, img_height) = img.dimensions();
 
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
-        
-        let name = field.name().unwrap_or("");
-        match name {
-            "file" => {
-                let data = field.bytes().await
-                    .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
-                validate_upload(&data)?;
-                image_data = Some(data);
-            }
-            "strip" => if let Ok(text) = field.text().await { strip = text.parse().unwrap_or(false); },
-            _ => {}
+    // A build without the face detector crops as if no face was found, and
+    // says so in X-Face-Detection
+    let (faces, detector_available) = match detect_faces(&img) {
+        Ok(faces) => (faces, true),
+        Err(AppError::NotImplemented) => (Vec::new(), false),
+        Err(e) => return Err(e),
+    };
+    // No faces: fall back to the largest centered square
+    let (region, fallback) = match face_crop_region(&faces, img_width, img_height, padding) {
+        Some(region) => (region, false),
+        None => {
+            let side = img_width.min(img_height);
+            (((img_width - side) / 2, (img_height - side) / 2, side, side), true)
         }
-    }
+    };
+    let (crop_x, crop_y, crop_width, crop_height) = region;
+    let cropped_img = img.crop_imm(crop_x, crop_y, crop_width, crop_height);
+    let result_data = encode_png_like_source(&cropped_img, &data, &EncodeOptions::default())?;
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    
-    if strip {
-        // Strip metadata and return image
-        let img = load_image_from_bytes(&data)?;
-        let temp_path = create_temp_file("png");
-        save_image(&img, &temp_path, ImageFormat::Png)?;
-        
-        let result_data = read_file_bytes(&temp_path)?;
-        delete_temp_file(&temp_path);
-
-        Ok((
-            StatusCode::OK,
-            [
-                (header::CONTENT_TYPE, "image/png"),
-                (header::CONTENT_DISPOSITION, "attachment; filename=\"stripped.png\""),
-            ],
-            result_data,
-        ).into_response())
-    } else {
-        // Return metadata as JSON
-        let exif_data = json!({
-            "message": "EXIF metadata extraction not fully implemented yet",
-            "data": {}
-        });
-        Ok(Json(exif_data).into_response())
+    let filename = output_filename(source.as_deref(), "face-crop", "png");
+    let mut headers = file_headers("image/png", &filename, disposition);
+    headers.insert(
+        "X-Crop-Region",
+        format!("{},{},{},{}", crop_x, crop_y, crop_width, crop_height).parse().unwrap(),
+    );
+    headers.insert("X-Face-Count", faces.len().into());
+    if fallback {
+        headers.insert("X-Face-Crop-Fallback", HeaderValue::from_static("center"));
+    }
+    if !detector_available {
+        headers.insert("X-Face-Detection", HeaderValue::from_static("unavailable"));
     }
+    Ok((headers, result_data))
 }
 
-pub async fn watermark_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+pub async fn rotate_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
     let mut image_data = None;
-    let mut text: Option<String> = None;
-    let mut position = "bottom-right".to_string();
-    let mut opacity = 0.3f32;
-    let mut size: Option<u32> = None;
-    let mut color = "white".to_string();
-    let mut tile = false;
-    let mut angle = 0.0f32;
-
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut degrees: Option<f32> = None;
+    let mut auto_rotate = false;
+    let mut auto_crop = false;
+    let mut interpolation = Interpolation::Bilinear;
+    let mut orient = true;
+    let mut preserve_metadata = false;
+    let mut disposition = Disposition::Attachment;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
         
         let name = field.name().unwrap_or("");
         match name {
             "file" => {
+                file_name = field.file_name().map(str::to_string);
                 let data = field.bytes().await
                     .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
                 validate_upload(&data)?;
                 image_data = Some(data);
             }
-            "text" => text = field.text().await.ok(),
-            "position" => position = field.text().await.unwrap_or("bottom-right".to_string()),
-            "opacity" => if let Ok(text_val) = field.text().await { opacity = text_val.parse().unwrap_or(0.3); },
-            "size" => if let Ok(text_val) = field.text().await { size = text_val.parse().ok(); },
-            "color" => color = field.text().await.unwrap_or("white".to_string()),
-            "tile" => if let Ok(text_val) = field.text().await { tile = text_val.parse().unwrap_or(false); },
-            "angle" => if let Ok(text_val) = field.text().await { angle = text_val.parse().unwrap_or(0.0); },
+            "url" => image_url = field.text().await.ok(),
+            "degrees" => if let Ok(text) = field.text().await { degrees = Some(parse_finite_f32("degrees", &text, ..)?); },
+            "auto" => if let Ok(text) = field.text().await { auto_rotate = text.parse().unwrap_or(false); },
+            "auto_crop" => if let Ok(text) = field.text().await { auto_crop = text.parse().unwrap_or(false); },
+            "interpolation" => if let Ok(text) = field.text().await { interpolation = text.parse()?; },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "preserve_metadata" => if let Ok(text) = field.text().await { preserve_metadata = text.parse().unwrap_or(false); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
             _ => {}
         }
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let watermark_text = text.ok_or(AppError::MissingField("text".to_string()))?;
-    
-    let mut img = load_image_from_bytes(&data)?;
-    
-    // Add watermark text
-    // This is a simplified implementation - in production you'd use proper text rendering
-    // For now, we'll just return the original image
-    // TODO: Implement proper text rendering with ab_glyph and the embedded font
-    
-    let temp_path = create_temp_file("png");
-    save_image(&img, &temp_path, ImageFormat::Png)?;
-    
-    let result_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    // `auto` only undoes the EXIF orientation, even with orient=false
+    let img = load_oriented_image(&data, orient || auto_rotate)?;
+    reject_animated(&data, "rotate")?;
+
+    let rotated_img = if auto_rotate {
+        img
+    } else if let Some(deg) = degrees {
+        // Right angles are exact and lossless; anything else is resampled
+        let normalized = deg.rem_euclid(360.0);
+        if normalized == 0.0 {
+            img
+        } else if normalized == 90.0 {
+            img.rotate90()
+        } else if normalized == 180.0 {
+            img.rotate180()
+        } else if normalized == 270.0 {
+            img.rotate270()
+        } else if auto_crop {
+            let rotated = rotate_arbitrary(&img, normalized, interpolation);
+            let (rect_width, rect_height) = largest_inscribed_rect(img.width(), img.height(), normalized);
+            // Pixels right on the rotated edge are blended with the empty
+            // corners, so stay a couple of pixels inside
+            let crop_width = ((rect_width.floor() as u32).saturating_sub(4)).clamp(1, rotated.width());
+            let crop_height = ((rect_height.floor() as u32).saturating_sub(4)).clamp(1, rotated.height());
+            rotated.crop_imm(
+                (rotated.width() - crop_width) / 2,
+                (rotated.height() - crop_height) / 2,
+                crop_width,
+                crop_height,
+            )
+        } else {
+            rotate_arbitrary(&img, normalized, interpolation)
+        }
+    } else {
+        return Err(AppError::MissingField("degrees or auto".to_string()));
+    };
+
+    let result_data = encode_png_like_source(&rotated_img, &data, &EncodeOptions::default())?;
+    let result_data = with_metadata(preserve_metadata, &data, result_data, orient || auto_rotate)?;
 
-    Ok((
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, "image/png"),
-            (header::CONTENT_DISPOSITION, "attachment; filename=\"watermarked.png\""),
-        ],
-        result_data,
-    ))
+    let filename = output_filename(source.as_deref(), "rotated", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
 }
 
-pub async fn blur_face_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+pub async fn convert_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
     let mut image_data = None;
-    let mut strength = 25u32;
-    let mut region: Option<String> = None;
-
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut format: Option<String> = None;
+    let mut encode_options = EncodeOptions::default();
+    let mut palette = false;
+    let mut colors: Option<u32> = None;
+    let mut color_mode: Option<ColorMode> = None;
+    let mut threshold: Option<u8> = None;
+    let mut dither: Option<Dither> = None;
+    let mut orient = true;
+    let mut preserve_metadata = false;
+    let mut disposition = Disposition::Attachment;
+    let mut encode_options_set = false;
+    let mut force_reencode = false;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
         
         let name = field.name().unwrap_or("");
         match name {
             "file" => {
+                file_name = field.file_name().map(str::to_string);
                 let data = field.bytes().await
                     .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
                 validate_upload(&data)?;
                 image_data = Some(data);
             }
-            "strength" => if let Ok(text) = field.text().await { strength = text.parse().unwrap_or(25); },
-            "region" => region = field.text().await.ok(),
+            "url" => image_url = field.text().await.ok(),
+            "format" => format = field.text().await.ok(),
+            name if EncodeOptions::FIELDS.contains(&name) => {
+                let name = name.to_string();
+                if let Ok(text) = field.text().await {
+                    encode_options.set_field(&name, &text)?;
+                    encode_options_set = true;
+                }
+            }
+            "force_reencode" => if let Ok(text) = field.text().await { force_reencode = text.parse().unwrap_or(false); },
+            "palette" => if let Ok(text) = field.text().await { palette = text.parse().unwrap_or(false); },
+            "colors" => if let Ok(text) = field.text().await { colors = Some(parse_palette_colors(&text)?); },
+            "color_mode" => if let Ok(text) = field.text().await { color_mode = Some(text.parse()?); },
+            "threshold" => if let Ok(text) = field.text().await { threshold = text.parse().ok(); },
+            "dither" => if let Ok(text) = field.text().await { dither = Some(text.parse()?); },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "preserve_metadata" => if let Ok(text) = field.text().await { preserve_metadata = text.parse().unwrap_or(false); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
             _ => {}
         }
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let mut img = load_image_from_bytes(&data)?;
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    let format_str = format.ok_or(AppError::MissingField("format".to_string()))?;
+    
+    let target_format: ImageFormat = format_str.parse()?;
+    if target_format == ImageFormat::Raw {
+        return Err(AppError::InvalidFieldValue("RAW is an input-only format".to_string()));
+    }
+    let filename = output_filename(source.as_deref(), "converted", target_format.extension());
+
+    // Already in the target format, nothing to change and the metadata is
+    // wanted: re-encoding would only cost CPU and, for lossy formats, quality.
+    // Otherwise the re-encode is what strips the metadata.
+    let transform_requested = encode_options_set
+        || palette
+        || colors.is_some()
+        || color_mode.is_some()
+        || (orient && exif_orientation(&data).is_some_and(|o| o != 1));
+    if preserve_metadata && !force_reencode && !transform_requested && ImageFormat::from_magic_bytes(&data) == Some(target_format) {
+        return Ok((file_headers(target_format.mime_type(), &filename, disposition), data.to_vec()));
+    }
 
-    // Apply blur effect
-    if let Some(region_str) = region {
-        let (x, y, w, h) = parse_region(&region_str)?;
-        // Apply blur to specific region
-        let blur_sigma = strength as f32 / 10.0;
-        let blurred = img.blur(blur_sigma);
-        
-        // For simplicity, return fully blurred image
-        // In production, you'd apply blur only to the specified region
-        img = blurred;
-    } else {
-        // Apply blur to entire image (simplified face detection)
-        let blur_sigma = strength as f32 / 10.0;
-        img = img.blur(blur_sigma);
+    let mut img = load_oriented_image(&data, orient)?;
+    if let Some(mode) = color_mode {
+        img = apply_color_mode(&img, mode, threshold, dither);
     }
 
-    let temp_path = create_temp_file("png");
-    save_image(&img, &temp_path, ImageFormat::Png)?;
-    
-    let result_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
+    // Palette quantization only applies to PNG output; truecolor stays the
+    // default. Bilevel images pack into a 1-bit palette.
+    let bilevel = color_mode == Some(ColorMode::Bilevel);
+    let encoded = if matches!(target_format, ImageFormat::Png) && (palette || colors.is_some() || bilevel) {
+        // Bilevel pixels are already dithered to black/white
+        let (colors, dither) = if bilevel { (2, Dither::None) } else { (colors.unwrap_or(256), dither.unwrap_or(Dither::None)) };
+        EncodedImage { data: encode_indexed_png(&img, colors, dither)?, alpha_flattened: None }
+    } else if matches!(target_format, ImageFormat::Png) && color_mode.is_none() {
+        EncodedImage { data: encode_png_like_source(&img, &data, &encode_options)?, alpha_flattened: None }
+    } else {
+        output_image(&img, target_format, &encode_options)?
+    };
 
-    Ok((
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, "image/png"),
-            (header::CONTENT_DISPOSITION, "attachment; filename=\"blurred.png\""),
-        ],
-        result_data,
-    ))
+    let mut headers = file_headers(target_format.mime_type(), &filename, disposition);
+    let lossy = match target_format {
+        ImageFormat::Jpeg => true,
+        ImageFormat::Webp => !encode_options.lossless,
+        _ => false,
+    };
+    if lossy {
+        headers.insert("X-Output-Quality", u16::from(encode_options.quality).into());
+    }
+    add_flatten_headers(&mut headers, &encoded);
+
+    Ok((headers, with_metadata(preserve_metadata, &data, encoded.data, orient)?))
 }
 
-pub async fn upscale_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+pub async fn fit_to_bytes_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
     let mut image_data = None;
-    let mut scale = 2u32;
-    let mut sharpen = true;
-
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut encode_options = EncodeOptions::default();
+    let mut format = "jpg".to_string();
+    let mut max_bytes: Option<usize> = None;
+    let mut min_quality: Option<u8> = None;
+    let mut orient = true;
+    let mut disposition = Disposition::Attachment;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
         
         let name = field.name().unwrap_or("");
         match name {
             "file" => {
+                file_name = field.file_name().map(str::to_string);
                 let data = field.bytes().await
                     .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
                 validate_upload(&data)?;
                 image_data = Some(data);
             }
-            "scale" => if let Ok(text) = field.text().await { scale = text.parse().unwrap_or(2).clamp(1, 8); },
-            "sharpen" => if let Ok(text) = field.text().await { sharpen = text.parse().unwrap_or(true); },
+            "url" => image_url = field.text().await.ok(),
+            name if EncodeOptions::FIELDS.contains(&name) => {
+                let name = name.to_string();
+                if let Ok(text) = field.text().await {
+                    encode_options.set_field(&name, &text)?;
+                }
+            }
+            "format" => format = field.text().await.unwrap_or("jpg".to_string()),
+            "max_bytes" => if let Ok(text) = field.text().await { max_bytes = Some(parse_int("max_bytes", &text)?); },
+            "min_quality" => if let Ok(text) = field.text().await { min_quality = Some(parse_int("min_quality", &text)?); },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
             _ => {}
         }
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let img = load_image_from_bytes(&data)?;
-    let (width, height) = img.dimensions();
-    
-    // Upscale using fast resize
-    let new_width = width * scale;
-    let new_height = height * scale;
-    let mut upscaled = resize_image_fast(&img, new_width, new_height)?;
-    
-    // Apply sharpening if requested
-    if sharpen {
-        upscaled = upscaled.unsharpen(1.0, 1);
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    let max_bytes = max_bytes.ok_or(AppError::MissingField("max_bytes".to_string()))?;
+    if max_bytes == 0 {
+        return Err(AppError::InvalidFieldValue("max_bytes must be at least 1".to_string()));
     }
+    let target_format: ImageFormat = format.parse()?;
+    let img = load_oriented_image(&data, orient)?;
+
+    // Without min_quality only the resolution changes
+    let min_quality = min_quality.unwrap_or(encode_options.quality).min(encode_options.quality);
+    let (encoded, (width, height), quality) =
+        fit_to_bytes(&img, target_format, &encode_options, max_bytes, min_quality)?;
+
+    let filename = output_filename(source.as_deref(), "fitted", target_format.extension());
+    let mut headers = file_headers(target_format.mime_type(), &filename, disposition);
+    headers.insert("X-Output-Width", width.into());
+    headers.insert("X-Output-Height", height.into());
+    headers.insert("X-Output-Quality", u16::from(quality).into());
+    add_flatten_headers(&mut headers, &encoded);
+
+    Ok((headers, encoded.data))
+}
+
+pub async fn annotate_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "shapes", "orient", "disposition"]).await?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let shapes: Vec<Shape> = serde_json::from_str(fields.require_str("shapes")?)
+        .map_err(|e| AppError::InvalidFieldValue(format!("Invalid shapes: {}", e)))?;
+
+    let img = load_oriented_image(&data, orient)?;
+    let annotated = draw_shapes(&img, &shapes)?;
+
+    let result_data = output_image(&annotated, ImageFormat::Png, &EncodeOptions::default())?.data;
+
+    let filename = output_filename(source.as_deref(), "annotated", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+pub async fn denoise_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "method", "radius", "strength", "orient", "disposition"]).await?;
+    let method = fields.get_parsed("method")?.unwrap_or(DenoiseMethod::Median);
+    let radius = fields.get_u32("radius")?.unwrap_or(2);
+    let strength = fields.get_f32("strength")?.unwrap_or(30.0);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if !(1..=10).contains(&radius) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid radius: {} (expected 1-10)", radius)));
+    }
+    if !strength.is_finite() || strength <= 0.0 {
+        return Err(AppError::InvalidFieldValue(format!("Invalid strength: {}", strength)));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let denoised = denoise(&img, method, radius, strength);
+
+    let result_data = output_image(&denoised, ImageFormat::Png, &EncodeOptions::default())?.data;
+
+    let filename = output_filename(source.as_deref(), "denoised", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+pub async fn threshold_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "threshold", "auto", "invert", "orient", "disposition"]).await?;
+    let threshold: Option<u8> = fields.get("threshold")?;
+    let invert = fields.get_bool("invert")?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let otsu = match fields.get_str("auto").map(|auto| auto.trim().to_lowercase()) {
+        None => false,
+        Some(auto) if auto == "otsu" => true,
+        Some(auto) => return Err(AppError::InvalidFieldValue(format!("Invalid auto '{}'. Expected 'otsu'", auto))),
+    };
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+
+    let level = if otsu { otsu_threshold(&img) } else { threshold.unwrap_or(128) };
+    let mut bilevel = apply_color_mode(&img, ColorMode::Bilevel, Some(level), None);
+    if invert {
+        bilevel.invert();
+    }
+    let result_data = encode_indexed_png(&bilevel, 2, Dither::None)?;
+
+    let filename = output_filename(source.as_deref(), "threshold", "png");
+    let mut headers = file_headers("image/png", &filename, disposition);
+    headers.insert("X-Threshold", u16::from(level).into());
+    Ok((headers, result_data))
+}
+
+// Slice the image into a grid of `rows` x `cols` tiles, or fixed-size
+// `tile_width` x `tile_height` tiles, returned as a ZIP
+pub async fn split_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "url", "rows", "cols", "tile_width", "tile_height", "orient",
+    ]).await?;
+    let rows = fields.get_u32("rows")?;
+    let cols = fields.get_u32("cols")?;
+    let tile_width = fields.get_u32("tile_width")?;
+    let tile_height = fields.get_u32("tile_height")?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+
+    if [rows, cols, tile_width, tile_height].contains(&Some(0)) {
+        return Err(AppError::InvalidFieldValue("Grid and tile sizes must be at least 1".to_string()));
+    }
+    if rows.is_none() && cols.is_none() && tile_width.is_none() && tile_height.is_none() {
+        return Err(AppError::MissingField("rows/cols or tile_width/tile_height".to_string()));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let (width, height) = img.dimensions();
+
+    let x_bounds = split_bounds(width, cols, tile_width)?;
+    let y_bounds = split_bounds(height, rows, tile_height)?;
+    if x_bounds.len() * y_bounds.len() > 1024 {
+        return Err(AppError::InvalidFieldValue(format!(
+            "{}x{} tiles exceeds the limit of 1024",
+            y_bounds.len(), x_bounds.len()
+        )));
+    }
+
+    let stem = source.as_deref().unwrap_or("tile");
+    let mut entries = Vec::new();
+    for (row, &(y, tile_h)) in y_bounds.iter().enumerate() {
+        for (col, &(x, tile_w)) in x_bounds.iter().enumerate() {
+            let tile = img.crop_imm(x, y, tile_w, tile_h);
+            let encoded = encode_png_like_source(&tile, &data, &EncodeOptions::default())?;
+            entries.push((format!("{}_r{}_c{}.png", stem, row + 1, col + 1), encoded));
+        }
+    }
+
+    let filename = output_filename(source.as_deref(), "tiles", "zip");
+    Ok((file_headers("application/zip", &filename, Disposition::Attachment), write_zip(&entries)?))
+}
+
+// (offset, length) of each tile along one axis. `count` splits evenly with
+// the remainder going to the last tile; `size` gives fixed tiles with a
+// smaller one at the edge. Neither means a single tile.
+fn split_bounds(length: u32, count: Option<u32>, size: Option<u32>) -> Result<Vec<(u32, u32)>, AppError> {
+    let size = match (count, size) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::InvalidFieldValue("Use either a tile count or a tile size per axis, not both".to_string()));
+        }
+        (Some(count), None) => {
+            if count > length {
+                return Err(AppError::InvalidFieldValue(format!("Cannot split {} pixels into {} tiles", length, count)));
+            }
+            let step = length / count;
+            return Ok((0..count)
+                .map(|i| {
+                    let offset = i * step;
+                    (offset, if i + 1 == count { length - offset } else { step })
+                })
+                .collect());
+        }
+        (None, Some(size)) => size.min(length),
+        (None, None) => length,
+    };
+
+    Ok((0..length).step_by(size as usize).map(|offset| (offset, size.min(length - offset))).collect())
+}
+
+// Slice a sprite sheet into `rows` x `cols` cells (or cells of
+// `frame_width` x `frame_height`) and play them back as an animated GIF or
+// WebP, in reading order. Leftover pixels at the right/bottom edge are
+// ignored, and `frames` drops unused cells at the end of the sheet.
+pub async fn spritesheet_to_gif_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "url", "rows", "cols", "frame_width", "frame_height", "frames", "delay_ms", "format", "orient",
+        "disposition",
+    ]).await?;
+    let rows = fields.get_u32("rows")?;
+    let cols = fields.get_u32("cols")?;
+    let frame_width = fields.get_u32("frame_width")?;
+    let frame_height = fields.get_u32("frame_height")?;
+    let frame_limit = fields.get_u32("frames")?;
+    let delay_ms = fields.get_u32("delay_ms")?.unwrap_or(100);
+    let format: ImageFormat = fields.get_parsed("format")?.unwrap_or(ImageFormat::Gif);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if [rows, cols, frame_width, frame_height, frame_limit].contains(&Some(0)) {
+        return Err(AppError::InvalidFieldValue("Grid, frame size and frame count must be at least 1".to_string()));
+    }
+    if !(1..=60_000).contains(&delay_ms) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid delay_ms: {} (expected 1-60000)", delay_ms)));
+    }
+    if !matches!(format, ImageFormat::Gif | ImageFormat::Webp) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid format '{}'. Expected gif or webp", format.extension())));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let (width, height) = img.dimensions();
+
+    // Per axis: a cell count, a cell size, or neither for a single cell
+    let cells = |length: u32, count: Option<u32>, size: Option<u32>, axis: &str| match (count, size) {
+        (Some(_), Some(_)) => Err(AppError::InvalidFieldValue(format!(
+            "Use either a {} count or a frame size, not both", axis
+        ))),
+        (Some(count), None) if count <= length => Ok((count, length / count)),
+        (None, Some(size)) if size <= length => Ok((length / size, size)),
+        (None, None) => Ok((1, length)),
+        _ => Err(AppError::InvalidFieldValue(format!("The {} frame layout doesn't fit the {}px sheet", axis, length))),
+    };
+    let (cols, cell_width) = cells(width, cols, frame_width, "column")?;
+    let (rows, cell_height) = cells(height, rows, frame_height, "row")?;
+
+    let total = rows as u64 * cols as u64;
+    let count = frame_limit.map_or(total, |limit| (limit as u64).min(total));
+    if count > 1024 {
+        return Err(AppError::InvalidFieldValue(format!("{} frames exceeds the limit of 1024", count)));
+    }
+
+    let sheet = img.to_rgba8();
+    let frames: Vec<(image::RgbaImage, u32)> = (0..count as u32)
+        .map(|i| {
+            let (x, y) = ((i % cols) * cell_width, (i / cols) * cell_height);
+            (image::imageops::crop_imm(&sheet, x, y, cell_width, cell_height).to_image(), delay_ms)
+        })
+        .collect();
+    let animation = encode_animation(&frames, format)?;
+
+    let filename = output_filename(source.as_deref(), "animated", format.extension());
+    let mut headers = file_headers(format.mime_type(), &filename, disposition);
+    headers.insert("X-Frame-Count", count.into());
+    headers.insert("X-Frame-Size", format!("{}x{}", cell_width, cell_height).parse().unwrap());
+    Ok((headers, animation))
+}
+
+pub async fn optimize_gif_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "frames", "colors", "disposition"]).await?;
+    let target = fields.get_u32("frames")?;
+    let colors = fields.get_u32("colors")?;
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if target.is_none() && colors.is_none() {
+        return Err(AppError::MissingField("frames or colors".to_string()));
+    }
+    if target == Some(0) {
+        return Err(AppError::InvalidFieldValue("frames must be at least 1".to_string()));
+    }
+    if colors.is_some_and(|colors| !(2..=256).contains(&colors)) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid colors: {} (expected 2-256)", colors.unwrap())));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    if ImageFormat::from_magic_bytes(&data) != Some(ImageFormat::Gif) {
+        return Err(AppError::InvalidFieldValue("optimize-gif expects a GIF input".to_string()));
+    }
+
+    let frames = decode_gif_frames(&data)?;
+    let original_count = frames.len();
+    let mut frames = sample_frames(frames, target.map_or(0, |target| target as usize));
+    if let Some(colors) = colors {
+        for (frame, _) in &mut frames {
+            reduce_frame_colors(frame, colors);
+        }
+    }
+    let animation = encode_animation(&frames, ImageFormat::Gif)?;
+
+    let filename = output_filename(source.as_deref(), "optimized", "gif");
+    let mut headers = file_headers(ImageFormat::Gif.mime_type(), &filename, disposition);
+    headers.insert("X-Original-Frame-Count", original_count.into());
+    headers.insert("X-Frame-Count", frames.len().into());
+    Ok((headers, animation))
+}
+
+pub async fn avatar_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "url", "size", "ring", "ring_color", "format", "orient", "disposition",
+    ]).await?;
+    let size = fields.get_u32("size")?.unwrap_or(256);
+    let ring = fields.get_u32("ring")?.unwrap_or(0);
+    let ring_color = parse_color(fields.get_str("ring_color").unwrap_or("white"))?;
+    let format = fields.get_parsed("format")?.unwrap_or(ImageFormat::Png);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if !(1..=4096).contains(&size) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid size: {} (expected 1-4096)", size)));
+    }
+    if !matches!(format, ImageFormat::Png | ImageFormat::Webp) {
+        return Err(AppError::InvalidFieldValue("Avatars need alpha: format must be png or webp".to_string()));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let avatar = circular_avatar(&img, size, ring.min(size / 2), ring_color)?;
+
+    let encoded = output_image(&avatar, format, &EncodeOptions::default())?;
+    let filename = output_filename(source.as_deref(), "avatar", format.extension());
+    Ok((file_headers(format.mime_type(), &filename, disposition), encoded.data))
+}
+
+// Multi-size favicon: `sizes` (comma-separated, 1-256, default 16,32,48)
+// each become an entry in a single .ico
+pub async fn favicon_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "sizes", "orient", "disposition"]).await?;
+    let sizes_text = fields.get_str("sizes").unwrap_or("16,32,48");
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let mut sizes = Vec::new();
+    for part in sizes_text.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let size = part
+            .parse::<u32>()
+            .ok()
+            .filter(|size| (1..=256).contains(size))
+            .ok_or_else(|| AppError::InvalidFieldValue(format!("Invalid icon size: {} (expected 1-256)", part)))?;
+        if !sizes.contains(&size) {
+            sizes.push(size);
+        }
+    }
+    if sizes.is_empty() || sizes.len() > 16 {
+        return Err(AppError::InvalidFieldValue("sizes must list 1 to 16 icon sizes".to_string()));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let ico = encode_ico(&img, &sizes)?;
+
+    let filename = output_filename(source.as_deref(), "favicon", ImageFormat::Ico.extension());
+    Ok((file_headers(ImageFormat::Ico.mime_type(), &filename, disposition), ico))
+}
+
+// The camera's EXIF thumbnail, served as-is without decoding the photo.
+// With `fallback`, images without one get a generated `size`-pixel JPEG
+// instead. X-Thumbnail-Source says which.
+pub async fn embedded_thumb_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "fallback", "size", "orient", "disposition"]).await?;
+    let fallback = fields.get_bool("fallback")?;
+    let size = fields.get_u32("size")?.unwrap_or(160);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if !(1..=1024).contains(&size) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid size: {} (expected 1-1024)", size)));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let embedded = read_exif(&data).and_then(|exif| exif_thumbnail(&exif).map(<[u8]>::to_vec));
+
+    let (thumbnail, origin) = match embedded {
+        Some(thumbnail) => (thumbnail, "embedded"),
+        None if fallback => {
+            let img = load_oriented_image(&data, orient)?;
+            let thumb = img.thumbnail(size, size);
+            (output_image(&thumb, ImageFormat::Jpeg, &EncodeOptions::default())?.data, "generated")
+        }
+        None => {
+            return Err(AppError::InvalidFieldValue(
+                "The image has no embedded EXIF thumbnail; send fallback=true to generate one".to_string(),
+            ))
+        }
+    };
+
+    let filename = output_filename(source.as_deref(), "thumb", "jpg");
+    let mut headers = file_headers("image/jpeg", &filename, disposition);
+    headers.insert("X-Thumbnail-Source", HeaderValue::from_static(origin));
+    Ok((headers, thumbnail))
+}
+
+// Kaleidoscope of `segments` mirrored wedges (even, 2-32), `size` pixels
+// square (default the input's shorter side)
+pub async fn mirror_tile_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "segments", "size", "orient", "disposition"]).await?;
+    let segments = fields.get_u32("segments")?.unwrap_or(6);
+    let size = fields.get_u32("size")?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    // An odd count would leave a seam where the last wedge meets the first
+    if !(2..=32).contains(&segments) || segments % 2 != 0 {
+        return Err(AppError::InvalidFieldValue(format!(
+            "Invalid segments: {} (expected an even number from 2 to 32)",
+            segments
+        )));
+    }
+    if let Some(size) = size.filter(|size| !(1..=4096).contains(size)) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid size: {} (expected 1-4096)", size)));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let size = size.unwrap_or_else(|| img.width().min(img.height()).min(4096));
+    let pattern = kaleidoscope(&img, segments, size);
+
+    let result_data = output_image(&pattern, ImageFormat::Png, &EncodeOptions::default())?.data;
+    let filename = output_filename(source.as_deref(), "kaleidoscope", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+// Opaque preview of a transparent image over a checkerboard of
+// `checker_size`-pixel squares
+pub async fn preview_alpha_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "checker_size", "orient", "disposition"]).await?;
+    let checker_size = fields.get_u32("checker_size")?.unwrap_or(8);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if !(1..=256).contains(&checker_size) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid checker_size: {} (expected 1-256)", checker_size)));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let preview = checkerboard_preview(&img, checker_size);
+
+    let result_data = output_image(&preview, ImageFormat::Png, &EncodeOptions::default())?.data;
+    let filename = output_filename(source.as_deref(), "alpha-preview", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+// Before/after review image from the `before` and `after` uploads:
+// onion-skin overlay at `alpha`, or side by side
+pub async fn compare_visual_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["before", "after", "mode", "alpha", "orient", "disposition"]).await?;
+    let mode = fields.get_parsed("mode")?.unwrap_or(CompareMode::Overlay);
+    let alpha = fields.get_str("alpha").map(|text| parse_finite_f32("alpha", text, 0.0..=1.0)).transpose()?.unwrap_or(0.5);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let before_upload = fields.require_upload("before")?;
+    let before = load_oriented_image(&before_upload.data, orient)?;
+    let after = load_oriented_image(&fields.require_upload("after")?.data, orient)?;
+    let composite = compare_visual(&before, &after, mode, alpha)?;
+
+    let result_data = output_image(&composite, ImageFormat::Png, &EncodeOptions::default())?.data;
+
+    let source = before_upload.file_name.as_deref().and_then(sanitize_file_stem);
+    let filename = output_filename(source.as_deref(), "compare", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+// Recolor the `source` upload to match the `reference` upload's palette
+pub async fn color_transfer_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["source", "reference", "orient", "disposition"]).await?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let source_upload = fields.require_upload("source")?;
+    let source_img = load_oriented_image(&source_upload.data, orient)?;
+    let reference = load_oriented_image(&fields.require_upload("reference")?.data, orient)?;
+    let recolored = color_transfer(&source_img, &reference);
+
+    let result_data = output_image(&recolored, ImageFormat::Png, &EncodeOptions::default())?.data;
+
+    let source = source_upload.file_name.as_deref().and_then(sanitize_file_stem);
+    let filename = output_filename(source.as_deref(), "color-transfer", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+// Resize to a physical print size: `width_in`/`height_in` or
+// `width_cm`/`height_cm` at `dpi`, with the resolution recorded in the
+// output so print software lays it out at that size
+pub async fn scale_to_print_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "url", "width_in", "height_in", "width_cm", "height_cm", "dpi", "fit", "format", "orient",
+        "disposition",
+    ]).await?;
+    let positive = |name: &str| -> Result<Option<f32>, AppError> {
+        fields.get_str(name).map(|text| parse_finite_f32(name, text, 0.01..=1000.0)).transpose()
+    };
+    let (width_in, height_in) = match (positive("width_in")?, positive("height_in")?, positive("width_cm")?, positive("height_cm")?) {
+        (Some(w), Some(h), None, None) => (w, h),
+        (None, None, Some(w), Some(h)) => (w / 2.54, h / 2.54),
+        _ => {
+            return Err(AppError::InvalidFieldValue(
+                "Give either width_in and height_in, or width_cm and height_cm".to_string(),
+            ))
+        }
+    };
+    let dpi = fields.get_u32("dpi")?.unwrap_or(300);
+    let fit = fields.get_parsed("fit")?.unwrap_or(PrintFit::Fill);
+    let format = fields.get_parsed("format")?.unwrap_or(ImageFormat::Jpeg);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if !(1..=u16::MAX as u32).contains(&dpi) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid dpi: {} (expected 1-65535)", dpi)));
+    }
+    if !matches!(format, ImageFormat::Jpeg | ImageFormat::Png) {
+        return Err(AppError::InvalidFieldValue("Resolution can only be recorded in jpeg or png".to_string()));
+    }
+    let width = ((width_in * dpi as f32).round() as u32).max(1);
+    let height = ((height_in * dpi as f32).round() as u32).max(1);
+    validate_dimensions(width, height)?;
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let printed = resize_for_print(&img, width, height, fit)?;
+
+    let options = EncodeOptions { dpi: Some(dpi as u16), ..EncodeOptions::default() };
+    let encoded = output_image(&printed, format, &options)?;
+    let filename = output_filename(source.as_deref(), "print", format.extension());
+    let mut headers = file_headers(format.mime_type(), &filename, disposition);
+    headers.insert("X-Print-Pixels", format!("{}x{}", printed.width(), printed.height()).parse().unwrap());
+    add_flatten_headers(&mut headers, &encoded);
+    Ok((headers, encoded.data))
+}
+
+// Decoded pixels as raw RGBA8, row-major with no padding, for clients that
+// would otherwise decode straight away. `width`/`height` resize first: one
+// side keeps the aspect ratio, both give exactly that size.
+pub async fn raw_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "url", "width", "height", "filter", "orient", "disposition",
+    ]).await?;
+    let width = fields.get_u32("width")?;
+    let height = fields.get_u32("height")?;
+    let filter = fields.get_parsed("filter")?.unwrap_or_else(default_resize_filter);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if width == Some(0) || height == Some(0) {
+        return Err(AppError::InvalidFieldValue("Dimensions must be at least 1 pixel".to_string()));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let mut img = load_oriented_image(&data, orient)?;
+
+    let (orig_width, orig_height) = img.dimensions();
+    let size = match (width, height) {
+        (Some(w), Some(h)) => Some((w, h)),
+        (Some(w), None) => Some((w, ((orig_height as f32 * w as f32 / orig_width as f32).round() as u32).max(1))),
+        (None, Some(h)) => Some((((orig_width as f32 * h as f32 / orig_height as f32).round() as u32).max(1), h)),
+        (None, None) => None,
+    };
+    if let Some((w, h)) = size {
+        validate_dimensions(w, h)?;
+        img = resize_image_with_alpha(&img, w, h, filter, true)?;
+    }
+
+    let rgba = img.to_rgba8();
+    let filename = output_filename(source.as_deref(), "raw", "rgba");
+    let mut headers = file_headers("application/octet-stream", &filename, disposition);
+    headers.insert("X-Width", rgba.width().into());
+    headers.insert("X-Height", rgba.height().into());
+    headers.insert("X-Channels", 4u32.into());
+    Ok((headers, rgba.into_raw()))
+}
+
+// Low-quality image placeholders: a BlurHash string and, unless
+// `thumbnail=false`, a tiny blurred PNG as a data URI
+pub async fn placeholder_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "url", "components_x", "components_y", "thumbnail", "thumb_size", "orient",
+    ]).await?;
+    let components_x = fields.get_u32("components_x")?.unwrap_or(4);
+    let components_y = fields.get_u32("components_y")?.unwrap_or(3);
+    let thumbnail = fields.get("thumbnail")?.unwrap_or(true);
+    let thumb_size = fields.get_u32("thumb_size")?.unwrap_or(16);
+    let orient = fields.get("orient")?.unwrap_or(true);
+
+    for (name, value) in [("components_x", components_x), ("components_y", components_y)] {
+        if !(1..=9).contains(&value) {
+            return Err(AppError::InvalidFieldValue(format!("Invalid {}: {} (expected 1-9)", name, value)));
+        }
+    }
+    if !(1..=64).contains(&thumb_size) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid thumb_size: {} (expected 1-64)", thumb_size)));
+    }
+
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let (width, height) = img.dimensions();
+
+    let mut response = json!({
+        "blurhash": encode_blurhash(&img, components_x, components_y)?,
+        "width": width,
+        "height": height,
+    });
+    if thumbnail {
+        use base64::Engine;
+        let png = placeholder_thumbnail(&img, thumb_size)?;
+        response["thumbnail"] = json!(format!(
+            "data:image/png;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(png)
+        ));
+    }
+
+    Ok(Json(response))
+}
+
+pub async fn perspective_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "url", "points", "width", "height", "interpolation", "orient", "disposition",
+    ]).await?;
+    let corners = parse_corner_points(fields.require_str("points")?)?;
+    let width = fields.get_u32("width")?;
+    let height = fields.get_u32("height")?;
+    let interpolation = fields.get_parsed("interpolation")?.unwrap_or(Interpolation::Bilinear);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let size = match (width, height) {
+        (Some(width), Some(height)) => Some((width, height)),
+        (None, None) => None,
+        _ => return Err(AppError::InvalidFieldValue("Give both width and height, or neither".to_string())),
+    };
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    reject_animated(&data, "perspective")?;
+    let warped = perspective_warp(&img, corners, size, interpolation)?;
+
+    let encoded = output_image(&warped, ImageFormat::Png, &EncodeOptions::default())?;
+    let filename = output_filename(source.as_deref(), "perspective", "png");
+    Ok((file_headers("image/png", &filename, disposition), encoded.data))
+}
+
+// Pack the uploads into one PNG and return it in a ZIP with a JSON atlas of
+// where each sprite landed
+pub async fn sprite_sheet_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "padding"]).await?;
+    let padding = fields.get_u32("padding")?.unwrap_or(0);
+    if fields.file.is_none() {
+        return Err(AppError::MissingField("file".to_string()));
+    }
+
+    let mut used_names = std::collections::HashSet::new();
+    let mut names = Vec::new();
+    let mut sprites = Vec::new();
+    for upload in fields.files("file") {
+        names.push(unique_stem(&mut used_names, upload.file_name.as_deref().unwrap_or("image")));
+        sprites.push(load_image_from_bytes(&upload.data)?);
+    }
+
+    let (sheet, positions) = pack_sprites(&sprites, padding)?;
+    let atlas = json!({
+        "width": sheet.width(),
+        "height": sheet.height(),
+        "sprites": names.iter().zip(&sprites).zip(&positions).map(|((name, sprite), &(x, y))| json!({
+            "name": name,
+            "x": x,
+            "y": y,
+            "width": sprite.width(),
+            "height": sprite.height(),
+        })).collect::<Vec<_>>(),
+    });
+
+    let encoded = encode_image_to_bytes(&sheet, ImageFormat::Png, &EncodeOptions::default())?;
+    let atlas_json = serde_json::to_vec_pretty(&atlas)
+        .map_err(|e| AppError::ImageProcessingError(e.to_string()))?;
+    let zip_data = write_zip(&[
+        ("sheet.png".to_string(), encoded.data),
+        ("atlas.json".to_string(), atlas_json),
+    ])?;
+
+    Ok((file_headers("application/zip", "sprite-sheet.zip", Disposition::Attachment), zip_data))
+}
+
+// Crop away a uniform border, or with `alpha` a fully transparent one
+pub async fn trim_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "alpha", "tolerance", "orient", "disposition"]).await?;
+    let alpha = fields.get_bool("alpha")?;
+    let tolerance: u8 = fields.get("tolerance")?.unwrap_or(0);
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    reject_animated(&data, "trim")?;
+
+    let (x, y, width, height) = content_bounds(&img, alpha, tolerance)
+        .ok_or_else(|| AppError::InvalidFieldValue("Nothing left after trimming: the image is all border".to_string()))?;
+    let trimmed = img.crop_imm(x, y, width, height);
+    let result_data = encode_png_like_source(&trimmed, &data, &EncodeOptions::default())?;
+
+    let filename = output_filename(source.as_deref(), "trimmed", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+pub async fn compare_formats_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let mut image_data = None;
+    let mut image_url: Option<String> = None;
+    let mut encode_options = EncodeOptions::default();
+    let mut formats = "jpg,png,webp,avif".to_string();
+    let mut with_psnr = false;
+    let mut orient = true;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
+        
+        let name = field.name().unwrap_or("");
+        match name {
+            "file" => {
+                let data = field.bytes().await
+                    .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
+                validate_upload(&data)?;
+                image_data = Some(data);
+            }
+            "url" => image_url = field.text().await.ok(),
+            name if EncodeOptions::FIELDS.contains(&name) => {
+                let name = name.to_string();
+                if let Ok(text) = field.text().await {
+                    encode_options.set_field(&name, &text)?;
+                }
+            }
+            "formats" => formats = field.text().await.unwrap_or(formats),
+            "psnr" => if let Ok(text) = field.text().await { with_psnr = text.parse().unwrap_or(false); },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            _ => {}
+        }
+    }
+
+    let data = resolve_image(image_data, image_url).await?;
+    let img = load_oriented_image(&data, orient)?;
+
+    let mut results = Vec::new();
+    for format_name in formats.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        let (label, encoded, decodable) = if format_name.eq_ignore_ascii_case("avif") {
+            ("avif".to_string(), encode_avif(&img, encode_options.quality, encode_options.effort)?, false)
+        } else {
+            let format: ImageFormat = format_name.parse()?;
+            (format.extension().to_string(), encode_image_to_bytes(&img, format, &encode_options)?.data, true)
+        };
+
+        let psnr_value = if with_psnr && decodable {
+            Some(psnr(&img, &load_image_from_bytes(&encoded)?))
+        } else {
+            None
+        };
+
+        results.push(FormatSize {
+            format: label,
+            size: encoded.len() as u64,
+            size_human: format_file_size(encoded.len() as u64),
+            psnr: psnr_value,
+        });
+    }
+
+    Ok(Json(json!({
+        "quality": encode_options.quality,
+        "original_size": data.len(),
+        "formats": results,
+    })))
+}
+
+// Identify an upload without requiring it to be a format we can decode
+pub async fn detect_format_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_unvalidated_fields(multipart, &["file", "url"]).await?;
+    let image_url = fields.get_string("url");
+    let data = match (fields.file, image_url) {
+        (Some(data), _) => data,
+        (None, Some(url)) => fetch_image_url(&url).await?,
+        (None, None) => return Err(AppError::MissingField("file".to_string())),
+    };
+    let detection = detect_format(&data).ok_or(AppError::UnsupportedImageFormat)?;
+    Ok(Json(detection))
+}
+
+pub async fn info_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let mut image_data = None;
+    let mut image_url: Option<String> = None;
+    let mut filename = "unknown".to_string();
+    let mut fields: Option<String> = None;
+    let mut count_colors = false;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
+        
+        let name = field.name().unwrap_or("");
+        if name == "file" {
+            if let Some(file_name) = field.file_name() {
+                filename = file_name.to_string();
+            }
+            let data = field.bytes().await
+                .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
+            validate_upload(&data)?;
+            image_data = Some(data);
+        } else if name == "url" {
+            image_url = field.text().await.ok();
+        } else if name == "fields" {
+            fields = field.text().await.ok();
+        } else if name == "count_colors" {
+            if let Ok(text) = field.text().await { count_colors = text.parse().unwrap_or(false); }
+        }
+    }
+
+    // Fetched images are named after the last segment of the URL path
+    if let (None, Some(name)) = (&image_data, image_url.as_deref().and_then(url_file_name)) {
+        filename = name.to_string();
+    }
+
+    let data = resolve_image(image_data, image_url).await?;
+
+    // Dimensions come from the header alone, which stays cheap for huge files
+    if fields.as_deref().map(str::trim) == Some("dimensions") {
+        return Ok(Json(get_image_dimensions(&data, &filename)?).into_response());
+    }
+
+    Ok(Json(decoded_image_info(&data, &filename, count_colors)?).into_response())
+}
+
+// Counting stops here, so a noisy photo costs a bounded amount of work
+const MAX_COUNTED_COLORS: usize = 65_536;
+
+fn decoded_image_info(data: &[u8], filename: &str, count_colors: bool) -> Result<ImageInfo, AppError> {
+    let img = load_image_from_bytes(data)?;
+
+    let mut info = get_image_info(&img, filename, data.len());
+    // Decoding expands palettes, so check the source for one
+    if let Some(palette_size) = png_palette_size(data) {
+        info.mode = "P".to_string();
+        info.palette_size = Some(palette_size);
+    }
+    if count_colors {
+        info.unique_colors = Some(count_unique_colors(&img, MAX_COUNTED_COLORS));
+    }
+    info.is_lossless = is_lossless(data);
+
+    Ok(info)
+}
+
+// Info for every uploaded `file`, streamed back as NDJSON while the upload is
+// still being read: one line per file, either its info or its error
+pub async fn info_batch_handler(multipart: Multipart) -> impl IntoResponse {
+    let lines = futures_util::stream::unfold(Some(FormReader::new(multipart)), |form| async move {
+        let mut form = form?;
+        loop {
+            let field = match form.next_field().await {
+                Ok(Some(field)) => field,
+                Ok(None) => return None,
+                Err(error) => {
+                    // The rest of the body can't (or won't) be read, so report and stop
+                    return Some((ndjson_line(&json!({ "error": error.to_string(), "code": error.code() })), None));
+                }
+            };
+            if field.name() != Some("file") {
+                continue;
+            }
+
+            let filename = field.file_name().unwrap_or("unknown").to_string();
+            let info = match field.bytes().await {
+                Ok(data) => validate_upload(&data).and_then(|_| decoded_image_info(&data, &filename, false)),
+                Err(e) => Err(AppError::ImageProcessingError(format!("Failed to read file: {}", e))),
+            };
+            let line = match info {
+                Ok(info) => ndjson_line(&info),
+                Err(e) => ndjson_line(&json!({ "file": filename, "error": e.to_string(), "code": e.code() })),
+            };
+            return Some((line, Some(form)));
+        }
+    });
+
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(lines))
+}
+
+fn ndjson_line(value: &impl serde::Serialize) -> Result<Bytes, std::convert::Infallible> {
+    let mut line = serde_json::to_vec(value).unwrap_or_default();
+    line.push(b'\n');
+    Ok(Bytes::from(line))
+}
+
+pub async fn metadata_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let mut image_data = None;
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut strip = false;
+    let mut set_text: Option<String> = None;
+    let mut orient = true;
+    let mut disposition = Disposition::Attachment;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
+        
+        let name = field.name().unwrap_or("");
+        match name {
+            "file" => {
+                file_name = field.file_name().map(str::to_string);
+                let data = field.bytes().await
+                    .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
+                validate_upload(&data)?;
+                image_data = Some(data);
+            }
+            "url" => image_url = field.text().await.ok(),
+            "strip" => if let Ok(text) = field.text().await { strip = text.parse().unwrap_or(false); },
+            "set_text" => set_text = field.text().await.ok(),
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
+            _ => {}
+        }
+    }
+
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+
+    if let Some(entry) = set_text {
+        // `keyword=value` becomes a PNG text chunk; the pixels are untouched
+        let (keyword, value) = entry.split_once('=').ok_or_else(|| {
+            AppError::InvalidFieldValue(format!("Invalid set_text: expected 'keyword=value', got '{}'", entry))
+        })?;
+        let result_data = write_png_text(&data, keyword.trim(), value)?;
+
+        let filename = output_filename(source.as_deref(), "text", "png");
+        Ok((file_headers("image/png", &filename, disposition), result_data).into_response())
+    } else if strip {
+        // Strip metadata and return image
+        let img = load_oriented_image(&data, orient)?;
+        let result_data = encode_png_like_source(&img, &data, &EncodeOptions::default())?;
+
+        let filename = output_filename(source.as_deref(), "stripped", "png");
+        Ok((file_headers("image/png", &filename, disposition), result_data).into_response())
+    } else {
+        // Return metadata as JSON
+        let xmp = find_xmp_packet(&data).map(|packet| parse_xmp(&packet));
+        let exif_data = json!({
+            "data": read_exif(&data).map(|exif| exif_tags(&exif)).unwrap_or_default(),
+            "xmp": xmp,
+            "png_text": png_text_chunks(&data),
+        });
+        Ok(Json(exif_data).into_response())
+    }
+}
+
+// Write text tags (Artist=..., Copyright=...) into the EXIF of a JPEG or PNG
+// without re-encoding it. Any field other than the input is a tag name.
+pub async fn exif_edit_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_all_fields(multipart).await?;
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+    let image_url = fields.get_string("url");
+
+    let mut tags: Vec<(exif::Tag, String)> = Vec::new();
+    for (name, value) in fields.text_fields() {
+        if matches!(name, "url" | "disposition") {
+            continue;
+        }
+        let tag = writable_exif_tag(name).ok_or_else(|| AppError::InvalidFieldValue(format!(
+            "Unknown EXIF tag '{}'. Expected one of: {}",
+            name,
+            writable_exif_tag_names().join(", ")
+        )))?;
+        tags.retain(|(existing, _)| *existing != tag);
+        tags.push((tag, value.to_string()));
+    }
+
+    if tags.is_empty() {
+        return Err(AppError::MissingField(format!("EXIF tag (one of: {})", writable_exif_tag_names().join(", "))));
+    }
+
+    let source = source_stem(fields.file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(fields.file, image_url).await?;
+    let format = validate_upload(&data)?;
+    let result_data = write_exif_tags(&data, &tags)?;
+
+    let filename = output_filename(source.as_deref(), "exif", format.extension());
+    Ok((file_headers(format.mime_type(), &filename, disposition), result_data))
+}
+
+// Strip metadata from every uploaded file and return them in a ZIP with a
+// manifest of what was removed
+pub async fn strip_all_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[&["file"][..], EncodeOptions::FIELDS].concat()).await?;
+    let encode_options = fields.encode_options()?;
+    if fields.file.is_none() {
+        return Err(AppError::MissingField("file".to_string()));
+    }
+
+    let mut entries = Vec::new();
+    let mut used_names = std::collections::HashSet::new();
+    let mut manifest = Vec::new();
+
+    for upload in fields.files("file") {
+        let file_name = upload.file_name.as_deref().unwrap_or("image");
+        let data = &upload.data;
+        let format = validate_upload(data)?;
+        let exif = read_exif(data);
+        let orientation = exif_orientation(data).filter(|&o| o != 1);
+
+        let mut stripped = Vec::new();
+        if let Some(exif) = &exif {
+            stripped.push("exif");
+            if has_gps(exif) {
+                stripped.push("gps");
+            }
+        }
+        if find_xmp_packet(data).is_some() {
+            stripped.push("xmp");
+        }
+
+        // Re-encoding drops every metadata block, so bake the rotation into
+        // the pixels first
+        let mut img = load_image_from_bytes(data)?;
+        if let Some(orientation) = orientation {
+            img = apply_exif_orientation(img, orientation);
+        }
+        let encoded = encode_image_to_bytes(&img, format, &encode_options)?;
+
+        let output_name = format!("{}.{}", unique_stem(&mut used_names, file_name), format.extension());
+
+        manifest.push(json!({
+            "file": file_name,
+            "output": output_name,
+            "stripped": stripped,
+            "orientation_applied": orientation,
+        }));
+        entries.push((output_name, encoded.data));
+    }
+
+    let manifest_json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| AppError::ImageProcessingError(e.to_string()))?;
+    entries.push(("manifest.json".to_string(), manifest_json));
+    let zip_data = write_zip(&entries)?;
+
+    Ok((file_headers("application/zip", "stripped.zip", Disposition::Attachment), zip_data))
+}
+
+pub async fn contact_sheet_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &[
+        "file", "columns", "thumb_size", "gap", "bg_color", "labels", "orient", "disposition",
+    ]).await?;
+    let columns = fields.get_u32("columns")?.unwrap_or(4);
+    let thumb_size = fields.get_u32("thumb_size")?.unwrap_or(200);
+    let gap = fields.get_u32("gap")?.unwrap_or(10);
+    let bg_color = fields.get_str("bg_color").unwrap_or("white");
+    let labels = fields.get_bool("labels")?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    if fields.file.is_none() {
+        return Err(AppError::MissingField("file".to_string()));
+    }
+    if thumb_size == 0 {
+        return Err(AppError::InvalidFieldValue("thumb_size must be at least 1 pixel".to_string()));
+    }
+
+    let background = parse_color(bg_color)?;
+    let images = fields
+        .files("file")
+        .map(|upload| {
+            let file_name = upload.file_name.clone().unwrap_or_else(|| "image".to_string());
+            Ok((file_name, load_oriented_image(&upload.data, orient)?))
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+    let sheet = contact_sheet(&images, columns, thumb_size, gap, background, labels)?;
+
+    let encoded = output_image(&sheet, ImageFormat::Png, &EncodeOptions::default())?;
+    Ok((file_headers("image/png", "contact-sheet.png", disposition), encoded.data))
+}
+
+pub async fn watermark_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let mut image_data = None;
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut text: Option<String> = None;
+    let mut position = "bottom-right".to_string();
+    let mut opacity = 0.3f32;
+    let mut size: Option<u32> = None;
+    let mut size_pct: Option<f32> = None;
+    let mut color = "white".to_string();
+    let mut tile = false;
+    let mut angle = 0.0f32;
+    let mut orient = true;
+    let mut preserve_metadata = false;
+    let mut disposition = Disposition::Attachment;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
+        
+        let name = field.name().unwrap_or("");
+        match name {
+            "file" => {
+                file_name = field.file_name().map(str::to_string);
+                let data = field.bytes().await
+                    .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
+                validate_upload(&data)?;
+                image_data = Some(data);
+            }
+            "url" => image_url = field.text().await.ok(),
+            "text" => text = field.text().await.ok(),
+            "position" => position = field.text().await.unwrap_or("bottom-right".to_string()),
+            "opacity" => if let Ok(text_val) = field.text().await { opacity = parse_finite_f32("opacity", &text_val, 0.0..=1.0)?; },
+            "size" => if let Ok(text_val) = field.text().await { size = Some(parse_int("size", &text_val)?); },
+            "size_pct" => if let Ok(text_val) = field.text().await {
+                size_pct = Some(parse_finite_f32("size_pct", text_val.trim().trim_end_matches('%'), 0.0..=100.0)?);
+            },
+            "color" => color = field.text().await.unwrap_or("white".to_string()),
+            "tile" => if let Ok(text_val) = field.text().await { tile = text_val.parse().unwrap_or(false); },
+            "angle" => if let Ok(text_val) = field.text().await { angle = parse_finite_f32("angle", &text_val, ..)?; },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "preserve_metadata" => if let Ok(text) = field.text().await { preserve_metadata = text.parse().unwrap_or(false); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
+            _ => {}
+        }
+    }
+
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    let watermark_text = text.ok_or(AppError::MissingField("text".to_string()))?;
+    
+    let img = load_oriented_image(&data, orient)?;
+
+    // `size` is absolute px; `size_pct` is a percentage of the shorter side,
+    // so the watermark keeps its proportions across resolutions
+    let shorter_side = img.width().min(img.height());
+    let font_size = match (size, size_pct) {
+        (Some(px), _) => px as f32,
+        (None, pct) => shorter_side as f32 * pct.unwrap_or(5.0) / 100.0,
+    };
+    if !font_size.is_finite() || font_size < 1.0 {
+        return Err(AppError::InvalidFieldValue("Watermark size must be at least 1px".to_string()));
+    }
+    if font_size > shorter_side as f32 {
+        return Err(AppError::InvalidFieldValue(format!(
+            "Watermark size {}px is larger than the image's shorter side ({}px)",
+            font_size, shorter_side
+        )));
+    }
+
+    let color = parse_color(&color)?;
+    let img = draw_watermark_text(&img, &watermark_text, font_size, color, opacity, &position)?;
+
+    let result_data = output_image(&img, ImageFormat::Png, &EncodeOptions::default())?.data;
+    let result_data = with_metadata(preserve_metadata, &data, result_data, orient)?;
+
+    let filename = output_filename(source.as_deref(), "watermarked", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+pub async fn blur_face_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let mut image_data = None;
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut strength = 25u32;
+    let mut region: Option<String> = None;
+    let mut blur_algo = BlurAlgorithm::Gaussian;
+    let mut style = CensorStyle::Gaussian;
+    let mut shape = CensorShape::Box;
+    let mut fill_color = "black".to_string();
+    let mut orient = true;
+    let mut disposition = Disposition::Attachment;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
+        
+        let name = field.name().unwrap_or("");
+        match name {
+            "file" => {
+                file_name = field.file_name().map(str::to_string);
+                let data = field.bytes().await
+                    .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
+                validate_upload(&data)?;
+                image_data = Some(data);
+            }
+            "url" => image_url = field.text().await.ok(),
+            "strength" => if let Ok(text) = field.text().await { strength = text.parse().unwrap_or(25); },
+            "region" => region = field.text().await.ok(),
+            "blur_algo" => if let Ok(text) = field.text().await { blur_algo = text.parse()?; },
+            "style" => if let Ok(text) = field.text().await { style = text.parse()?; },
+            "shape" => if let Ok(text) = field.text().await { shape = text.parse()?; },
+            "fill_color" => if let Ok(text) = field.text().await { fill_color = text; },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
+            _ => {}
+        }
+    }
+
+    let fill = parse_color(&fill_color)?;
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    let img = load_oriented_image(&data, orient)?;
+    // Blurring or pixelating black-and-white art invents grays it never had
+    if is_bilevel(&data) && style != CensorStyle::Fill {
+        return Err(AppError::InvalidFieldValue(
+            "The input is a 1-bit image; only style=fill keeps it black and white".to_string(),
+        ));
+    }
+
+    // Censor the given region, or the entire image (simplified face detection)
+    let region = match region {
+        Some(region_str) => parse_region(&region_str)?,
+        None => (0, 0, img.width(), img.height()),
+    };
+    let img = censor_region(&img, region, style, shape, strength, blur_algo, fill)?;
+
+    let result_data = output_image(&img, ImageFormat::Png, &EncodeOptions::default())?.data;
+
+    let filename = output_filename(source.as_deref(), "blurred", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+pub async fn face_count_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "orient"]).await?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let boxes = detect_faces(&img)?;
+
+    Ok(Json(FaceCount { count: boxes.len(), boxes }))
+}
+
+pub async fn stego_embed_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "message", "orient", "disposition"]).await?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let message = fields.require_str("message")?;
+    let img = load_oriented_image(&data, orient)?;
+
+    let carrier = embed_lsb_message(&img, message.as_bytes())?;
+
+    // Output must be lossless or the hidden bits are destroyed
+    let result_data = output_image(&carrier, ImageFormat::Png, &EncodeOptions::default())?.data;
+
+    let filename = output_filename(source.as_deref(), "stego", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+pub async fn stego_extract_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "orient"]).await?;
+    // Must match stego-embed's orient, or the bits are read in another order
+    let orient = fields.get("orient")?.unwrap_or(true);
+
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+
+    let message = String::from_utf8(extract_lsb_message(&img)?)
+        .map_err(|_| AppError::InvalidFieldValue("No hidden message found in image".to_string()))?;
+
+    Ok(Json(json!({ "message": message })))
+}
+
+pub async fn upscale_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let mut image_data = None;
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut scale = 2u32;
+    let mut sharpen = true;
+    let mut sharpen_amount = 1.0;
+    let mut sharpen_threshold = 1u8;
+    let mut mode = "fast".to_string();
+    let mut model: Option<String> = None;
+    let mut filter: Option<ResizeFilter> = None;
+    let mut antialias = true;
+    let mut orient = true;
+    let mut disposition = Disposition::Attachment;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
+        
+        let name = field.name().unwrap_or("");
+        match name {
+            "file" => {
+                file_name = field.file_name().map(str::to_string);
+                let data = field.bytes().await
+                    .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
+                validate_upload(&data)?;
+                image_data = Some(data);
+            }
+            "url" => image_url = field.text().await.ok(),
+            "scale" => if let Ok(text) = field.text().await { scale = text.parse().unwrap_or(2).clamp(1, 8); },
+            "sharpen" => if let Ok(text) = field.text().await { sharpen = text.parse().unwrap_or(true); },
+            "sharpen_amount" => if let Ok(text) = field.text().await {
+                sharpen_amount = parse_finite_f32("sharpen_amount", &text, (Bound::Excluded(0.0), Bound::Included(10.0)))?;
+            },
+            "sharpen_threshold" => if let Ok(text) = field.text().await {
+                sharpen_threshold = text.trim().parse().map_err(|_| {
+                    AppError::InvalidFieldValue(format!("Invalid sharpen_threshold: {} (expected 0 to 255)", text.trim()))
+                })?;
+            },
+            "mode" => mode = field.text().await.unwrap_or("fast".to_string()),
+            "model" => model = field.text().await.ok(),
+            "filter" => if let Ok(text) = field.text().await { filter = Some(text.parse()?); },
+            "antialias" => if let Ok(text) = field.text().await { antialias = text.parse().unwrap_or(true); },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
+            _ => {}
+        }
+    }
+
+    // Without anti-aliasing every source pixel becomes a hard-edged block
+    let filter = match (filter, antialias) {
+        (Some(filter), true) => filter,
+        (None, true) => default_resize_filter(),
+        (None | Some(ResizeFilter::Nearest), false) => ResizeFilter::Nearest,
+        (Some(filter), false) => {
+            return Err(AppError::InvalidFieldValue(format!(
+                "antialias=false upscales with nearest; it can't be combined with filter={}",
+                filter.as_str()
+            )));
+        }
+    };
 
-    let temp_path = create_temp_file("png");
-    save_image(&upscaled, &temp_path, ImageFormat::Png)?;
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let (width, height) = img.dimensions();
+    validate_upscale(width, height, scale)?;
+
+    let new_width = width * scale;
+    let new_height = height * scale;
+    let mut upscaled = match (model.as_deref(), mode.as_str()) {
+        (Some("esrgan"), _) => upscale_with_model(&img, new_width, new_height)?,
+        (Some(other), _) => return Err(AppError::InvalidFieldValue(format!("Unknown upscale model: {}", other))),
+        // Nearest keeps pixel art crisp; doubling in steps gains nothing
+        (None, _) if filter == ResizeFilter::Nearest => {
+            resize_image_with_alpha(&img, new_width, new_height, filter, true)?
+        }
+        (None, "fast") => resize_image_with_alpha(&img, new_width, new_height, filter, true)?,
+        (None, "quality") => upscale_iterative(&img, new_width, new_height, filter)?,
+        _ => return Err(AppError::InvalidFieldValue(format!("Unknown upscale mode: {}", mode))),
+    };
     
-    let result_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
+    // Apply sharpening if requested. Sharpening nearest output would only
+    // add halos around the blocks.
+    if sharpen && filter != ResizeFilter::Nearest {
+        upscaled = unsharpen_image(&upscaled, sharpen_amount, sharpen_threshold as i32);
+    }
+
+    let result_data = output_image(&upscaled, ImageFormat::Png, &EncodeOptions::default())?.data;
 
-    Ok((
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, "image/png"),
-            (header::CONTENT_DISPOSITION, "attachment; filename=\"upscaled.png\""),
-        ],
-        result_data,
-    ))
+    let filename = output_filename(source.as_deref(), "upscaled", "png");
+    let mut headers = file_headers("image/png", &filename, disposition);
+    if model.is_none() {
+        headers.insert("X-Resize-Filter", HeaderValue::from_static(filter.as_str()));
+    }
+    Ok((headers, result_data))
+}
+
+pub async fn colorize_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+    let fields = collect_fields(multipart, &["file", "url", "orient", "disposition"]).await?;
+    let orient = fields.get("orient")?.unwrap_or(true);
+    let disposition = fields.get_parsed("disposition")?.unwrap_or(Disposition::Attachment);
+
+    let source = source_stem(fields.file_name.as_deref(), fields.get_str("url"));
+    let data = resolve_image(fields.file.clone(), fields.get_string("url")).await?;
+    let img = load_oriented_image(&data, orient)?;
+    let colorized = colorize(&img)?;
+
+    let encoded = output_image(&colorized, ImageFormat::Png, &EncodeOptions::default())?;
+    let filename = output_filename(source.as_deref(), "colorized", "png");
+    Ok((file_headers("image/png", &filename, disposition), encoded.data))
 }
 
-pub async fn meme_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+pub async fn meme_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
     let mut image_data = None;
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
     let mut top: Option<String> = None;
     let mut bottom: Option<String> = None;
     let mut size: Option<u32> = None;
+    let mut orient = true;
+    let mut disposition = Disposition::Attachment;
 
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
         
         let name = field.name().unwrap_or("");
         match name {
             "file" => {
+                file_name = field.file_name().map(str::to_string);
                 let data = field.bytes().await
                     .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
                 validate_upload(&data)?;
                 image_data = Some(data);
             }
+            "url" => image_url = field.text().await.ok(),
             "top" => top = field.text().await.ok(),
             "bottom" => bottom = field.text().await.ok(),
             "size" => if let Ok(text) = field.text().await { size = text.parse().ok(); },
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
             _ => {}
         }
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let img = load_image_from_bytes(&data)?;
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    let img = load_oriented_image(&data, orient)?;
     
     // Add meme text (simplified - would need proper text rendering with fonts)
     // For now, return original image
     // TODO: Implement meme text rendering with ab_glyph and embedded font
     
-    let temp_path = create_temp_file("png");
-    save_image(&img, &temp_path, ImageFormat::Png)?;
-    
-    let result_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
+    let result_data = output_image(&img, ImageFormat::Png, &EncodeOptions::default())?.data;
 
-    Ok((
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, "image/png"),
-            (header::CONTENT_DISPOSITION, "attachment; filename=\"meme.png\""),
-        ],
-        result_data,
-    ))
+    let filename = output_filename(source.as_deref(), "meme", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
 }
 
-pub async fn edit_handler(mut multipart: Multipart) -> Result<impl IntoResponse, AppError> {
+pub async fn edit_handler(multipart: Multipart) -> Result<impl IntoResponse, AppError> {
     let mut image_data = None;
+    let mut image_url: Option<String> = None;
+    let mut file_name: Option<String> = None;
     let mut brightness: Option<f32> = None;
     let mut contrast: Option<f32> = None;
     let mut saturation: Option<f32> = None;
@@ -652,43 +2128,107 @@ pub async fn edit_handler(mut multipart: Multipart) -> Result<impl IntoResponse,
     let mut flip: Option<String> = None;
     let mut auto_enhance = false;
     let mut thumbnail: Option<u32> = None;
-
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| AppError::ImageProcessingError(format!("Multipart error: {}", e)))? {
+    let mut equalize = false;
+    let mut per_channel = false;
+    let mut blur_algo = BlurAlgorithm::Gaussian;
+    let mut shadow = false;
+    let mut shadow_color = "black".to_string();
+    let mut shadow_blur = 8.0f32;
+    let mut shadow_offset_x = 10i32;
+    let mut shadow_offset_y = 10i32;
+    let mut temperature: Option<f32> = None;
+    let mut tint: Option<f32> = None;
+    let mut duotone = false;
+    let mut duotone_shadow = "black".to_string();
+    let mut duotone_highlight = "white".to_string();
+    let mut levels: Option<String> = None;
+    let mut variants: Option<String> = None;
+    let mut orient = true;
+    let mut preserve_metadata = false;
+    let mut disposition = Disposition::Attachment;
+
+    let mut form = FormReader::new(multipart);
+    while let Some(field) = form.next_field().await? {
         
         let name = field.name().unwrap_or("");
         match name {
             "file" => {
+                file_name = field.file_name().map(str::to_string);
                 let data = field.bytes().await
                     .map_err(|e| AppError::ImageProcessingError(format!("Failed to read file: {}", e)))?;
                 validate_upload(&data)?;
                 image_data = Some(data);
             }
-            "brightness" => if let Ok(text) = field.text().await { brightness = text.parse().ok(); },
-            "contrast" => if let Ok(text) = field.text().await { contrast = text.parse().ok(); },
-            "saturation" => if let Ok(text) = field.text().await { saturation = text.parse().ok(); },
-            "sharpness" => if let Ok(text) = field.text().await { sharpness = text.parse().ok(); },
+            "url" => image_url = field.text().await.ok(),
+            "brightness" => if let Ok(text) = field.text().await {
+                brightness = Some(parse_finite_f32("brightness", &text, ..)?.clamp(-1.0, 1.0));
+            },
+            "contrast" => if let Ok(text) = field.text().await { contrast = Some(parse_finite_f32("contrast", &text, ..)?); },
+            "saturation" => if let Ok(text) = field.text().await { saturation = Some(parse_finite_f32("saturation", &text, ..)?); },
+            "sharpness" => if let Ok(text) = field.text().await { sharpness = Some(parse_finite_f32("sharpness", &text, ..)?); },
             "filter" => filter = field.text().await.ok(),
             "border" => if let Ok(text) = field.text().await { border = text.parse().ok(); },
             "border_color" => border_color = field.text().await.unwrap_or("black".to_string()),
             "flip" => flip = field.text().await.ok(),
             "auto_enhance" => if let Ok(text) = field.text().await { auto_enhance = text.parse().unwrap_or(false); },
             "thumbnail" => if let Ok(text) = field.text().await { thumbnail = text.parse().ok(); },
+            "equalize" => if let Ok(text) = field.text().await { equalize = text.parse().unwrap_or(false); },
+            "blur_algo" => if let Ok(text) = field.text().await { blur_algo = text.parse()?; },
+            "per_channel" => if let Ok(text) = field.text().await { per_channel = text.parse().unwrap_or(false); },
+            "shadow" => if let Ok(text) = field.text().await { shadow = text.parse().unwrap_or(false); },
+            "shadow_color" => shadow_color = field.text().await.unwrap_or("black".to_string()),
+            "shadow_blur" => if let Ok(text) = field.text().await { shadow_blur = parse_finite_f32("shadow_blur", &text, 0.0..=MAX_SHADOW_BLUR)?; },
+            "shadow_offset_x" => if let Ok(text) = field.text().await { shadow_offset_x = parse_int("shadow_offset_x", &text)?; },
+            "shadow_offset_y" => if let Ok(text) = field.text().await { shadow_offset_y = parse_int("shadow_offset_y", &text)?; },
+            "temperature" => if let Ok(text) = field.text().await { temperature = Some(parse_finite_f32("temperature", &text, ..)?); },
+            "tint" => if let Ok(text) = field.text().await { tint = Some(parse_finite_f32("tint", &text, ..)?); },
+            "duotone" => if let Ok(text) = field.text().await { duotone = text.parse().unwrap_or(false); },
+            "duotone_shadow" => duotone_shadow = field.text().await.unwrap_or("black".to_string()),
+            "duotone_highlight" => duotone_highlight = field.text().await.unwrap_or("white".to_string()),
+            "levels" => levels = field.text().await.ok(),
+            "variants" => variants = field.text().await.ok(),
+            "orient" => if let Ok(text) = field.text().await { orient = text.parse().unwrap_or(true); },
+            "preserve_metadata" => if let Ok(text) = field.text().await { preserve_metadata = text.parse().unwrap_or(false); },
+            "disposition" => if let Ok(text) = field.text().await { disposition = text.parse()?; },
             _ => {}
         }
     }
 
-    let data = image_data.ok_or(AppError::MissingField("file".to_string()))?;
-    let mut img = load_image_from_bytes(&data)?;
+    let levels = levels.as_deref().map(parse_levels).transpose()?;
+    let variants = match variants {
+        Some(json) => Some(parse_variants(&json)?),
+        None => None,
+    };
+
+    let source = source_stem(file_name.as_deref(), image_url.as_deref());
+    let data = resolve_image(image_data, image_url).await?;
+    let mut img = load_oriented_image(&data, orient)?;
+    reject_animated(&data, "edit")?;
 
-    // Apply brightness adjustment
+    // Apply brightness adjustment: -1.0 (black) to 1.0 (white)
     if let Some(b) = brightness {
-        img = img.brighten((b * 255.0) as i32);
+        img = img.brighten((b * 255.0).round().clamp(-255.0, 255.0) as i32);
+    }
+
+    // Stretch contrast of washed-out images
+    if equalize {
+        img = equalize_histogram(&img, per_channel);
+    }
+
+    // Warm/cool and green/magenta white balance
+    if temperature.is_some() || tint.is_some() {
+        img = apply_white_balance(&img, temperature.unwrap_or(0.0), tint.unwrap_or(0.0));
+    }
+
+    // Per-channel black/white points
+    if let Some(levels) = &levels {
+        img = apply_levels(&img, levels);
     }
 
     // Apply filters
     if let Some(filter_name) = filter {
         match filter_name.as_str() {
+            // image 0.25 maps RGBA to LumaA here, so transparency survives
             "grayscale" => img = img.grayscale(),
             "sepia" => {
                 // Simple sepia effect by converting to grayscale and tinting
@@ -706,11 +2246,18 @@ pub async fn edit_handler(mut multipart: Multipart) -> Result<impl IntoResponse,
                 }
                 img = DynamicImage::ImageRgba8(rgba_img);
             },
-            "blur" => img = img.blur(2.0),
+            "blur" => img = blur_image(&img, 2.0, blur_algo),
             _ => {}
         }
     }
 
+    // Map tones onto a two-color gradient
+    if duotone {
+        let shadow = parse_color(&duotone_shadow)?;
+        let highlight = parse_color(&duotone_highlight)?;
+        img = apply_duotone(&img, shadow, highlight);
+    }
+
     // Apply flip
     if let Some(flip_dir) = flip {
         match flip_dir.as_str() {
@@ -722,6 +2269,9 @@ pub async fn edit_handler(mut multipart: Multipart) -> Result<impl IntoResponse,
 
     // Create thumbnail if requested
     if let Some(thumb_size) = thumbnail {
+        if thumb_size == 0 {
+            return Err(AppError::InvalidFieldValue("Thumbnail size must be at least 1 pixel".to_string()));
+        }
         let (width, height) = img.dimensions();
         let scale = (thumb_size as f32) / width.max(height) as f32;
         if scale < 1.0 {
@@ -731,18 +2281,1242 @@ pub async fn edit_handler(mut multipart: Multipart) -> Result<impl IntoResponse,
         }
     }
 
-    let temp_path = create_temp_file("png");
-    save_image(&img, &temp_path, ImageFormat::Png)?;
-    
-    let result_data = read_file_bytes(&temp_path)?;
-    delete_temp_file(&temp_path);
-
-    Ok((
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, "image/png"),
-            (header::CONTENT_DISPOSITION, "attachment; filename=\"edited.png\""),
-        ],
-        result_data,
-    ))
-}
\ No newline at end of file
+    // Border and drop shadow go last so they match the final size
+    let border = match border.filter(|&width| width > 0) {
+        Some(width) => Some((width, parse_color(&border_color)?)),
+        None => None,
+    };
+    let shadow_color = if shadow { Some(parse_color(&shadow_color)?) } else { None };
+    let finish = |mut img: DynamicImage| -> Result<DynamicImage, AppError> {
+        if let Some((width, color)) = border {
+            img = add_border(&img, width, color)?;
+        }
+        if let Some(color) = shadow_color {
+            img = add_drop_shadow(&img, color, shadow_blur, shadow_offset_x, shadow_offset_y)?;
+        }
+        Ok(img)
+    };
+
+    // The edits above ran once; each variant only resizes the result
+    if let Some(variants) = variants {
+        let mut entries = Vec::new();
+        let mut manifest = serde_json::Map::new();
+        for (stem, variant) in variants {
+            let (width, height) = img.dimensions();
+            let (new_width, new_height) = variant_size(width, height, &variant);
+            let resized = if (new_width, new_height) == (width, height) {
+                img.clone()
+            } else {
+                resize_image_fast(&img, new_width, new_height)?
+            };
+            let output = finish(resized)?;
+
+            let entry_name = format!("{}.png", stem);
+            manifest.insert(variant.name, json!({
+                "width": output.width(),
+                "height": output.height(),
+                "filename": entry_name,
+            }));
+            let encoded = output_image(&output, ImageFormat::Png, &EncodeOptions::default())?.data;
+            entries.push((entry_name, with_metadata(preserve_metadata, &data, encoded, orient)?));
+        }
+
+        let manifest_json = serde_json::to_vec_pretty(&manifest)
+            .map_err(|e| AppError::ImageProcessingError(e.to_string()))?;
+        entries.push(("manifest.json".to_string(), manifest_json));
+
+        let filename = output_filename(source.as_deref(), "variants", "zip");
+        return Ok((file_headers("application/zip", &filename, Disposition::Attachment), write_zip(&entries)?));
+    }
+
+    let img = finish(img)?;
+    let result_data = output_image(&img, ImageFormat::Png, &EncodeOptions::default())?.data;
+    let result_data = with_metadata(preserve_metadata, &data, result_data, orient)?;
+
+    let filename = output_filename(source.as_deref(), "edited", "png");
+    Ok((file_headers("image/png", &filename, disposition), result_data))
+}
+
+// Parse edit's `levels` JSON, rejecting inverted input ranges
+fn parse_levels(json: &str) -> Result<Levels, AppError> {
+    let levels: Levels = serde_json::from_str(json)
+        .map_err(|e| AppError::InvalidFieldValue(format!("Invalid levels: {}", e)))?;
+    for (name, channel) in [("r", &levels.r), ("g", &levels.g), ("b", &levels.b)] {
+        if let Some(ChannelLevels { input: [black, white], .. }) = channel {
+            if black >= white {
+                return Err(AppError::InvalidFieldValue(format!(
+                    "Invalid levels for '{}': input black point {} must be below white point {}",
+                    name, black, white
+                )));
+            }
+        }
+    }
+    Ok(levels)
+}
+
+// Most variants one edit request may produce
+const MAX_VARIANTS: usize = 20;
+
+// Parse edit's `variants` JSON, pairing each with the file stem its ZIP entry
+// will use
+fn parse_variants(json: &str) -> Result<Vec<(String, Variant)>, AppError> {
+    let variants: Vec<Variant> = serde_json::from_str(json)
+        .map_err(|e| AppError::InvalidFieldValue(format!("Invalid variants: {}", e)))?;
+    if variants.is_empty() || variants.len() > MAX_VARIANTS {
+        return Err(AppError::InvalidFieldValue(format!("Expected 1-{} variants", MAX_VARIANTS)));
+    }
+
+    let mut names = std::collections::HashSet::new();
+    let mut stems = std::collections::HashSet::new();
+    variants
+        .into_iter()
+        .map(|variant| {
+            if variant.width == Some(0) || variant.height == Some(0) {
+                return Err(AppError::InvalidFieldValue(format!(
+                    "Variant '{}': dimensions must be at least 1 pixel",
+                    variant.name
+                )));
+            }
+            if !names.insert(variant.name.clone()) {
+                return Err(AppError::InvalidFieldValue(format!("Duplicate variant name '{}'", variant.name)));
+            }
+            let stem = unique_stem(&mut stems, &variant.name);
+            Ok((stem, variant))
+        })
+        .collect()
+}
+
+// Size of a variant of a `width` x `height` image. Variants never enlarge.
+fn variant_size(width: u32, height: u32, variant: &Variant) -> (u32, u32) {
+    let scale = match (variant.width, variant.height) {
+        (Some(w), Some(h)) => (w as f32 / width as f32).min(h as f32 / height as f32),
+        (Some(w), None) => w as f32 / width as f32,
+        (None, Some(h)) => h as f32 / height as f32,
+        (None, None) => 1.0,
+    };
+    if scale >= 1.0 {
+        return (width, height);
+    }
+    (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{encode, post, unzip, with_exif, with_exif_thumbnail, Part};
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn corrupt_jpeg_is_unprocessable() {
+        // A valid SOI/APP0 header, then noise where the frame should be
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00];
+        data.extend((0..400u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8));
+
+        let (status, _, body) = post(compress_handler, &[Part::File("file", "broken.jpg", &data)]).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["code"], "CORRUPT_IMAGE");
+    }
+
+    #[tokio::test]
+    async fn unparseable_or_huge_shadow_settings_are_rejected() {
+        let png = encode(&DynamicImage::new_rgba8(16, 16), ImageFormat::Png);
+        for (name, value) in [("shadow_offset_x", "abc"), ("shadow_offset_y", "99999"), ("shadow_blur", "1e9")] {
+            let (status, _, _) = post(edit_handler, &[
+                Part::File("file", "a.png", &png),
+                Part::Text("shadow", "true"),
+                Part::Text(name, value),
+            ]).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}={}", name, value);
+        }
+    }
+
+    // Height of the box around every pixel the watermark changed
+    fn watermark_height(original: &DynamicImage, marked: &[u8]) -> u32 {
+        let marked = load_image_from_bytes(marked).unwrap().to_rgba8();
+        let original = original.to_rgba8();
+        let rows: Vec<u32> = marked
+            .enumerate_pixels()
+            .filter(|(x, y, pixel)| *pixel != original.get_pixel(*x, *y))
+            .map(|(_, y, _)| y)
+            .collect();
+        rows.iter().max().unwrap() - rows.iter().min().unwrap() + 1
+    }
+
+    #[tokio::test]
+    async fn strip_all_removes_gps_and_bakes_in_orientation() {
+        use exif::{Field, In, Tag, Value};
+
+        // Orientation 6 means the stored 40x20 pixels display as 20x40
+        let jpeg = encode(&DynamicImage::new_rgb8(40, 20), ImageFormat::Jpeg);
+        let geotagged = with_exif(&jpeg, &[
+            Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![6]) },
+            Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"N".to_vec()]) },
+        ]);
+        assert!(has_gps(&read_exif(&geotagged).unwrap()));
+
+        let (status, _, body) = post(strip_all_handler, &[
+            Part::File("file", "trip.jpg", &geotagged),
+            Part::File("file", "trip.jpg", &geotagged),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let entries = unzip(&body);
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["trip.jpg", "trip-2.jpg", "manifest.json"]);
+        for (_, data) in &entries[..2] {
+            assert!(read_exif(data).is_none_or(|exif| !has_gps(&exif)));
+            assert_eq!(load_image_from_bytes(data).unwrap().dimensions(), (20, 40));
+        }
+    }
+
+    #[tokio::test]
+    async fn converting_jpeg_to_jpg_with_metadata_returns_the_upload_untouched() {
+        let jpeg = encode(&DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 90]))), ImageFormat::Jpeg);
+
+        let (status, headers, body) = post(convert_handler, &[
+            Part::File("file", "photo.jpg", &jpeg),
+            Part::Text("format", "jpg"),
+            Part::Text("preserve_metadata", "true"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "image/jpeg");
+        assert_eq!(body, jpeg);
+
+        for (name, value) in [("force_reencode", "true"), ("quality", "40")] {
+            let (_, _, body) = post(convert_handler, &[
+                Part::File("file", "photo.jpg", &jpeg),
+                Part::Text("format", "jpg"),
+                Part::Text("preserve_metadata", "true"),
+                Part::Text(name, value),
+            ]).await;
+            assert_ne!(body, jpeg, "{}={} should re-encode", name, value);
+        }
+    }
+
+    #[tokio::test]
+    async fn converting_jpeg_to_jpg_by_default_drops_the_exif() {
+        use exif::{Field, In, Tag, Value};
+
+        let geotagged = with_exif(&encode(&DynamicImage::new_rgb8(16, 16), ImageFormat::Jpeg), &[
+            Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"N".to_vec()]) },
+        ]);
+        assert!(has_gps(&read_exif(&geotagged).unwrap()));
+
+        let (status, _, body) = post(convert_handler, &[
+            Part::File("file", "trip.jpg", &geotagged),
+            Part::Text("format", "jpg"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(read_exif(&body).is_none());
+    }
+
+    #[tokio::test]
+    async fn exif_edit_copyright_reads_back_through_metadata() {
+        let jpeg = encode(&DynamicImage::new_rgb8(16, 16), ImageFormat::Jpeg);
+        let (status, _, edited) = post(exif_edit_handler, &[
+            Part::File("file", "photo.jpg", &jpeg),
+            Part::Text("copyright", "(c) 2026 Example"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        // Only the EXIF segment changes; the scan data is copied through
+        let scan = jpeg.windows(2).position(|marker| marker == [0xFF, 0xDA]).unwrap();
+        assert!(edited.ends_with(&jpeg[scan..]));
+
+        let (status, _, body) = post(metadata_handler, &[Part::File("file", "photo.jpg", &edited)]).await;
+        assert_eq!(status, StatusCode::OK);
+        let metadata: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(metadata["data"]["Copyright"], "(c) 2026 Example");
+
+        let (status, _, _) = post(exif_edit_handler, &[
+            Part::File("file", "photo.jpg", &jpeg),
+            Part::Text("LensModel", "50mm"),
+        ]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn compare_overlay_alpha_picks_before_or_after() {
+        let solid = |color| encode(&DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb(color))), ImageFormat::Png);
+        let (before, after) = (solid([255, 0, 0]), solid([0, 0, 255]));
+
+        for (alpha, expected) in [("0", [255, 0, 0, 255]), ("1", [0, 0, 255, 255])] {
+            let (status, _, body) = post(compare_visual_handler, &[
+                Part::File("before", "before.png", &before),
+                Part::File("after", "after.png", &after),
+                Part::Text("alpha", alpha),
+            ]).await;
+            assert_eq!(status, StatusCode::OK);
+            let composite = load_image_from_bytes(&body).unwrap().to_rgba8();
+            assert!(composite.pixels().all(|pixel| pixel.0 == expected), "alpha={}", alpha);
+        }
+
+        let (status, _, _) = post(compare_visual_handler, &[
+            Part::File("before", "before.png", &before),
+            Part::File("after", "after.png", &after),
+            Part::Text("alpha", "1.5"),
+        ]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn color_transfer_moves_source_colors_toward_the_reference() {
+        let gradient = |base: [u8; 3]| {
+            DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| {
+                image::Rgb(base.map(|c| c.saturating_add((x + y) as u8 * 2)))
+            }))
+        };
+        let mean = |img: &DynamicImage| {
+            let rgb = img.to_rgb8();
+            let count = rgb.pixels().len() as f64;
+            [0, 1, 2].map(|c| rgb.pixels().map(|p| p[c] as f64).sum::<f64>() / count)
+        };
+        let (source, reference) = (gradient([40, 60, 200]), gradient([200, 120, 30]));
+
+        let (status, _, body) = post(color_transfer_handler, &[
+            Part::File("source", "sky.png", &encode(&source, ImageFormat::Png)),
+            Part::File("reference", "sunset.png", &encode(&reference, ImageFormat::Png)),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (before, after, target) = (mean(&source), mean(&load_image_from_bytes(&body).unwrap()), mean(&reference));
+        for c in 0..3 {
+            assert!((after[c] - target[c]).abs() < (before[c] - target[c]).abs() / 2.0, "channel {}: {:?} -> {:?}, target {:?}", c, before, after, target);
+        }
+    }
+
+    #[tokio::test]
+    async fn levels_input_range_stretches_only_its_channel() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(4, 1, |x, _| image::Rgb([x as u8 * 32, 100, 50])));
+        let (status, _, body) = post(edit_handler, &[
+            Part::File("file", "a.png", &encode(&img, ImageFormat::Png)),
+            Part::Text("levels", r#"{"r":{"in":[0,128]}}"#),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let leveled = load_image_from_bytes(&body).unwrap().to_rgb8();
+        // 0..=128 maps onto 0..=255, so red roughly doubles
+        for (x, pixel) in leveled.pixels().enumerate() {
+            assert!(pixel[0].abs_diff(x as u8 * 64) <= 1, "red {} at x={}", pixel[0], x);
+            assert_eq!([pixel[1], pixel[2]], [100, 50]);
+        }
+
+        let (status, _, _) = post(edit_handler, &[
+            Part::File("file", "a.png", &encode(&img, ImageFormat::Png)),
+            Part::Text("levels", r#"{"r":{"in":[128,0]}}"#),
+        ]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn detect_format_names_avif_that_cannot_be_decoded() {
+        // An ftyp box with the avif major brand, then the start of a meta box
+        let mut avif = vec![0, 0, 0, 0x1C];
+        avif.extend_from_slice(b"ftypavif\0\0\0\0avifmif1miaf");
+        avif.extend_from_slice(&[0, 0, 0, 0x20]);
+        avif.extend_from_slice(b"meta");
+        avif.resize(64, 0);
+
+        let (status, _, body) = post(detect_format_handler, &[Part::File("file", "photo.avif", &avif)]).await;
+        assert_eq!(status, StatusCode::OK);
+        let detection: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(detection["mime"], "image/avif");
+        assert_eq!(detection["extension"], "avif");
+        assert_eq!(detection["decodable"], false);
+    }
+
+    #[cfg(not(feature = "faces"))]
+    #[tokio::test]
+    async fn face_crop_without_a_detector_falls_back_to_the_center() {
+        let png = encode(&DynamicImage::new_rgb8(60, 40), ImageFormat::Png);
+        let (status, headers, body) = post(face_crop_handler, &[Part::File("file", "group.png", &png)]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["X-Face-Crop-Fallback"], "center");
+        assert_eq!(headers["X-Face-Detection"], "unavailable");
+        assert_eq!(headers["X-Crop-Region"], "10,0,40,40");
+        assert_eq!(load_image_from_bytes(&body).unwrap().dimensions(), (40, 40));
+    }
+
+    #[tokio::test]
+    async fn contact_sheet_lays_four_uploads_out_in_a_grid() {
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 0]];
+        let uploads: Vec<Vec<u8>> = colors
+            .iter()
+            .map(|&color| encode(&DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb(color))), ImageFormat::Png))
+            .collect();
+        let mut parts: Vec<Part> = uploads.iter().map(|data| Part::File("file", "tile.png", data)).collect();
+        parts.extend([
+            Part::Text("columns", "2"),
+            Part::Text("thumb_size", "20"),
+            Part::Text("gap", "4"),
+            Part::Text("bg_color", "black"),
+        ]);
+
+        let (status, _, body) = post(contact_sheet_handler, &parts).await;
+        assert_eq!(status, StatusCode::OK);
+        let sheet = load_image_from_bytes(&body).unwrap().to_rgb8();
+        // Two 20px cells plus three 4px gaps each way
+        assert_eq!(sheet.dimensions(), (52, 52));
+        for (index, color) in colors.iter().enumerate() {
+            let (column, row) = (index as u32 % 2, index as u32 / 2);
+            let center = sheet.get_pixel(4 + column * 24 + 10, 4 + row * 24 + 10);
+            assert_eq!(center.0, *color, "cell {}", index);
+        }
+        assert_eq!(sheet.get_pixel(0, 0).0, [0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn contact_sheet_rejects_unparseable_numbers() {
+        let png = encode(&DynamicImage::new_rgb8(8, 8), ImageFormat::Png);
+        for name in ["columns", "thumb_size", "gap"] {
+            let (status, _, _) = post(contact_sheet_handler, &[
+                Part::File("file", "a.png", &png),
+                Part::Text(name, "many"),
+            ]).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn sprite_sheet_atlas_places_sprites_without_overlap() {
+        let sizes = [(30, 10), (12, 12), (8, 20), (16, 6)];
+        let uploads: Vec<Vec<u8>> = sizes
+            .iter()
+            .map(|&(width, height)| encode(&DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(width, height, image::Rgba([9, 9, 9, 255]))), ImageFormat::Png))
+            .collect();
+        let mut parts: Vec<Part> = uploads.iter().map(|data| Part::File("file", "icon.png", data)).collect();
+        parts.push(Part::Text("padding", "2"));
+
+        let (status, _, body) = post(sprite_sheet_handler, &parts).await;
+        assert_eq!(status, StatusCode::OK);
+        let entries = unzip(&body);
+        let sheet = load_image_from_bytes(&entries[0].1).unwrap();
+        let atlas: serde_json::Value = serde_json::from_slice(&entries[1].1).unwrap();
+        let sprites = atlas["sprites"].as_array().unwrap();
+        let rects: Vec<[u64; 4]> = sprites
+            .iter()
+            .map(|s| ["x", "y", "width", "height"].map(|key| s[key].as_u64().unwrap()))
+            .collect();
+
+        let names: Vec<&str> = sprites.iter().map(|s| s["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["icon", "icon-2", "icon-3", "icon-4"]);
+        for (i, [x, y, w, h]) in rects.iter().enumerate() {
+            assert_eq!((*w as u32, *h as u32), sizes[i]);
+            assert!(x + w <= sheet.width() as u64 && y + h <= sheet.height() as u64);
+            for [ox, oy, ow, oh] in &rects[i + 1..] {
+                let apart = x + w + 2 <= *ox || ox + ow + 2 <= *x || y + h + 2 <= *oy || oy + oh + 2 <= *y;
+                assert!(apart, "sprites {:?} and {:?} are closer than the padding", rects[i], [ox, oy, ow, oh]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn watermark_size_pct_scales_with_the_image() {
+        let mut heights = Vec::new();
+        for (width, height) in [(240, 120), (480, 240)] {
+            let img = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(width, height, image::Rgba([0, 0, 0, 255])));
+            let (status, _, body) = post(watermark_handler, &[
+                Part::File("file", "dark.png", &encode(&img, ImageFormat::Png)),
+                Part::Text("text", "Hg"),
+                Part::Text("size_pct", "25"),
+                Part::Text("opacity", "1"),
+                Part::Text("position", "top-left"),
+            ]).await;
+            assert_eq!(status, StatusCode::OK);
+            heights.push(watermark_height(&img, &body));
+        }
+
+        let ratio = heights[1] as f32 / heights[0] as f32;
+        assert!((1.8..=2.2).contains(&ratio), "text heights {:?}", heights);
+    }
+
+    #[tokio::test]
+    async fn watermark_size_must_parse_and_fit_the_image() {
+        let png = encode(&DynamicImage::new_rgba8(64, 32), ImageFormat::Png);
+        for size in ["abc", "100000", "33"] {
+            let (status, _, _) = post(watermark_handler, &[
+                Part::File("file", "a.png", &png),
+                Part::Text("text", "hi"),
+                Part::Text("size", size),
+            ]).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "size={}", size);
+        }
+    }
+
+    #[tokio::test]
+    async fn inline_disposition_keeps_the_filename() {
+        let png = encode(&DynamicImage::new_rgb8(20, 10), ImageFormat::Png);
+        let (status, headers, _) = post(resize_handler, &[
+            Part::File("file", "beach.png", &png),
+            Part::Text("width", "10"),
+            Part::Text("disposition", "inline"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        let disposition = headers["content-disposition"].to_str().unwrap();
+        assert!(disposition.starts_with("inline; filename=\""), "{}", disposition);
+        assert!(disposition.contains("beach"), "{}", disposition);
+    }
+
+    #[tokio::test]
+    async fn locked_aspect_resize_fits_inside_the_box() {
+        let png = encode(&DynamicImage::new_rgb8(200, 100), ImageFormat::Png);
+        let (status, _, body) = post(resize_handler, &[
+            Part::File("file", "wide.png", &png),
+            Part::Text("width", "100"),
+            Part::Text("height", "100"),
+            Part::Text("lock_aspect", "true"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(load_image_from_bytes(&body).unwrap().dimensions(), (100, 50));
+    }
+
+    #[tokio::test]
+    async fn tiny_percentage_resize_keeps_at_least_one_pixel() {
+        let png = encode(&DynamicImage::new_rgb8(3, 2), ImageFormat::Png);
+        let (status, _, body) = post(resize_handler, &[
+            Part::File("file", "tiny.png", &png),
+            Part::Text("percentage", "0.1"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(load_image_from_bytes(&body).unwrap().dimensions(), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn crop_preview_returns_the_centered_box_as_json() {
+        let png = encode(&DynamicImage::new_rgb8(200, 100), ImageFormat::Png);
+        let (status, headers, body) = post(crop_handler, &[
+            Part::File("file", "wide.png", &png),
+            Part::Text("ratio", "1:1"),
+            Part::Text("preview", "true"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "application/json");
+        let bounds: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(bounds, serde_json::json!({"x": 50, "y": 0, "width": 100, "height": 100}));
+    }
+
+    #[tokio::test]
+    async fn compare_formats_reports_a_size_per_requested_format() {
+        let png = encode(&DynamicImage::new_rgb8(24, 24), ImageFormat::Png);
+        let (status, _, body) = post(compare_formats_handler, &[
+            Part::File("file", "a.png", &png),
+            Part::Text("formats", "jpg, png,webp,avif"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let sizes = response["formats"].as_array().unwrap();
+        let names: Vec<&str> = sizes.iter().map(|entry| entry["format"].as_str().unwrap()).collect();
+        assert_eq!(names, ["jpg", "png", "webp", "avif"]);
+        assert!(sizes.iter().all(|entry| entry["size"].as_u64().unwrap() > 0));
+    }
+
+    #[tokio::test]
+    async fn converting_transparent_png_to_jpeg_reports_the_flattening() {
+        let transparent = encode(&DynamicImage::new_rgba8(8, 8), ImageFormat::Png);
+        let (status, headers, _) = post(convert_handler, &[
+            Part::File("file", "logo.png", &transparent),
+            Part::Text("format", "jpeg"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["X-Alpha-Flattened"], "true");
+        assert_eq!(headers["X-Alpha-Background"], "#ffffff");
+
+        let opaque = encode(&DynamicImage::new_rgb8(8, 8), ImageFormat::Png);
+        let (_, headers, _) = post(convert_handler, &[
+            Part::File("file", "photo.png", &opaque),
+            Part::Text("format", "jpeg"),
+        ]).await;
+        assert!(!headers.contains_key("X-Alpha-Flattened"));
+    }
+
+    #[tokio::test]
+    async fn fit_to_bytes_shrinks_a_photo_under_the_budget() {
+        // Noise compresses about as badly as a detailed photo
+        let mut seed = 12345u32;
+        let photo = DynamicImage::ImageRgb8(image::RgbImage::from_fn(400, 300, |_, _| {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            let [r, g, b, _] = seed.to_le_bytes();
+            image::Rgb([r, g, b])
+        }));
+        let jpeg = encode(&photo, ImageFormat::Jpeg);
+        assert!(jpeg.len() > 60_000, "{} bytes", jpeg.len());
+
+        let (status, headers, body) = post(fit_to_bytes_handler, &[
+            Part::File("file", "photo.jpg", &jpeg),
+            Part::Text("max_bytes", "20000"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.len() <= 20_000, "{} bytes", body.len());
+
+        let fitted = load_image_from_bytes(&body).unwrap();
+        assert!(fitted.width() < 400 && fitted.height() < 300);
+        assert_eq!(headers["X-Output-Width"], fitted.width().to_string().as_str());
+
+        for (name, value) in [("max_bytes", "10k"), ("max_bytes", "0"), ("min_quality", "abc")] {
+            let mut parts = vec![Part::File("file", "photo.jpg", &jpeg), Part::Text(name, value)];
+            if name != "max_bytes" {
+                parts.push(Part::Text("max_bytes", "20000"));
+            }
+            let (status, _, body) = post(fit_to_bytes_handler, &parts).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}={}", name, value);
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["code"], "INVALID_FIELD_VALUE", "{}={}", name, value);
+        }
+    }
+
+    #[tokio::test]
+    async fn convert_color_modes_reduce_the_channels() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 8, |x, _| image::Rgb([x as u8 * 8, 120, 255 - x as u8 * 8])));
+        let png = encode(&img, ImageFormat::Png);
+        let convert = |mode| {
+            let png = png.clone();
+            async move {
+                let (status, _, body) = post(convert_handler, &[
+                    Part::File("file", "a.png", &png),
+                    Part::Text("format", "png"),
+                    Part::Text("color_mode", mode),
+                ]).await;
+                assert_eq!(status, StatusCode::OK);
+                load_image_from_bytes(&body).unwrap()
+            }
+        };
+
+        assert_eq!(convert("grayscale").await.color(), image::ColorType::L8);
+        let bilevel = convert("bilevel").await.to_luma8();
+        assert!(bilevel.pixels().all(|p| p[0] == 0 || p[0] == 255));
+        assert!(bilevel.pixels().any(|p| p[0] == 0) && bilevel.pixels().any(|p| p[0] == 255));
+    }
+
+    #[tokio::test]
+    async fn compress_without_quality_uses_the_configured_default() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(16, 16, image::Rgb([90, 140, 200])));
+        let jpeg = encode(&img, ImageFormat::Jpeg);
+        let (status, headers, _) = post(compress_handler, &[Part::File("file", "a.jpg", &jpeg)]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["X-Output-Quality"], crate::types::default_quality().to_string().as_str());
+    }
+
+    #[tokio::test]
+    async fn info_dimensions_come_from_the_header_alone() {
+        let mut png = encode(&DynamicImage::ImageLuma8(image::GrayImage::new(3000, 2000)), ImageFormat::Png);
+        // Corrupt the pixel data but leave IHDR intact
+        let idat = png.windows(4).position(|w| w == b"IDAT").unwrap();
+        png[idat + 4..idat + 20].fill(0xFF);
+
+        let (status, _, body) = post(info_handler, &[Part::File("file", "big.png", &png), Part::Text("fields", "dimensions")]).await;
+        assert_eq!(status, StatusCode::OK);
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!((info["width"].as_u64(), info["height"].as_u64()), (Some(3000), Some(2000)));
+
+        let (status, _, _) = post(info_handler, &[Part::File("file", "big.png", &png)]).await;
+        assert!(!status.is_success());
+    }
+
+    #[tokio::test]
+    async fn resize_output_is_named_after_the_upload() {
+        let png = encode(&DynamicImage::new_rgb8(20, 10), ImageFormat::Png);
+        let (status, headers, _) = post(resize_handler, &[Part::File("file", "beach.png", &png), Part::Text("width", "10")]).await;
+        assert_eq!(status, StatusCode::OK);
+        let disposition = headers["Content-Disposition"].to_str().unwrap();
+        assert!(disposition.contains("beach") && disposition.contains(".png"), "{}", disposition);
+    }
+
+    #[tokio::test]
+    async fn explicit_resize_filter_is_reported() {
+        let png = encode(&DynamicImage::new_rgb8(20, 10), ImageFormat::Png);
+        let (status, headers, _) = post(resize_handler, &[
+            Part::File("file", "a.png", &png),
+            Part::Text("width", "40"),
+            Part::Text("filter", "nearest"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["X-Resize-Filter"], "nearest");
+    }
+
+    #[tokio::test]
+    async fn resize_turns_an_orientation_6_photo_upright() {
+        use exif::{Field, In, Tag, Value};
+
+        // Stored sideways: red on the left, which displays at the top
+        let sideways = DynamicImage::ImageRgb8(image::RgbImage::from_fn(40, 20, |x, _| {
+            if x < 20 { image::Rgb([220, 0, 0]) } else { image::Rgb([0, 0, 220]) }
+        }));
+        let jpeg = with_exif(&encode(&sideways, ImageFormat::Jpeg), &[
+            Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![6]) },
+        ]);
+
+        let (status, _, body) = post(resize_handler, &[Part::File("file", "portrait.jpg", &jpeg), Part::Text("width", "10")]).await;
+        assert_eq!(status, StatusCode::OK);
+        let upright = load_image_from_bytes(&body).unwrap().to_rgb8();
+        assert_eq!(upright.dimensions(), (10, 20));
+        assert!(upright.get_pixel(5, 2)[0] > 150 && upright.get_pixel(5, 17)[2] > 150);
+    }
+
+    #[tokio::test]
+    async fn split_2x2_yields_four_equal_tiles() {
+        let png = encode(&DynamicImage::new_rgb8(100, 100), ImageFormat::Png);
+        let (status, _, body) = post(split_handler, &[
+            Part::File("file", "grid.png", &png),
+            Part::Text("rows", "2"),
+            Part::Text("cols", "2"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let tiles = unzip(&body);
+        assert_eq!(tiles.len(), 4);
+        for (name, data) in tiles {
+            assert_eq!(load_image_from_bytes(&data).unwrap().dimensions(), (50, 50), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn avatar_is_a_circle_in_a_transparent_square() {
+        let png = encode(&DynamicImage::ImageRgb8(image::RgbImage::from_pixel(120, 80, image::Rgb([30, 160, 90]))), ImageFormat::Png);
+        let (status, _, body) = post(avatar_handler, &[Part::File("file", "me.png", &png), Part::Text("size", "64")]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let avatar = load_image_from_bytes(&body).unwrap().to_rgba8();
+        assert_eq!(avatar.dimensions(), (64, 64));
+        for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63)] {
+            assert_eq!(avatar.get_pixel(x, y)[3], 0);
+        }
+        assert_eq!(avatar.get_pixel(32, 32)[3], 255);
+    }
+
+    #[tokio::test]
+    async fn nearest_upscale_of_pixel_art_gives_crisp_blocks() {
+        let colors = [image::Rgba([255, 0, 0, 255]), image::Rgba([0, 255, 0, 255]), image::Rgba([0, 0, 255, 255]), image::Rgba([255, 255, 0, 255])];
+        let sprite = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(2, 2, |x, y| colors[(y * 2 + x) as usize]));
+        let png = encode(&sprite, ImageFormat::Png);
+
+        let (status, _, body) = post(upscale_handler, &[
+            Part::File("file", "sprite.png", &png),
+            Part::Text("scale", "4"),
+            Part::Text("filter", "nearest"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let upscaled = load_image_from_bytes(&body).unwrap().to_rgba8();
+        assert_eq!(upscaled.dimensions(), (8, 8));
+        for (x, y, pixel) in upscaled.enumerate_pixels() {
+            assert_eq!(*pixel, colors[(y / 4 * 2 + x / 4) as usize], "at {},{}", x, y);
+        }
+    }
+
+    #[tokio::test]
+    async fn infinite_brightness_is_rejected() {
+        let png = encode(&DynamicImage::new_rgb8(8, 8), ImageFormat::Png);
+        for value in ["inf", "-inf", "NaN"] {
+            let (status, _, body) = post(edit_handler, &[Part::File("file", "a.png", &png), Part::Text("brightness", value)]).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "brightness={}", value);
+            assert!(String::from_utf8_lossy(&body).contains("brightness"));
+        }
+    }
+
+    #[tokio::test]
+    async fn alpha_trim_crops_to_the_opaque_shape() {
+        let mut canvas = image::RgbaImage::new(200, 150);
+        for y in 40..60 {
+            for x in 70..100 {
+                canvas.put_pixel(x, y, image::Rgba([200, 50, 50, 255]));
+            }
+        }
+        let png = encode(&DynamicImage::ImageRgba8(canvas), ImageFormat::Png);
+
+        let (status, _, body) = post(trim_handler, &[Part::File("file", "sticker.png", &png), Part::Text("alpha", "true")]).await;
+        assert_eq!(status, StatusCode::OK);
+        let trimmed = load_image_from_bytes(&body).unwrap().to_rgba8();
+        assert_eq!(trimmed.dimensions(), (30, 20));
+        assert!(trimmed.pixels().all(|p| p[3] == 255));
+    }
+
+    #[cfg(not(feature = "colorize"))]
+    #[tokio::test]
+    async fn colorize_without_the_feature_is_not_implemented() {
+        let png = encode(&DynamicImage::new_luma8(8, 8), ImageFormat::Png);
+        let (status, _, _) = post(colorize_handler, &[Part::File("file", "old.png", &png)]).await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[tokio::test]
+    async fn info_batch_streams_one_line_per_file() {
+        let png = encode(&DynamicImage::new_rgb8(12, 8), ImageFormat::Png);
+        let jpeg = encode(&DynamicImage::new_rgb8(6, 4), ImageFormat::Jpeg);
+        let (status, headers, body) = post(info_batch_handler, &[
+            Part::File("file", "a.png", &png),
+            Part::File("file", "b.jpg", &jpeg),
+            Part::File("file", "broken.png", b"not an image"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["Content-Type"], "application/x-ndjson");
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(body).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["width"], 12);
+        assert_eq!(lines[1]["width"], 6);
+        assert_eq!(lines[2]["file"], "broken.png");
+        assert!(lines[2]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn extreme_ratio_crop_keeps_at_least_one_pixel() {
+        let png = encode(&DynamicImage::new_rgb8(50, 50), ImageFormat::Png);
+        let (status, headers, body) = post(crop_handler, &[Part::File("file", "a.png", &png), Part::Text("ratio", "100:1")]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let cropped = load_image_from_bytes(&body).unwrap();
+        assert_eq!(cropped.dimensions(), (50, 1));
+        assert_eq!(headers["X-Crop-Applied"], "0,24,50,1");
+    }
+
+    #[tokio::test]
+    async fn info_counts_the_colors_of_a_flat_image() {
+        let colors = [image::Rgb([255, 0, 0]), image::Rgb([0, 255, 0]), image::Rgb([0, 0, 255])];
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(30, 10, |x, _| colors[(x / 10) as usize]));
+        let png = encode(&img, ImageFormat::Png);
+
+        let (status, _, body) = post(info_handler, &[Part::File("file", "flags.png", &png), Part::Text("count_colors", "true")]).await;
+        assert_eq!(status, StatusCode::OK);
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["unique_colors"], 3);
+    }
+
+    #[tokio::test]
+    async fn edit_variants_come_back_at_their_sizes() {
+        let png = encode(&DynamicImage::new_rgb8(400, 200), ImageFormat::Png);
+        let (status, _, body) = post(edit_handler, &[
+            Part::File("file", "hero.png", &png),
+            Part::Text("filter", "grayscale"),
+            Part::Text("variants", r#"[{"name":"thumb","width":100},{"name":"full","width":300}]"#),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let entries = unzip(&body);
+        let (_, manifest) = entries.iter().find(|(name, _)| name == "manifest.json").unwrap();
+        let manifest: serde_json::Value = serde_json::from_slice(manifest).unwrap();
+        for (variant, size) in [("thumb", (100, 50)), ("full", (300, 150))] {
+            let filename = manifest[variant]["filename"].as_str().unwrap();
+            assert_eq!((manifest[variant]["width"].as_u64(), manifest[variant]["height"].as_u64()), (Some(size.0 as u64), Some(size.1 as u64)));
+            let (_, data) = entries.iter().find(|(name, _)| name == filename).unwrap();
+            assert_eq!(load_image_from_bytes(data).unwrap().dimensions(), size);
+        }
+    }
+
+    #[tokio::test]
+    async fn nan_watermark_opacity_is_rejected() {
+        let png = encode(&DynamicImage::new_rgb8(64, 64), ImageFormat::Png);
+        let (status, _, body) = post(watermark_handler, &[
+            Part::File("file", "a.png", &png),
+            Part::Text("text", "draft"),
+            Part::Text("opacity", "NaN"),
+        ]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(String::from_utf8_lossy(&body).contains("opacity"));
+    }
+
+    #[tokio::test]
+    async fn auto_cropped_rotation_has_no_transparent_corners() {
+        let png = encode(&DynamicImage::ImageRgb8(image::RgbImage::from_pixel(120, 80, image::Rgb([40, 90, 160]))), ImageFormat::Png);
+        let (status, _, body) = post(rotate_handler, &[
+            Part::File("file", "a.png", &png),
+            Part::Text("degrees", "30"),
+            Part::Text("auto_crop", "true"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let rotated = load_image_from_bytes(&body).unwrap().to_rgba8();
+        let (width, height) = rotated.dimensions();
+        assert!(width < 120 && height < 80);
+        for (x, y) in [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1)] {
+            assert_eq!(rotated.get_pixel(x, y)[3], 255, "corner {},{}", x, y);
+        }
+    }
+
+    #[tokio::test]
+    async fn translucent_border_color_keeps_its_alpha() {
+        let png = encode(&DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 20, image::Rgb([250, 250, 250]))), ImageFormat::Png);
+        let (status, _, body) = post(edit_handler, &[
+            Part::File("file", "a.png", &png),
+            Part::Text("border", "5"),
+            Part::Text("border_color", "#00000080"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let framed = load_image_from_bytes(&body).unwrap().to_rgba8();
+        assert_eq!(framed.dimensions(), (30, 30));
+        assert!(framed.get_pixel(1, 1)[3].abs_diff(128) <= 1);
+        assert_eq!(framed.get_pixel(15, 15)[3], 255);
+    }
+
+    #[tokio::test]
+    async fn raw_returns_tightly_packed_rgba() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(7, 5, |x, y| image::Rgb([x as u8 * 30, y as u8 * 40, 9])));
+        let png = encode(&img, ImageFormat::Png);
+        let (status, headers, body) = post(raw_handler, &[Part::File("file", "a.png", &png)]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(headers["Content-Type"], "application/octet-stream");
+        assert_eq!(headers["X-Width"], "7");
+        assert_eq!(headers["X-Height"], "5");
+        assert_eq!(headers["X-Channels"], "4");
+        assert_eq!(body.len(), 7 * 5 * 4);
+        assert_eq!(body, img.to_rgba8().into_raw());
+    }
+
+    #[tokio::test]
+    async fn scale_to_print_sizes_4x6_inches_at_300_dpi() {
+        let jpeg = encode(&DynamicImage::new_rgb8(400, 600), ImageFormat::Jpeg);
+        let print = |format| {
+            let jpeg = jpeg.clone();
+            async move {
+                let (status, _, body) = post(scale_to_print_handler, &[
+                    Part::File("file", "photo.jpg", &jpeg),
+                    Part::Text("width_in", "4"),
+                    Part::Text("height_in", "6"),
+                    Part::Text("dpi", "300"),
+                    Part::Text("format", format),
+                ]).await;
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(load_image_from_bytes(&body).unwrap().dimensions(), (1200, 1800));
+                body
+            }
+        };
+
+        // JFIF: units 1 (dots per inch), then the X and Y density
+        let jpeg = print("jpg").await;
+        let jfif = jpeg.windows(5).position(|w| w == b"JFIF\0").unwrap() + 7;
+        assert_eq!(jpeg[jfif..jfif + 5], [1, 0x01, 0x2C, 0x01, 0x2C]);
+
+        // pHYs: pixels per meter on both axes, unit 1 (meter)
+        let png = print("png").await;
+        let phys = png.windows(4).position(|w| w == b"pHYs").unwrap() + 4;
+        assert_eq!(png[phys..phys + 4], 11_811u32.to_be_bytes());
+        assert_eq!(png[phys + 4..phys + 8], 11_811u32.to_be_bytes());
+        assert_eq!(png[phys + 8], 1);
+    }
+
+    #[tokio::test]
+    async fn cropping_an_animated_gif_is_refused() {
+        let frames: Vec<_> = [[255, 0, 0, 255], [0, 0, 255, 255]]
+            .into_iter()
+            .map(|color| (image::RgbaImage::from_pixel(20, 20, image::Rgba(color)), 100))
+            .collect();
+        let gif = encode_animation(&frames, ImageFormat::Gif).unwrap();
+
+        let (status, _, body) = post(crop_handler, &[Part::File("file", "spin.gif", &gif), Part::Text("width", "10")]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(String::from_utf8_lossy(&body).contains("animated"));
+    }
+
+    #[tokio::test]
+    async fn oversized_upscale_is_rejected_before_allocating() {
+        let png = encode(&DynamicImage::new_luma8(2100, 1400), ImageFormat::Png);
+        let (status, _, body) = post(upscale_handler, &[Part::File("file", "big.png", &png), Part::Text("scale", "8")]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["code"], "DIMENSIONS_TOO_LARGE");
+        assert!(error["error"].as_str().unwrap().contains("largest allowed scale is 5x"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn favicon_holds_every_requested_size() {
+        let png = encode(&DynamicImage::new_rgba8(300, 300), ImageFormat::Png);
+        let (status, _, ico) = post(favicon_handler, &[Part::File("file", "logo.png", &png), Part::Text("sizes", "16,32,48,256")]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // ICONDIR: reserved, type 1, entry count; then 16-byte entries whose
+        // first two bytes are the width and height (0 meaning 256)
+        assert_eq!(ico[..4], [0, 0, 1, 0]);
+        let count = u16::from_le_bytes([ico[4], ico[5]]) as usize;
+        let mut sizes: Vec<(u32, u32)> = (0..count)
+            .map(|i| {
+                let entry = &ico[6 + i * 16..];
+                let size = |byte: u8| if byte == 0 { 256 } else { byte as u32 };
+                (size(entry[0]), size(entry[1]))
+            })
+            .collect();
+        sizes.sort();
+        assert_eq!(sizes, [(16, 16), (32, 32), (48, 48), (256, 256)]);
+        assert_eq!(load_image_from_bytes(&ico).unwrap().dimensions(), (256, 256));
+    }
+
+    #[tokio::test]
+    async fn stronger_sharpening_raises_edge_contrast() {
+        let edge = DynamicImage::ImageLuma8(image::GrayImage::from_fn(32, 8, |x, _| image::Luma([if x < 16 { 60 } else { 180 }])));
+        let png = encode(&edge, ImageFormat::Png);
+        let upscale_contrast = |amount: Option<&'static str>| {
+            let png = png.clone();
+            async move {
+                let mut parts = vec![Part::File("file", "edge.png", &png), Part::Text("scale", "2")];
+                parts.extend(amount.map(|amount| Part::Text("sharpen_amount", amount)));
+                let (status, _, body) = post(upscale_handler, &parts).await;
+                assert_eq!(status, StatusCode::OK);
+                let row: Vec<u8> = load_image_from_bytes(&body).unwrap().to_luma8().rows().nth(8).unwrap().map(|p| p[0]).collect();
+                row.iter().max().unwrap() - row.iter().min().unwrap()
+            }
+        };
+
+        let (default, strong) = (upscale_contrast(None).await, upscale_contrast(Some("3.0")).await);
+        assert!(strong > default, "strong {} vs default {}", strong, default);
+    }
+
+    #[tokio::test]
+    async fn grayscale_jpeg_has_a_single_component() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])));
+        let png = encode(&img, ImageFormat::Png);
+        let to_jpeg = |mode| {
+            let png = png.clone();
+            async move {
+                let (status, _, body) = post(convert_handler, &[
+                    Part::File("file", "a.png", &png),
+                    Part::Text("format", "jpg"),
+                    Part::Text("color_mode", mode),
+                ]).await;
+                assert_eq!(status, StatusCode::OK);
+                body
+            }
+        };
+        // The component count follows the SOF marker's length, precision,
+        // height and width
+        let components = |jpeg: &[u8]| {
+            let sof = jpeg.windows(2).position(|w| w[0] == 0xFF && matches!(w[1], 0xC0 | 0xC2)).unwrap();
+            jpeg[sof + 9]
+        };
+
+        let (gray, rgb) = (to_jpeg("grayscale").await, to_jpeg("rgb").await);
+        assert_eq!(components(&gray), 1);
+        assert_eq!(components(&rgb), 3);
+        assert!(gray.len() < rgb.len(), "gray {} vs rgb {}", gray.len(), rgb.len());
+    }
+
+    #[tokio::test]
+    async fn png_text_written_by_metadata_reads_back() {
+        let png = encode(&DynamicImage::new_rgb8(8, 8), ImageFormat::Png);
+        let (status, _, tagged) = post(metadata_handler, &[Part::File("file", "a.png", &png), Part::Text("set_text", "Author=Jane")]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _, body) = post(metadata_handler, &[Part::File("file", "a.png", &tagged)]).await;
+        assert_eq!(status, StatusCode::OK);
+        let metadata: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(metadata["png_text"]["Author"], "Jane");
+        assert_eq!(load_image_from_bytes(&tagged).unwrap().to_rgb8(), DynamicImage::new_rgb8(8, 8).to_rgb8());
+    }
+
+    #[tokio::test]
+    async fn one_pixel_images_survive_blur_sharpen_and_crop() {
+        let pixel = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb([12, 200, 99])));
+        for algorithm in [BlurAlgorithm::Gaussian, BlurAlgorithm::Box] {
+            assert_eq!(blur_image(&pixel, 50.0, algorithm).to_rgb8(), pixel.to_rgb8());
+        }
+        assert_eq!(unsharpen_image(&pixel, 5.0, 1).to_rgb8(), pixel.to_rgb8());
+
+        let png = encode(&pixel, ImageFormat::Png);
+        let crop = |x: &'static str, width: &'static str| {
+            let png = png.clone();
+            async move {
+                post(crop_handler, &[
+                    Part::File("file", "dot.png", &png),
+                    Part::Text("x", x),
+                    Part::Text("y", "0"),
+                    Part::Text("width", width),
+                    Part::Text("height", "1"),
+                ]).await
+            }
+        };
+        let (status, _, body) = crop("0", "1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(load_image_from_bytes(&body).unwrap().to_rgb8(), pixel.to_rgb8());
+        assert_eq!(crop("5", "1").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(crop("0", "0").await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn two_segment_mirror_halves_reflect_each_other() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(60, 60, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x * y) % 256) as u8])));
+        let png = encode(&img, ImageFormat::Png);
+        let (status, _, body) = post(mirror_tile_handler, &[
+            Part::File("file", "a.png", &png),
+            Part::Text("segments", "2"),
+            Part::Text("size", "40"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let pattern = load_image_from_bytes(&body).unwrap();
+        assert_eq!(pattern.dimensions(), (40, 40));
+        let left = pattern.crop_imm(0, 0, 20, 40).to_rgba8();
+        let right = pattern.crop_imm(20, 0, 20, 40).fliph().to_rgba8();
+        assert_eq!(left, right);
+    }
+
+    #[tokio::test]
+    async fn info_tells_lossless_webp_from_lossy() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([x as u8 * 16, y as u8 * 16, 0])));
+        for lossless in [true, false] {
+            let options = EncodeOptions { lossless, ..EncodeOptions::default() };
+            let webp = encode_image_to_bytes(&img, ImageFormat::Webp, &options).unwrap().data;
+            let (status, _, body) = post(info_handler, &[Part::File("file", "a.webp", &webp)]).await;
+            assert_eq!(status, StatusCode::OK);
+            let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(info["is_lossless"], lossless);
+        }
+    }
+
+    #[tokio::test]
+    async fn embedded_thumbnail_is_returned_as_is() {
+        use exif::{Field, In, Tag, Value};
+
+        let thumbnail = encode(&DynamicImage::new_rgb8(16, 12), ImageFormat::Jpeg);
+        let jpeg = with_exif_thumbnail(
+            &encode(&DynamicImage::new_rgb8(400, 300), ImageFormat::Jpeg),
+            &[Field { tag: Tag::Make, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Camera".to_vec()]) }],
+            &thumbnail,
+        );
+
+        let (status, headers, body) = post(embedded_thumb_handler, &[Part::File("file", "photo.jpg", &jpeg)]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["X-Thumbnail-Source"], "embedded");
+        assert_eq!(body, thumbnail);
+
+        // Without one, only fallback=true makes a thumbnail
+        let plain = encode(&DynamicImage::new_rgb8(400, 300), ImageFormat::Jpeg);
+        let (status, _, _) = post(embedded_thumb_handler, &[Part::File("file", "photo.jpg", &plain)]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, headers, _) = post(embedded_thumb_handler, &[Part::File("file", "photo.jpg", &plain), Part::Text("fallback", "true")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["X-Thumbnail-Source"], "generated");
+    }
+
+    #[tokio::test]
+    async fn grayscale_edit_keeps_transparency() {
+        let img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(20, 10, |x, _| {
+            image::Rgba([200, 40, 40, if x < 10 { 255 } else { 0 }])
+        }));
+        let png = encode(&img, ImageFormat::Png);
+        let (status, _, body) = post(edit_handler, &[Part::File("file", "a.png", &png), Part::Text("filter", "grayscale")]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let gray = load_image_from_bytes(&body).unwrap().to_rgba8();
+        for (x, _, pixel) in gray.enumerate_pixels() {
+            assert_eq!(pixel[3], if x < 10 { 255 } else { 0 });
+        }
+        let opaque = gray.get_pixel(0, 0);
+        assert!(opaque[0] == opaque[1] && opaque[1] == opaque[2]);
+    }
+
+    #[tokio::test]
+    async fn convert_can_carry_exif_from_jpeg_to_jpeg() {
+        use exif::{Field, In, Tag, Value};
+
+        let jpeg = with_exif(&encode(&DynamicImage::new_rgb8(16, 16), ImageFormat::Jpeg), &[
+            Field { tag: Tag::Artist, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Jane".to_vec()]) },
+        ]);
+        let convert = |preserve: &'static str, quality: Option<&'static str>| {
+            let jpeg = jpeg.clone();
+            async move {
+                let mut parts = vec![
+                    Part::File("file", "a.jpg", &jpeg),
+                    Part::Text("format", "jpeg"),
+                    Part::Text("preserve_metadata", preserve),
+                ];
+                parts.extend(quality.map(|quality| Part::Text("quality", quality)));
+                let (status, _, body) = post(convert_handler, &parts).await;
+                assert_eq!(status, StatusCode::OK);
+                body
+            }
+        };
+
+        // With a quality the JPEG is re-encoded; without one it would be
+        // passed through as-is, which must not bring the EXIF along either
+        for quality in [Some("60"), None] {
+            let kept = read_exif(&convert("true", quality).await).unwrap();
+            let artist = kept.get_field(Tag::Artist, In::PRIMARY).unwrap();
+            assert_eq!(artist.display_value().to_string(), "\"Jane\"");
+            assert!(read_exif(&convert("false", quality).await).is_none(), "quality {:?}", quality);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_2x2_sprite_sheet_becomes_four_frames() {
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 255, 255]];
+        let sheet = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(32, 32, |x, y| image::Rgba(colors[(y / 16 * 2 + x / 16) as usize])));
+        let png = encode(&sheet, ImageFormat::Png);
+
+        let (status, _, gif) = post(spritesheet_to_gif_handler, &[
+            Part::File("file", "walk.png", &png),
+            Part::Text("rows", "2"),
+            Part::Text("cols", "2"),
+            Part::Text("delay_ms", "80"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let frames = decode_gif_frames(&gif).unwrap();
+        assert_eq!(frames.len(), 4);
+        for ((frame, delay), color) in frames.iter().zip(colors) {
+            assert_eq!(frame.dimensions(), (16, 16));
+            assert_eq!(*delay, 80);
+            assert_eq!(frame.get_pixel(8, 8).0, color);
+        }
+    }
+
+    #[tokio::test]
+    async fn reducing_a_gif_to_fewer_frames_keeps_its_duration() {
+        let frames: Vec<_> = (0..30u8)
+            .map(|i| (image::RgbaImage::from_pixel(8, 8, image::Rgba([i * 8, 100, 200, 255])), 50))
+            .collect();
+        let gif = encode_animation(&frames, ImageFormat::Gif).unwrap();
+
+        let (status, headers, body) = post(optimize_gif_handler, &[Part::File("file", "spin.gif", &gif), Part::Text("frames", "10")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["X-Original-Frame-Count"], "30");
+        assert_eq!(headers["X-Frame-Count"], "10");
+
+        let reduced = decode_gif_frames(&body).unwrap();
+        assert_eq!(reduced.len(), 10);
+        let total: u32 = reduced.iter().map(|(_, delay)| delay).sum();
+        assert!(total.abs_diff(30 * 50) <= 100, "total {}ms", total);
+    }
+
+    #[cfg(not(feature = "faces"))]
+    #[tokio::test]
+    async fn face_count_without_a_detector_is_not_implemented() {
+        let png = encode(&DynamicImage::new_rgb8(32, 32), ImageFormat::Png);
+        let (status, _, _) = post(face_count_handler, &[Part::File("file", "group.png", &png)]).await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[tokio::test]
+    async fn palette_colors_outside_2_to_256_are_rejected() {
+        let png = encode(&DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([x as u8 * 16, y as u8 * 16, 0]))), ImageFormat::Png);
+
+        for colors in ["abc", "1", "1000"] {
+            let (status, _, body) = post(convert_handler, &[
+                Part::File("file", "a.png", &png),
+                Part::Text("format", "png"),
+                Part::Text("colors", colors),
+            ]).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "colors={}", colors);
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["code"], "INVALID_FIELD_VALUE");
+
+            let (status, _, _) = post(compress_handler, &[Part::File("file", "a.png", &png), Part::Text("colors", colors)]).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "colors={}", colors);
+        }
+
+        let (status, _, body) = post(convert_handler, &[
+            Part::File("file", "a.png", &png),
+            Part::Text("format", "png"),
+            Part::Text("colors", "16"),
+        ]).await;
+        assert_eq!(status, StatusCode::OK);
+        // IHDR color type 3 is indexed
+        assert_eq!(body[25], 3);
+    }
+}
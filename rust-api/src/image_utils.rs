@@ -1,11 +1,97 @@
-use crate::types::{AppError, ImageFormat, ImageInfo, format_file_size};
-use image::{DynamicImage, ImageFormat as ImageFormatEnum, GenericImageView};
+use crate::types::{aspect_ratio, default_resize_filter, AppError, BlurAlgorithm, CensorShape, CensorStyle, ChannelLevels, ChromaSubsampling, ColorCount, CompareMode, ColorMode, DenoiseMethod, DimensionsInfo, Dither, EncodeOptions, EncodedImage, FaceBox, FormatDetection, ImageFormat, ImageInfo, Interpolation, Levels, PadMode, PrintFit, ResizeFilter, Shape, TiffCompression, format_file_size};
+use crate::camera_raw::embedded_preview;
+use crate::metadata::{exif_orientation, set_png_density};
+use crate::validation::{validate_dimensions, validate_frame_count, validate_upload};
+use color_quant::NeuQuant;
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::{DynamicImage, ImageError, ImageFormat as ImageFormatEnum, GenericImageView, RgbaImage};
+use std::collections::HashMap;
 use std::fs;
+use std::io::{BufWriter, Cursor, Seek, Write};
+use std::sync::OnceLock;
 use uuid::Uuid;
 
+fn decode_error(e: ImageError) -> AppError {
+    match e {
+        // The bytes came from the client, so a failed decode is their fault
+        ImageError::Decoding(_) | ImageError::IoError(_) => {
+            AppError::CorruptImage(format!("Failed to decode image: {}", e))
+        }
+        ImageError::Unsupported(_) => AppError::UnsupportedImageFormat,
+        _ => AppError::ImageProcessingError(format!("Failed to load image: {}", e)),
+    }
+}
+
 pub fn load_image_from_bytes(data: &[u8]) -> Result<DynamicImage, AppError> {
-    image::load_from_memory(data)
-        .map_err(|e| AppError::ImageProcessingError(format!("Failed to load image: {}", e)))
+    // Camera RAW decodes through its embedded preview. Without one, a
+    // TIFF-based RAW still has a (small) baseline image in IFD0.
+    if ImageFormat::from_magic_bytes(data) == Some(ImageFormat::Raw) {
+        if let Some(preview) = embedded_preview(data) {
+            return load_image_from_bytes(preview);
+        }
+        if !data.starts_with(b"II*\0") && !data.starts_with(b"MM\0*") {
+            return Err(AppError::CorruptImage("RAW file has no embedded preview to decode".to_string()));
+        }
+    }
+
+    // Check the header's dimensions before allocating for the pixels
+    let reader = || {
+        image::ImageReader::new(Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|e| decode_error(ImageError::IoError(e)))
+    };
+    let (width, height) = reader()?.into_dimensions().map_err(decode_error)?;
+    validate_dimensions(width, height)?;
+
+    reader()?.decode().map_err(decode_error)
+}
+
+// Decode and, with `orient`, rotate/flip the pixels upright per the EXIF
+// orientation tag. The tag is read from the raw bytes since decoding drops it.
+pub fn load_oriented_image(data: &[u8], orient: bool) -> Result<DynamicImage, AppError> {
+    let img = load_image_from_bytes(data)?;
+    Ok(match exif_orientation(data).filter(|_| orient) {
+        Some(orientation) => apply_exif_orientation(img, orientation),
+        None => img,
+    })
+}
+
+// Decoding keeps only the first frame, so callers check this before
+// operations that would silently turn an animation into a still
+pub fn is_animated(data: &[u8]) -> bool {
+    use image::AnimationDecoder;
+
+    match ImageFormat::from_magic_bytes(data) {
+        Some(ImageFormat::Gif) => image::codecs::gif::GifDecoder::new(Cursor::new(data))
+            .map(|decoder| decoder.into_frames().take(2).count() > 1)
+            .unwrap_or(false),
+        // APNG announces itself with an acTL chunk ahead of the image data
+        Some(ImageFormat::Png) => png_chunk_types(data).take_while(|t| t != b"IDAT").any(|t| &t == b"acTL"),
+        // VP8X header with the animation flag set
+        Some(ImageFormat::Webp) => data.get(12..16) == Some(b"VP8X") && data.get(20).is_some_and(|flags| flags & 0x02 != 0),
+        _ => false,
+    }
+}
+
+// 1-bit grayscale PNG or BMP. Decoding widens these to 8 bits, so only
+// the header still says so.
+pub fn is_bilevel(data: &[u8]) -> bool {
+    match ImageFormat::from_magic_bytes(data) {
+        // IHDR bit depth 1, color type 0
+        Some(ImageFormat::Png) => data.get(24..26) == Some(&[1, 0]),
+        Some(ImageFormat::Bmp) => data.get(28..30) == Some(&[1, 0]),
+        _ => false,
+    }
+}
+
+fn png_chunk_types(data: &[u8]) -> impl Iterator<Item = [u8; 4]> + '_ {
+    let mut pos = 8;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 8)?;
+        let length = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        pos += 12 + length;
+        Some(header[4..].try_into().unwrap())
+    })
 }
 
 pub fn create_temp_file(extension: &str) -> String {
@@ -13,20 +99,348 @@ pub fn create_temp_file(extension: &str) -> String {
     format!("/tmp/{}", filename)
 }
 
-pub fn save_image(img: &DynamicImage, path: &str, format: ImageFormat) -> Result<(), AppError> {
-    let image_format = match format {
+fn image_format_enum(format: ImageFormat) -> Result<ImageFormatEnum, AppError> {
+    Ok(match format {
         ImageFormat::Jpeg => ImageFormatEnum::Jpeg,
         ImageFormat::Png => ImageFormatEnum::Png,
         ImageFormat::Webp => ImageFormatEnum::WebP,
         ImageFormat::Bmp => ImageFormatEnum::Bmp,
         ImageFormat::Tiff => ImageFormatEnum::Tiff,
         ImageFormat::Gif => ImageFormatEnum::Gif,
-    };
+        ImageFormat::Ico => ImageFormatEnum::Ico,
+        ImageFormat::Raw => return Err(raw_output_error()),
+    })
+}
 
-    img.save_with_format(path, image_format)
+fn raw_output_error() -> AppError {
+    AppError::InvalidFieldValue("RAW is an input-only format".to_string())
+}
+
+pub fn save_image(img: &DynamicImage, path: &str, format: ImageFormat) -> Result<(), AppError> {
+    img.save_with_format(path, image_format_enum(format)?)
         .map_err(|e| AppError::ImageProcessingError(format!("Failed to save image: {}", e)))
 }
 
+// Transparent pixels are composited onto this when the format has no alpha
+pub const FLATTEN_BACKGROUND: [u8; 3] = [255, 255, 255];
+
+// Encode an image in memory with the given encoder settings
+pub fn encode_image_to_bytes(
+    img: &DynamicImage,
+    format: ImageFormat,
+    options: &EncodeOptions,
+) -> Result<EncodedImage, AppError> {
+    let mut output = Cursor::new(Vec::new());
+    let alpha_flattened = encode_image_into(img, format, options, &mut output)?;
+    Ok(EncodedImage { data: output.into_inner(), alpha_flattened })
+}
+
+static USE_TEMP_FILES: OnceLock<bool> = OnceLock::new();
+
+// GIMG_USE_TEMP=true encodes responses through a temp file instead of an
+// in-memory buffer, for hosts where very large outputs should spill to disk
+fn use_temp_files() -> bool {
+    *USE_TEMP_FILES.get_or_init(|| {
+        std::env::var("GIMG_USE_TEMP")
+            .map(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true"))
+            .unwrap_or(false)
+    })
+}
+
+// Encode a response image, in memory or via a temp file per GIMG_USE_TEMP.
+// Both strategies produce the same bytes.
+pub fn output_image(
+    img: &DynamicImage,
+    format: ImageFormat,
+    options: &EncodeOptions,
+) -> Result<EncodedImage, AppError> {
+    if use_temp_files() {
+        encode_via_temp_file(img, format, options)
+    } else {
+        encode_image_to_bytes(img, format, options)
+    }
+}
+
+fn encode_via_temp_file(
+    img: &DynamicImage,
+    format: ImageFormat,
+    options: &EncodeOptions,
+) -> Result<EncodedImage, AppError> {
+    let temp_path = create_temp_file(format.extension());
+    let result = (|| {
+        let file = fs::File::create(&temp_path)
+            .map_err(|e| AppError::IoError(format!("Failed to create temp file: {}", e)))?;
+        let mut writer = BufWriter::new(file);
+        let alpha_flattened = encode_image_into(img, format, options, &mut writer)?;
+        writer
+            .flush()
+            .map_err(|e| AppError::IoError(format!("Failed to write temp file: {}", e)))?;
+        drop(writer);
+        Ok(EncodedImage { data: read_file_bytes(&temp_path)?, alpha_flattened })
+    })();
+    delete_temp_file(&temp_path);
+    result
+}
+
+// Write the encoding to `output`. Returns the background color transparent
+// pixels were flattened onto, if any.
+fn encode_image_into<W: Write + Seek>(
+    img: &DynamicImage,
+    format: ImageFormat,
+    options: &EncodeOptions,
+    output: &mut W,
+) -> Result<Option<[u8; 3]>, AppError> {
+    let encode_error = |e: String| AppError::ImageProcessingError(format!("Failed to encode image: {}", e));
+    let mut alpha_flattened = None;
+
+    match format {
+        ImageFormat::Jpeg => {
+            let mut encoder = jpeg_encoder::Encoder::new(&mut *output, options.quality);
+            encoder.set_progressive(options.progressive);
+            encoder.set_optimized_huffman_tables(options.optimize || options.effort >= 5);
+            if let Some(interval) = options.restart_interval {
+                encoder.set_restart_interval(interval);
+            }
+            if let Some(dpi) = options.dpi {
+                encoder.set_density(jpeg_encoder::PixelDensity::dpi(dpi));
+            }
+            encoder.set_sampling_factor(match options.subsampling {
+                ChromaSubsampling::Yuv444 => jpeg_encoder::SamplingFactor::R_4_4_4,
+                ChromaSubsampling::Yuv422 => jpeg_encoder::SamplingFactor::R_4_2_2,
+                ChromaSubsampling::Yuv420 => jpeg_encoder::SamplingFactor::R_4_2_0,
+            });
+
+            let (width, height) = img.dimensions();
+            if width > u16::MAX as u32 || height > u16::MAX as u32 {
+                return Err(AppError::InvalidFieldValue("Image too large for JPEG".to_string()));
+            }
+            let rgb = if has_transparency(img) {
+                alpha_flattened = Some(FLATTEN_BACKGROUND);
+                flatten_alpha(img, FLATTEN_BACKGROUND)
+            } else {
+                img.to_rgb8()
+            };
+            // Gray images get a single-component JPEG rather than three
+            // identical channels
+            let grayscale = !img.color().has_color();
+            let encoded = if grayscale {
+                let gray = DynamicImage::ImageRgb8(rgb).to_luma8();
+                encoder.encode(gray.as_raw(), width as u16, height as u16, jpeg_encoder::ColorType::Luma)
+            } else {
+                encoder.encode(rgb.as_raw(), width as u16, height as u16, jpeg_encoder::ColorType::Rgb)
+            };
+            encoded.map_err(|e| encode_error(e.to_string()))?;
+        }
+        ImageFormat::Png => {
+            // Effort 0 keeps the fast single-pass encoder; 1-9 are zlib levels
+            let compression = match options.compression_level {
+                None if options.effort == 0 => CompressionType::Fast,
+                None => CompressionType::Level(options.effort),
+                Some(0) => CompressionType::Uncompressed,
+                Some(level) => CompressionType::Level(level),
+            };
+            // The encoder can't write pHYs, so splice it in afterwards
+            let Some(dpi) = options.dpi else {
+                let encoder = PngEncoder::new_with_quality(&mut *output, compression, PngFilterType::Adaptive);
+                img.write_with_encoder(encoder).map_err(|e| encode_error(e.to_string()))?;
+                return Ok(alpha_flattened);
+            };
+            let mut png = Vec::new();
+            let encoder = PngEncoder::new_with_quality(&mut png, compression, PngFilterType::Adaptive);
+            img.write_with_encoder(encoder).map_err(|e| encode_error(e.to_string()))?;
+            output.write_all(&set_png_density(&png, dpi)?).map_err(|e| encode_error(e.to_string()))?;
+        }
+        ImageFormat::Webp => {
+            let rgba = img.to_rgba8();
+            let encoder = webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height());
+            let mut config = webp::WebPConfig::new().map_err(|_| encode_error("invalid WebP config".to_string()))?;
+            config.lossless = options.lossless as i32;
+            config.alpha_compression = !options.lossless as i32;
+            config.quality = if options.lossless { 75.0 } else { options.quality as f32 };
+            // libwebp's method runs 0-6
+            config.method = options.effort.min(6) as i32;
+            let encoded = encoder.encode_advanced(&config).map_err(|e| encode_error(format!("{:?}", e)))?;
+            output.write_all(&encoded).map_err(|e| encode_error(e.to_string()))?;
+        }
+        ImageFormat::Tiff => encode_tiff(img, options.tiff_compression, output)?,
+        ImageFormat::Ico if img.width() > 256 || img.height() > 256 => {
+            return Err(AppError::InvalidFieldValue("Image too large for ICO (max 256x256)".to_string()));
+        }
+        ImageFormat::Bmp | ImageFormat::Gif | ImageFormat::Ico => {
+            img.write_to(output, image_format_enum(format)?)
+                .map_err(|e| encode_error(e.to_string()))?;
+        }
+        ImageFormat::Raw => return Err(raw_output_error()),
+    }
+
+    Ok(alpha_flattened)
+}
+
+// Shrink (and, down to `min_quality`, re-compress) until the encoded output
+// is at most `max_bytes`. Returns the encoding and the quality that got there.
+pub fn fit_to_bytes(
+    img: &DynamicImage,
+    format: ImageFormat,
+    options: &EncodeOptions,
+    max_bytes: usize,
+    min_quality: u8,
+) -> Result<(EncodedImage, (u32, u32), u8), AppError> {
+    let mut current = img.clone();
+    let mut options = options.clone();
+    let start_quality = options.quality;
+
+    loop {
+        options.quality = start_quality;
+        let mut encoded = encode_image_to_bytes(&current, format, &options)?;
+        while encoded.data.len() > max_bytes && options.quality > min_quality {
+            options.quality = options.quality.saturating_sub(10).max(min_quality);
+            encoded = encode_image_to_bytes(&current, format, &options)?;
+        }
+
+        let size = encoded.data.len();
+        if size <= max_bytes {
+            return Ok((encoded, current.dimensions(), options.quality));
+        }
+
+        let (width, height) = current.dimensions();
+        if width == 1 && height == 1 {
+            return Err(AppError::InvalidFieldValue(format!(
+                "Image cannot be encoded within {} bytes",
+                max_bytes
+            )));
+        }
+
+        // Encoded size roughly tracks pixel count, so step by the square root
+        // of the overshoot, always shrinking at least a little
+        let scale = (max_bytes as f64 / size as f64).sqrt().clamp(0.5, 0.9);
+        let new_width = ((width as f64 * scale) as u32).max(1);
+        let new_height = ((height as f64 * scale) as u32).max(1);
+        current = resize_image_fast(img, new_width, new_height)?;
+    }
+}
+
+fn encode_tiff<W: Write + Seek>(img: &DynamicImage, compression: TiffCompression, output: &mut W) -> Result<(), AppError> {
+    use tiff::encoder::{colortype, Compression, DeflateLevel, Predictor, TiffEncoder};
+
+    let encode_error = |e: tiff::TiffError| AppError::ImageProcessingError(format!("Failed to encode TIFF: {}", e));
+    let (compression, predictor) = match compression {
+        TiffCompression::None => (Compression::Uncompressed, Predictor::None),
+        // Horizontal differencing makes LZW/Deflate noticeably smaller on photos
+        TiffCompression::Lzw => (Compression::Lzw, Predictor::Horizontal),
+        TiffCompression::Deflate => (Compression::Deflate(DeflateLevel::Balanced), Predictor::Horizontal),
+        TiffCompression::Packbits => (Compression::Packbits, Predictor::None),
+    };
+
+    let mut encoder = TiffEncoder::new(output)
+        .map_err(encode_error)?
+        .with_compression(compression)
+        .with_predictor(predictor);
+    let (width, height) = img.dimensions();
+
+    match img {
+        DynamicImage::ImageLuma8(gray) => encoder.write_image::<colortype::Gray8>(width, height, gray.as_raw()),
+        _ if img.color().has_alpha() => {
+            encoder.write_image::<colortype::RGBA8>(width, height, img.to_rgba8().as_raw())
+        }
+        _ => encoder.write_image::<colortype::RGB8>(width, height, img.to_rgb8().as_raw()),
+    }
+    .map_err(encode_error)
+}
+
+fn has_transparency(img: &DynamicImage) -> bool {
+    img.color().has_alpha() && img.to_rgba8().pixels().any(|p| p[3] < 255)
+}
+
+// Composite onto a solid background, dropping the alpha channel
+fn flatten_alpha(img: &DynamicImage, background: [u8; 3]) -> image::RgbImage {
+    let rgba = img.to_rgba8();
+    image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let pixel = rgba.get_pixel(x, y);
+        let alpha = pixel[3] as u32;
+        image::Rgb(std::array::from_fn(|c| {
+            ((pixel[c] as u32 * alpha + background[c] as u32 * (255 - alpha) + 127) / 255) as u8
+        }))
+    })
+}
+
+// Kaleidoscope: a `size` x `size` image made of `segments` mirrored wedges
+// around the center, each showing the wedge of the source that starts
+// straight up from its center. The fold uses the absolute angle, so the
+// result is exactly mirror-symmetric left to right.
+pub fn kaleidoscope(img: &DynamicImage, segments: u32, size: u32) -> DynamicImage {
+    let source = img.to_rgba8();
+    let (src_width, src_height) = source.dimensions();
+    let (src_cx, src_cy) = (src_width as f32 / 2.0, src_height as f32 / 2.0);
+    let scale = src_width.min(src_height) as f32 / size as f32;
+    let wedge = std::f32::consts::TAU / segments as f32;
+    let center = size as f32 / 2.0;
+
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(size, size, |x, y| {
+        let (dx, dy) = (x as f32 + 0.5 - center, y as f32 + 0.5 - center);
+        let radius = (dx * dx + dy * dy).sqrt() * scale;
+
+        // Clockwise from straight up, folded into the first wedge with every
+        // other wedge mirrored
+        let angle = dx.atan2(-dy).abs();
+        let segment = (angle / wedge).floor();
+        let mut local = angle - segment * wedge;
+        if segment as u32 % 2 == 1 {
+            local = wedge - local;
+        }
+
+        let sx = (src_cx + radius * local.sin() - 0.5).clamp(0.0, (src_width - 1) as f32);
+        let sy = (src_cy - radius * local.cos() - 0.5).clamp(0.0, (src_height - 1) as f32);
+        image::imageops::interpolate_bilinear(&source, sx, sy).unwrap_or(image::Rgba([0, 0, 0, 0]))
+    }))
+}
+
+// Light squares of the alpha preview; the dark ones are CHECKER_DARK
+const CHECKER_LIGHT: [u8; 3] = [255, 255, 255];
+const CHECKER_DARK: [u8; 3] = [204, 204, 204];
+
+// Composite over the usual editor checkerboard of `size`-pixel squares, so
+// transparency is visible in an opaque image
+pub fn checkerboard_preview(img: &DynamicImage, size: u32) -> DynamicImage {
+    let rgba = img.to_rgba8();
+    DynamicImage::ImageRgb8(image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let background = if (x / size + y / size).is_multiple_of(2) { CHECKER_LIGHT } else { CHECKER_DARK };
+        let pixel = rgba.get_pixel(x, y);
+        let alpha = pixel[3] as u32;
+        image::Rgb(std::array::from_fn(|c| {
+            ((pixel[c] as u32 * alpha + background[c] as u32 * (255 - alpha) + 127) / 255) as u8
+        }))
+    }))
+}
+
+// AVIF is output-only (no decoder is built in), so it isn't an ImageFormat.
+// Encoder speed runs 1 (slowest) to 10, the reverse of effort.
+pub fn encode_avif(img: &DynamicImage, quality: u8, effort: u8) -> Result<Vec<u8>, AppError> {
+    let mut output = Vec::new();
+    let speed = 10 - effort.min(9);
+    let encoder = image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut output, speed, quality);
+    img.to_rgba8()
+        .write_with_encoder(encoder)
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to encode AVIF: {}", e)))?;
+    Ok(output)
+}
+
+// Peak signal-to-noise ratio over RGB, capped at 100 dB for identical images
+pub fn psnr(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    let (a, b) = (a.to_rgb8(), b.to_rgb8());
+    let squared_error: f64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw().iter())
+        .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+        .sum();
+    let mse = squared_error / a.as_raw().len().max(1) as f64;
+    if mse == 0.0 {
+        100.0
+    } else {
+        (10.0 * (255.0f64 * 255.0 / mse).log10()).min(100.0)
+    }
+}
+
 pub fn get_image_info(img: &DynamicImage, original_path: &str, original_size: usize) -> ImageInfo {
     let (width, height) = img.dimensions();
     let color_type = img.color();
@@ -51,10 +465,16 @@ pub fn get_image_info(img: &DynamicImage, original_path: &str, original_size: us
         "TIFF"
     } else if original_path.ends_with(".gif") {
         "GIF"
+    } else if original_path.ends_with(".ico") {
+        "ICO"
+    } else if [".cr2", ".nef", ".arw", ".dng", ".orf", ".rw2", ".raf"].iter().any(|ext| original_path.to_lowercase().ends_with(ext)) {
+        "RAW"
     } else {
         "Unknown"
     };
 
+    let (aspect_ratio, aspect_ratio_string) = aspect_ratio(width, height).unzip();
+
     ImageInfo {
         file: original_path.split('/').last().unwrap_or("unknown").to_string(),
         format: detected_format.to_string(),
@@ -62,11 +482,44 @@ pub fn get_image_info(img: &DynamicImage, original_path: &str, original_size: us
         width,
         height,
         mode: format.to_string(),
+        palette_size: None,
+        aspect_ratio,
+        aspect_ratio_string,
+        unique_colors: None,
+        is_lossless: None,
         file_size: original_size as u64,
         file_size_human: format_file_size(original_size as u64),
     }
 }
 
+// Read only the image header, without decoding any pixels
+pub fn get_image_dimensions(data: &[u8], original_path: &str) -> Result<DimensionsInfo, AppError> {
+    let raw_preview = (ImageFormat::from_magic_bytes(data) == Some(ImageFormat::Raw))
+        .then(|| embedded_preview(data))
+        .flatten();
+    let reader = image::ImageReader::new(Cursor::new(raw_preview.unwrap_or(data)))
+        .with_guessed_format()
+        .map_err(|e| decode_error(ImageError::IoError(e)))?;
+    let format = match raw_preview {
+        Some(_) => "RAW".to_string(),
+        None => reader
+            .format()
+            .map(|f| format!("{:?}", f).to_uppercase())
+            .unwrap_or_else(|| "Unknown".to_string()),
+    };
+    let (width, height) = reader.into_dimensions().map_err(decode_error)?;
+
+    Ok(DimensionsInfo {
+        file: original_path.split('/').next_back().unwrap_or("unknown").to_string(),
+        format,
+        dimensions: format!("{}x{}", width, height),
+        width,
+        height,
+        file_size: data.len() as u64,
+        file_size_human: format_file_size(data.len() as u64),
+    })
+}
+
 pub fn read_file_bytes(path: &str) -> Result<Vec<u8>, AppError> {
     fs::read(path).map_err(|e| AppError::IoError(format!("Failed to read file: {}", e)))
 }
@@ -75,41 +528,2248 @@ pub fn delete_temp_file(path: &str) {
     let _ = fs::remove_file(path);
 }
 
+// Rotate/flip so the pixels match an EXIF orientation of 1
+pub fn apply_exif_orientation(mut img: DynamicImage, orientation: u8) -> DynamicImage {
+    if let Some(orientation) = image::metadata::Orientation::from_exif(orientation) {
+        img.apply_orientation(orientation);
+    }
+    img
+}
+
 // Resize using the standard image crate for now (TODO: optimize with fast_image_resize)
 pub fn resize_image_fast(
     img: &DynamicImage,
     new_width: u32,
     new_height: u32,
 ) -> Result<DynamicImage, AppError> {
-    // Use the standard image crate resize with Lanczos3 filtering
-    Ok(img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3))
+    resize_image_with_alpha(img, new_width, new_height, default_resize_filter(), true)
 }
 
-// Color parsing utility
-pub fn parse_color(color_str: &str) -> Result<image::Rgba<u8>, AppError> {
-    match color_str.to_lowercase().as_str() {
-        "white" => Ok(image::Rgba([255, 255, 255, 255])),
-        "black" => Ok(image::Rgba([0, 0, 0, 255])),
-        "red" => Ok(image::Rgba([255, 0, 0, 255])),
-        "green" => Ok(image::Rgba([0, 255, 0, 255])),
-        "blue" => Ok(image::Rgba([0, 0, 255, 255])),
-        "yellow" => Ok(image::Rgba([255, 255, 0, 255])),
-        "cyan" => Ok(image::Rgba([0, 255, 255, 255])),
-        "magenta" => Ok(image::Rgba([255, 0, 255, 255])),
-        _ => {
-            // Try to parse as hex color
-            if color_str.starts_with('#') && color_str.len() == 7 {
-                let hex = &color_str[1..];
-                let r = u8::from_str_radix(&hex[0..2], 16)
-                    .map_err(|_| AppError::InvalidFieldValue(format!("Invalid color: {}", color_str)))?;
-                let g = u8::from_str_radix(&hex[2..4], 16)
-                    .map_err(|_| AppError::InvalidFieldValue(format!("Invalid color: {}", color_str)))?;
-                let b = u8::from_str_radix(&hex[4..6], 16)
-                    .map_err(|_| AppError::InvalidFieldValue(format!("Invalid color: {}", color_str)))?;
-                Ok(image::Rgba([r, g, b, 255]))
+fn filter_type(filter: ResizeFilter) -> image::imageops::FilterType {
+    use image::imageops::FilterType;
+    match filter {
+        ResizeFilter::Nearest => FilterType::Nearest,
+        ResizeFilter::Bilinear => FilterType::Triangle,
+        ResizeFilter::Bicubic => FilterType::CatmullRom,
+        ResizeFilter::Gaussian => FilterType::Gaussian,
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+    }
+}
+
+// Resize with `filter`. Callers compute the exact target size, so don't
+// re-fit to the aspect ratio here. Rounding down extreme ratios can reach 0,
+// which the encoders reject.
+//
+// With `premultiply`, transparent images are filtered in premultiplied
+// alpha so fully transparent (often black) pixels don't bleed into edges.
+pub fn resize_image_with_alpha(
+    img: &DynamicImage,
+    new_width: u32,
+    new_height: u32,
+    filter: ResizeFilter,
+    premultiply: bool,
+) -> Result<DynamicImage, AppError> {
+    let (new_width, new_height) = (new_width.max(1), new_height.max(1));
+    let filter = filter_type(filter);
+
+    if !premultiply || !has_transparency(img) {
+        return Ok(img.resize_exact(new_width, new_height, filter));
+    }
+
+    let mut linear = img.to_rgba32f();
+    for pixel in linear.pixels_mut() {
+        let alpha = pixel[3];
+        for c in 0..3 {
+            pixel[c] *= alpha;
+        }
+    }
+
+    let mut resized = image::imageops::resize(&linear, new_width, new_height, filter);
+    for pixel in resized.pixels_mut() {
+        let alpha = pixel[3].clamp(0.0, 1.0);
+        for c in 0..3 {
+            pixel[c] = if alpha > 0.0 { (pixel[c] / alpha).clamp(0.0, 1.0) } else { 0.0 };
+        }
+        pixel[3] = alpha;
+    }
+
+    let resized = DynamicImage::ImageRgba32F(resized);
+    Ok(match img {
+        DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgba16(resized.to_rgba16()),
+        DynamicImage::ImageRgba32F(_) => resized,
+        _ => DynamicImage::ImageRgba8(resized.to_rgba8()),
+    })
+}
+
+// Resize with `downscale` on an axis that shrinks and `upscale` on one that
+// grows; an unchanged axis follows the other. When the two axes need
+// different filters, width and height are resampled in separate passes.
+// Returns the filters used for width and height.
+pub fn resize_directional(
+    img: &DynamicImage,
+    new_width: u32,
+    new_height: u32,
+    downscale: ResizeFilter,
+    upscale: ResizeFilter,
+    premultiply: bool,
+) -> Result<(DynamicImage, ResizeFilter, ResizeFilter), AppError> {
+    let (width, height) = img.dimensions();
+    let pick = |from: u32, to: u32| match to.max(1).cmp(&from) {
+        std::cmp::Ordering::Less => Some(downscale),
+        std::cmp::Ordering::Greater => Some(upscale),
+        std::cmp::Ordering::Equal => None,
+    };
+    let (horizontal, vertical) = match (pick(width, new_width), pick(height, new_height)) {
+        (Some(h), Some(v)) => (h, v),
+        (Some(h), None) => (h, h),
+        (None, Some(v)) => (v, v),
+        (None, None) => (downscale, downscale),
+    };
+
+    let resized = if horizontal == vertical {
+        resize_image_with_alpha(img, new_width, new_height, horizontal, premultiply)?
+    } else {
+        let wide = resize_image_with_alpha(img, new_width, height, horizontal, premultiply)?;
+        resize_image_with_alpha(&wide, new_width, new_height, vertical, premultiply)?
+    };
+    Ok((resized, horizontal, vertical))
+}
+
+// Upscale in successive 2x steps with a light sharpen after each, which
+// keeps more detail than a single large jump.
+pub fn upscale_iterative(
+    img: &DynamicImage,
+    new_width: u32,
+    new_height: u32,
+    filter: ResizeFilter,
+) -> Result<DynamicImage, AppError> {
+    let mut current = img.clone();
+
+    while current.width() * 2 <= new_width && current.height() * 2 <= new_height {
+        let (width, height) = current.dimensions();
+        current = unsharpen_image(&resize_image_with_alpha(&current, width * 2, height * 2, filter, true)?, 0.5, 1);
+    }
+
+    if current.dimensions() != (new_width, new_height) {
+        current = resize_image_with_alpha(&current, new_width, new_height, filter, true)?;
+    }
+
+    Ok(current)
+}
+
+// Super-resolution through the ESRGAN model when the `esrgan` feature is
+// enabled and the model file is present; otherwise plain Lanczos.
+pub fn upscale_with_model(
+    img: &DynamicImage,
+    new_width: u32,
+    new_height: u32,
+) -> Result<DynamicImage, AppError> {
+    #[cfg(feature = "esrgan")]
+    if let Some(upscaled) = crate::super_resolution::upscale(img)? {
+        // The model has a fixed scale factor, so land on the requested size
+        if upscaled.dimensions() == (new_width, new_height) {
+            return Ok(upscaled);
+        }
+        return resize_image_fast(&upscaled, new_width, new_height);
+    }
+
+    resize_image_fast(img, new_width, new_height)
+}
+
+// Add plausible color to a grayscale image with the colorization model.
+// Only available with the `colorize` feature and the model file present.
+pub fn colorize(img: &DynamicImage) -> Result<DynamicImage, AppError> {
+    #[cfg(feature = "colorize")]
+    if let Some(colorized) = crate::colorization::colorize(img)? {
+        return Ok(colorized);
+    }
+
+    #[cfg(not(feature = "colorize"))]
+    let _ = img;
+    Err(AppError::NotImplemented)
+}
+
+// Faces found by the face detection model, most confident first. Only
+// available with the `faces` feature and the model file present.
+pub fn detect_faces(img: &DynamicImage) -> Result<Vec<FaceBox>, AppError> {
+    #[cfg(feature = "faces")]
+    if let Some(faces) = crate::face_detection::detect_faces(img)? {
+        return Ok(faces);
+    }
+
+    #[cfg(not(feature = "faces"))]
+    let _ = img;
+    Err(AppError::NotImplemented)
+}
+
+// Smallest rectangle holding every face, grown on each side by `padding`
+// times the faces' extent on that axis and clamped to the image. None when
+// there are no faces.
+pub fn face_crop_region(faces: &[FaceBox], width: u32, height: u32, padding: f32) -> Option<(u32, u32, u32, u32)> {
+    let left = faces.iter().map(|f| f.x).min()?;
+    let top = faces.iter().map(|f| f.y).min()?;
+    let right = faces.iter().map(|f| f.x + f.width).max()?.min(width);
+    let bottom = faces.iter().map(|f| f.y + f.height).max()?.min(height);
+
+    let pad_x = ((right.saturating_sub(left)) as f32 * padding).round() as u32;
+    let pad_y = ((bottom.saturating_sub(top)) as f32 * padding).round() as u32;
+    let x0 = left.saturating_sub(pad_x).min(width - 1);
+    let y0 = top.saturating_sub(pad_y).min(height - 1);
+    let x1 = right.saturating_add(pad_x).min(width).max(x0 + 1);
+    let y1 = bottom.saturating_add(pad_y).min(height).max(y0 + 1);
+    Some((x0, y0, x1 - x0, y1 - y0))
+}
+
+// Find the top-left corner of the `crop_width` x `crop_height` window with
+// the highest total color saturation. Uses an integral image so every
+// window position is evaluated in constant time.
+pub fn find_most_colorful_region(img: &DynamicImage, crop_width: u32, crop_height: u32) -> (u32, u32) {
+    let rgb = img.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let stride = width as usize + 1;
+
+    let mut integral = vec![0u64; stride * (height as usize + 1)];
+    for y in 0..height as usize {
+        let mut row_sum = 0u64;
+        for x in 0..width as usize {
+            let p = rgb.get_pixel(x as u32, y as u32);
+            let max = p[0].max(p[1]).max(p[2]) as u64;
+            let min = p[0].min(p[1]).min(p[2]) as u64;
+            row_sum += ((max - min) * 255).checked_div(max).unwrap_or(0);
+            integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    let (cw, ch) = (crop_width as usize, crop_height as usize);
+    let window_sum = |x: usize, y: usize| {
+        integral[(y + ch) * stride + x + cw] + integral[y * stride + x]
+            - integral[y * stride + x + cw]
+            - integral[(y + ch) * stride + x]
+    };
+
+    // Start centered so an image with no color at all gets a center crop
+    let mut best = ((width - crop_width) / 2, (height - crop_height) / 2);
+    let mut best_sum = window_sum(best.0 as usize, best.1 as usize);
+    for y in 0..=(height - crop_height) as usize {
+        for x in 0..=(width - crop_width) as usize {
+            let sum = window_sum(x, y);
+            if sum > best_sum {
+                best_sum = sum;
+                best = (x as u32, y as u32);
+            }
+        }
+    }
+
+    best
+}
+
+// Map histogram values through their CDF so they span the full 0-255 range.
+fn equalization_lut(histogram: &[u64; 256]) -> [u8; 256] {
+    let total: u64 = histogram.iter().sum();
+    let cdf_min = histogram.iter().copied().find(|&count| count > 0).unwrap_or(0);
+    let mut lut = [0u8; 256];
+    let mut cdf = 0u64;
+
+    for (value, &count) in histogram.iter().enumerate() {
+        cdf += count;
+        lut[value] = if total == cdf_min {
+            value as u8
+        } else {
+            (cdf.saturating_sub(cdf_min) * 255 / (total - cdf_min)) as u8
+        };
+    }
+
+    lut
+}
+
+// Histogram equalization. By default the luma channel is equalized and RGB
+// is rescaled proportionally to keep hues; `per_channel` equalizes R, G and B
+// independently instead. Alpha is left untouched.
+pub fn equalize_histogram(img: &DynamicImage, per_channel: bool) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+
+    if per_channel {
+        for channel in 0..3 {
+            let mut histogram = [0u64; 256];
+            for pixel in rgba.pixels() {
+                histogram[pixel[channel] as usize] += 1;
+            }
+            let lut = equalization_lut(&histogram);
+            for pixel in rgba.pixels_mut() {
+                pixel[channel] = lut[pixel[channel] as usize];
+            }
+        }
+    } else {
+        let luma = |p: &image::Rgba<u8>| {
+            ((p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000) as usize
+        };
+
+        let mut histogram = [0u64; 256];
+        for pixel in rgba.pixels() {
+            histogram[luma(pixel)] += 1;
+        }
+        let lut = equalization_lut(&histogram);
+
+        for pixel in rgba.pixels_mut() {
+            let old_luma = luma(pixel);
+            let new_luma = lut[old_luma] as f32;
+            if old_luma == 0 {
+                pixel[0] = new_luma as u8;
+                pixel[1] = new_luma as u8;
+                pixel[2] = new_luma as u8;
             } else {
-                Err(AppError::InvalidFieldValue(format!("Unsupported color: {}", color_str)))
+                let gain = new_luma / old_luma as f32;
+                for channel in 0..3 {
+                    pixel[channel] = (pixel[channel] as f32 * gain).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+
+    DynamicImage::ImageRgba8(rgba)
+}
+
+// Shift white balance with per-channel gains. `temperature` warms (raises
+// red, lowers blue) when positive; `tint` pushes towards magenta when
+// positive and green when negative. Both are clamped to -100..100.
+pub fn apply_white_balance(img: &DynamicImage, temperature: f32, tint: f32) -> DynamicImage {
+    let temperature = temperature.clamp(-100.0, 100.0) / 100.0;
+    let tint = tint.clamp(-100.0, 100.0) / 100.0;
+    let gains = [
+        1.0 + 0.3 * temperature,
+        1.0 - 0.3 * tint,
+        1.0 - 0.3 * temperature,
+    ];
+
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        for channel in 0..3 {
+            pixel[channel] = (pixel[channel] as f32 * gains[channel]).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    DynamicImage::ImageRgba8(rgba)
+}
+
+// Remap each channel through a lookup table built from its levels. Alpha is
+// left untouched.
+pub fn apply_levels(img: &DynamicImage, levels: &Levels) -> DynamicImage {
+    let lut = |channel: &Option<ChannelLevels>| -> [u8; 256] {
+        let Some(ChannelLevels { input: [in_black, in_white], out: [out_black, out_white] }) = *channel else {
+            return std::array::from_fn(|v| v as u8);
+        };
+        std::array::from_fn(|v| {
+            let t = (v as f32 - in_black as f32) / (in_white as f32 - in_black as f32);
+            let value = out_black as f32 + (out_white as f32 - out_black as f32) * t.clamp(0.0, 1.0);
+            value.round() as u8
+        })
+    };
+    let luts = [lut(&levels.r), lut(&levels.g), lut(&levels.b)];
+
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        for channel in 0..3 {
+            pixel[channel] = luts[channel][pixel[channel] as usize];
+        }
+    }
+
+    DynamicImage::ImageRgba8(rgba)
+}
+
+// Gradient map: each pixel's luma picks a color between `shadow` (black)
+// and `highlight` (white). Alpha is left untouched.
+pub fn apply_duotone(img: &DynamicImage, shadow: image::Rgba<u8>, highlight: image::Rgba<u8>) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let luma = (pixel[0] as f32 * 0.299 + pixel[1] as f32 * 0.587 + pixel[2] as f32 * 0.114) / 255.0;
+        for channel in 0..3 {
+            let value = shadow[channel] as f32 + (highlight[channel] as f32 - shadow[channel] as f32) * luma;
+            pixel[channel] = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    DynamicImage::ImageRgba8(rgba)
+}
+
+pub fn blur_image(img: &DynamicImage, sigma: f32, algorithm: BlurAlgorithm) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    if width <= 1 && height <= 1 {
+        return img.clone();
+    }
+    // Past the image's size a wider kernel only costs time
+    let sigma = sigma.min(width.max(height) as f32);
+    match algorithm {
+        BlurAlgorithm::Gaussian => img.blur(sigma),
+        BlurAlgorithm::Box => box_blur(img, sigma),
+    }
+}
+
+// Unsharp mask with the same guards as blur_image
+pub fn unsharpen_image(img: &DynamicImage, sigma: f32, threshold: i32) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    if width <= 1 && height <= 1 {
+        return img.clone();
+    }
+    img.unsharpen(sigma.min(width.max(height) as f32), threshold)
+}
+
+// Hide the `(x, y, width, height)` region, clipped to the image. `strength`
+// is the blur sigma x10 for gaussian, and twice the block size for pixelate.
+pub fn censor_region(
+    img: &DynamicImage,
+    region: (u32, u32, u32, u32),
+    style: CensorStyle,
+    shape: CensorShape,
+    strength: u32,
+    blur_algo: BlurAlgorithm,
+    fill: image::Rgba<u8>,
+) -> Result<DynamicImage, AppError> {
+    let (img_width, img_height) = img.dimensions();
+    let (x, y) = (region.0.min(img_width), region.1.min(img_height));
+    let width = region.2.min(img_width - x);
+    let height = region.3.min(img_height - y);
+    if width == 0 || height == 0 {
+        return Err(AppError::InvalidFieldValue("Region is outside the image".to_string()));
+    }
+
+    let patch = img.crop_imm(x, y, width, height);
+    let censored = match style {
+        CensorStyle::Gaussian => blur_image(&patch, strength as f32 / 10.0, blur_algo).to_rgba8(),
+        CensorStyle::Pixelate => {
+            let block = (strength / 2).max(2);
+            let small = patch.resize_exact(
+                width.div_ceil(block),
+                height.div_ceil(block),
+                image::imageops::FilterType::Triangle,
+            );
+            small.resize_exact(width, height, image::imageops::FilterType::Nearest).to_rgba8()
+        }
+        CensorStyle::Fill => {
+            // A translucent fill tints the region rather than replacing it
+            let mut patch = patch.to_rgba8();
+            let alpha = fill[3] as f32 / 255.0;
+            for pixel in patch.pixels_mut() {
+                for c in 0..3 {
+                    pixel[c] = (fill[c] as f32 * alpha + pixel[c] as f32 * (1.0 - alpha)).round() as u8;
+                }
+                pixel[3] = (pixel[3] as f32 + (255 - pixel[3]) as f32 * alpha).round() as u8;
+            }
+            patch
+        }
+    };
+
+    let mut output = img.to_rgba8();
+    let (rx, ry) = (width as f32 / 2.0, height as f32 / 2.0);
+    for (px, py, pixel) in censored.enumerate_pixels() {
+        if shape == CensorShape::Ellipse {
+            let dx = (px as f32 + 0.5 - rx) / rx;
+            let dy = (py as f32 + 0.5 - ry) / ry;
+            if dx * dx + dy * dy > 1.0 {
+                continue;
             }
         }
+        output.put_pixel(x + px, y + py, *pixel);
     }
-}
\ No newline at end of file
+
+    Ok(DynamicImage::ImageRgba8(output))
+}
+
+pub fn denoise(img: &DynamicImage, method: DenoiseMethod, radius: u32, strength: f32) -> DynamicImage {
+    let rgba = img.to_rgba8();
+    let radius = radius.min(rgba.width().max(rgba.height()));
+    match method {
+        DenoiseMethod::Median => DynamicImage::ImageRgba8(imageproc::filter::median_filter(&rgba, radius, radius)),
+        DenoiseMethod::Bilateral => DynamicImage::ImageRgba8(bilateral_filter(&rgba, radius, strength)),
+    }
+}
+
+// Edge-preserving smoothing: each neighbour within `radius` is weighted by
+// its distance and by how far its color is from the center pixel, with
+// `sigma_color` setting how different a color can be and still count.
+// imageproc's version only handles grayscale. Alpha is left untouched.
+fn bilateral_filter(rgba: &RgbaImage, radius: u32, sigma_color: f32) -> RgbaImage {
+    let (width, height) = rgba.dimensions();
+    let radius = radius as i32;
+    let sigma_spatial = (radius as f32 / 2.0).max(0.5);
+    let sigma_color = sigma_color.max(1.0);
+
+    let spatial: Vec<f32> = (-radius..=radius)
+        .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+        .map(|(dx, dy)| (-((dx * dx + dy * dy) as f32) / (2.0 * sigma_spatial * sigma_spatial)).exp())
+        .collect();
+    let color_weight = |distance_sq: f32| (-distance_sq / (2.0 * sigma_color * sigma_color)).exp();
+
+    let mut output = rgba.clone();
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let center = rgba.get_pixel(x as u32, y as u32);
+            let mut sum = [0f32; 3];
+            let mut total = 0f32;
+            let mut index = 0;
+
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let nx = (x + dx).clamp(0, width as i32 - 1) as u32;
+                    let ny = (y + dy).clamp(0, height as i32 - 1) as u32;
+                    let neighbour = rgba.get_pixel(nx, ny);
+                    let distance_sq: f32 = (0..3)
+                        .map(|c| (neighbour[c] as f32 - center[c] as f32).powi(2))
+                        .sum();
+                    let weight = spatial[index] * color_weight(distance_sq);
+                    for c in 0..3 {
+                        sum[c] += neighbour[c] as f32 * weight;
+                    }
+                    total += weight;
+                    index += 1;
+                }
+            }
+
+            let pixel = output.get_pixel_mut(x as u32, y as u32);
+            for c in 0..3 {
+                pixel[c] = (sum[c] / total).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    output
+}
+
+// Approximate a Gaussian blur with three successive box blurs. Each pass is
+// a running sum, so the cost is independent of sigma.
+pub fn box_blur(img: &DynamicImage, sigma: f32) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    let (width, height) = (rgba.width() as usize, rgba.height() as usize);
+    if sigma <= 0.0 || width == 0 || height == 0 {
+        return DynamicImage::ImageRgba8(rgba);
+    }
+
+    // Box widths whose combined variance matches sigma (3 passes)
+    let passes = 3.0f32;
+    let ideal = (12.0 * sigma * sigma / passes + 1.0).sqrt();
+    let mut lower = ideal.floor() as i32;
+    if lower % 2 == 0 {
+        lower -= 1;
+    }
+    let upper = lower + 2;
+    let lower_f = lower as f32;
+    let lower_count = ((12.0 * sigma * sigma - passes * lower_f * lower_f - 4.0 * passes * lower_f - 3.0 * passes)
+        / (-4.0 * lower_f - 4.0))
+        .round() as i32;
+
+    let buffer: &mut [u8] = &mut rgba;
+    for pass in 0..3 {
+        let size = if pass < lower_count { lower } else { upper };
+        let radius = ((size - 1) / 2).max(0) as usize;
+        box_blur_pass(buffer, width, height, radius, true);
+        box_blur_pass(buffer, width, height, radius, false);
+    }
+
+    DynamicImage::ImageRgba8(rgba)
+}
+
+fn box_blur_pass(buffer: &mut [u8], width: usize, height: usize, radius: usize, horizontal: bool) {
+    if radius == 0 {
+        return;
+    }
+    let (lines, length) = if horizontal { (height, width) } else { (width, height) };
+    let index = |line: usize, pos: usize| {
+        if horizontal { (line * width + pos) * 4 } else { (pos * width + line) * 4 }
+    };
+    let window = (2 * radius + 1) as u32;
+    let mut source = vec![0u8; length * 4];
+
+    for line in 0..lines {
+        for pos in 0..length {
+            let i = index(line, pos);
+            source[pos * 4..pos * 4 + 4].copy_from_slice(&buffer[i..i + 4]);
+        }
+        let at = |pos: isize, channel: usize| {
+            source[pos.clamp(0, length as isize - 1) as usize * 4 + channel] as u32
+        };
+
+        for channel in 0..4 {
+            let mut sum: u32 = (-(radius as isize)..=radius as isize).map(|p| at(p, channel)).sum();
+            for pos in 0..length {
+                buffer[index(line, pos) + channel] = ((sum + window / 2) / window) as u8;
+                let p = pos as isize;
+                sum = sum + at(p + radius as isize + 1, channel) - at(p - radius as isize, channel);
+            }
+        }
+    }
+}
+
+// Rotate clockwise by an arbitrary angle. The canvas grows to fit the
+// rotated image and the uncovered corners are transparent.
+pub fn rotate_arbitrary(img: &DynamicImage, degrees: f32, interpolation: Interpolation) -> DynamicImage {
+    use imageproc::geometric_transformations::{rotate_about_center, Interpolation as Interp};
+
+    let radians = degrees.to_radians();
+    let (width, height) = img.dimensions();
+    let (sin, cos) = (radians.sin().abs(), radians.cos().abs());
+    let new_width = (width as f32 * cos + height as f32 * sin).round().max(1.0) as u32;
+    let new_height = (width as f32 * sin + height as f32 * cos).round().max(1.0) as u32;
+
+    let mut canvas = RgbaImage::new(new_width.max(width), new_height.max(height));
+    let offset_x = (canvas.width() - width) / 2;
+    let offset_y = (canvas.height() - height) / 2;
+    image::imageops::overlay(&mut canvas, &img.to_rgba8(), offset_x as i64, offset_y as i64);
+
+    let interpolation = match interpolation {
+        Interpolation::Nearest => Interp::Nearest,
+        Interpolation::Bilinear => Interp::Bilinear,
+        Interpolation::Bicubic => Interp::Bicubic,
+    };
+    let rotated = rotate_about_center(&canvas, radians, interpolation, image::Rgba([0, 0, 0, 0]));
+
+    // Trim back to the rotated bounding box
+    let crop_x = (rotated.width() - new_width) / 2;
+    let crop_y = (rotated.height() - new_height) / 2;
+    DynamicImage::ImageRgba8(rotated).crop_imm(crop_x, crop_y, new_width, new_height)
+}
+
+// Largest axis-aligned rectangle that fits inside a `width` x `height`
+// image rotated by `degrees`, so a straightened photo has no empty corners
+pub fn largest_inscribed_rect(width: u32, height: u32, degrees: f32) -> (f32, f32) {
+    let (w, h) = (width as f32, height as f32);
+    let radians = degrees.to_radians();
+    let (sin, cos) = (radians.sin().abs(), radians.cos().abs());
+    let (long, short) = if w >= h { (w, h) } else { (h, w) };
+
+    if short <= 2.0 * sin * cos * long || (sin - cos).abs() < 1e-6 {
+        // Half-constrained: two corners of the rectangle touch the longer sides
+        let x = 0.5 * short;
+        if w >= h { (x / sin, x / cos) } else { (x / cos, x / sin) }
+    } else {
+        // Fully constrained: all four corners touch the rotated edges
+        let cos_2a = cos * cos - sin * sin;
+        ((w * cos - h * sin) / cos_2a, (h * cos - w * sin) / cos_2a)
+    }
+}
+
+// Bounding box (x, y, width, height) of everything that isn't border. With
+// `alpha`, border pixels are transparent ones (alpha <= `tolerance`);
+// otherwise they're within `tolerance` of the top-left pixel on every
+// channel. None if the whole image is border.
+pub fn content_bounds(img: &DynamicImage, alpha: bool, tolerance: u8) -> Option<(u32, u32, u32, u32)> {
+    let rgba = img.to_rgba8();
+    let background = *rgba.get_pixel(0, 0);
+    let is_border = |pixel: &image::Rgba<u8>| {
+        if alpha {
+            pixel[3] <= tolerance
+        } else {
+            (0..4).all(|c| pixel[c].abs_diff(background[c]) <= tolerance)
+        }
+    };
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        if !is_border(pixel) {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+
+    (min_x != u32::MAX).then(|| (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+}
+
+// Map the quadrilateral `corners` (top-left, top-right, bottom-right,
+// bottom-left) onto a `width` x `height` rectangle. Without a size, the
+// longer of each pair of opposite edges is used.
+pub fn perspective_warp(
+    img: &DynamicImage,
+    corners: [(f32, f32); 4],
+    size: Option<(u32, u32)>,
+    interpolation: Interpolation,
+) -> Result<DynamicImage, AppError> {
+    use imageproc::geometric_transformations::{warp_into, Interpolation as Interp, Projection};
+
+    let distance = |a: (f32, f32), b: (f32, f32)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+    let [top_left, top_right, bottom_right, bottom_left] = corners;
+    let (width, height) = size.unwrap_or_else(|| {
+        let width = distance(top_left, top_right).max(distance(bottom_left, bottom_right));
+        let height = distance(top_left, bottom_left).max(distance(top_right, bottom_right));
+        (width.round() as u32, height.round() as u32)
+    });
+    if width == 0 || height == 0 {
+        return Err(AppError::InvalidFieldValue("Output rectangle must be at least 1 pixel".to_string()));
+    }
+    validate_dimensions(width, height)?;
+
+    let target = [
+        (0.0, 0.0),
+        (width as f32, 0.0),
+        (width as f32, height as f32),
+        (0.0, height as f32),
+    ];
+    let projection = Projection::from_control_points(corners, target).ok_or_else(|| {
+        AppError::InvalidFieldValue("Corner points don't form a valid quadrilateral".to_string())
+    })?;
+
+    let interpolation = match interpolation {
+        Interpolation::Nearest => Interp::Nearest,
+        Interpolation::Bilinear => Interp::Bilinear,
+        Interpolation::Bicubic => Interp::Bicubic,
+    };
+    let mut output = RgbaImage::new(width, height);
+    warp_into(&img.to_rgba8(), &projection, interpolation, image::Rgba([0, 0, 0, 0]), &mut output);
+    Ok(DynamicImage::ImageRgba8(output))
+}
+
+// Frame the image with `width` pixels of `color`. A translucent color stays
+// translucent; the image itself is copied in unchanged.
+pub fn add_border(img: &DynamicImage, width: u32, color: image::Rgba<u8>) -> Result<DynamicImage, AppError> {
+    let (img_width, img_height) = img.dimensions();
+    let (canvas_width, canvas_height) = (img_width + 2 * width, img_height + 2 * width);
+    validate_dimensions(canvas_width, canvas_height)?;
+
+    let mut canvas = RgbaImage::from_pixel(canvas_width, canvas_height, color);
+    image::imageops::replace(&mut canvas, &img.to_rgba8(), width as i64, width as i64);
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+pub const MAX_SHADOW_BLUR: f32 = 100.0;
+
+// Composite the image over a blurred, offset copy of its own alpha filled
+// with `color`. The canvas grows so the shadow isn't clipped, which is why
+// blur is capped and neither offset may exceed the image's size.
+pub fn add_drop_shadow(
+    img: &DynamicImage,
+    color: image::Rgba<u8>,
+    blur: f32,
+    offset_x: i32,
+    offset_y: i32,
+) -> Result<DynamicImage, AppError> {
+    let source = img.to_rgba8();
+    let (width, height) = source.dimensions();
+    if !(0.0..=MAX_SHADOW_BLUR).contains(&blur) {
+        return Err(AppError::InvalidFieldValue(format!(
+            "Invalid shadow_blur: {} (expected 0 to {})",
+            blur, MAX_SHADOW_BLUR
+        )));
+    }
+    if offset_x.unsigned_abs() > width || offset_y.unsigned_abs() > height {
+        return Err(AppError::InvalidFieldValue(format!(
+            "Shadow offset {},{} is larger than the {}x{} image",
+            offset_x, offset_y, width, height
+        )));
+    }
+
+    let margin = (blur * 3.0).ceil() as u32;
+    let left = margin + (-offset_x).max(0) as u32;
+    let right = margin + offset_x.max(0) as u32;
+    let top = margin + (-offset_y).max(0) as u32;
+    let bottom = margin + offset_y.max(0) as u32;
+    let (canvas_width, canvas_height) = (width + left + right, height + top + bottom);
+    validate_dimensions(canvas_width, canvas_height)?;
+
+    // Fill with the shadow color at zero alpha so blurring only spreads alpha
+    let [r, g, b, color_alpha] = color.0;
+    let mut canvas = RgbaImage::from_pixel(canvas_width, canvas_height, image::Rgba([r, g, b, 0]));
+    let shadow_x = (left as i32 + offset_x) as u32;
+    let shadow_y = (top as i32 + offset_y) as u32;
+    for (x, y, pixel) in source.enumerate_pixels() {
+        canvas.get_pixel_mut(x + shadow_x, y + shadow_y)[3] = pixel[3];
+    }
+
+    if blur > 0.0 {
+        canvas = image::imageops::blur(&canvas, blur);
+    }
+    for pixel in canvas.pixels_mut() {
+        pixel[3] = (pixel[3] as u32 * color_alpha as u32 / 255) as u8;
+    }
+
+    image::imageops::overlay(&mut canvas, &source, left as i64, top as i64);
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+// Scale to a print's pixel size. Fill center-crops to the paper's aspect
+// first so the result is exactly `width`x`height`; fit scales the whole image
+// to fit inside.
+pub fn resize_for_print(img: &DynamicImage, width: u32, height: u32, fit: PrintFit) -> Result<DynamicImage, AppError> {
+    let (src_width, src_height) = img.dimensions();
+    match fit {
+        PrintFit::Fill => {
+            let scale = (src_width as f64 / width as f64).min(src_height as f64 / height as f64);
+            let crop_width = ((width as f64 * scale).round() as u32).clamp(1, src_width);
+            let crop_height = ((height as f64 * scale).round() as u32).clamp(1, src_height);
+            let cropped = img.crop_imm((src_width - crop_width) / 2, (src_height - crop_height) / 2, crop_width, crop_height);
+            resize_image_fast(&cropped, width, height)
+        }
+        PrintFit::Fit => {
+            let scale = (width as f64 / src_width as f64).min(height as f64 / src_height as f64);
+            let fit_width = ((src_width as f64 * scale).round() as u32).clamp(1, width);
+            let fit_height = ((src_height as f64 * scale).round() as u32).clamp(1, height);
+            resize_image_fast(img, fit_width, fit_height)
+        }
+    }
+}
+
+// Center-crop to a square, scale to `size` and cut out an anti-aliased
+// circle, optionally framed by a `ring_width` border in `ring_color`
+pub fn circular_avatar(
+    img: &DynamicImage,
+    size: u32,
+    ring_width: u32,
+    ring_color: image::Rgba<u8>,
+) -> Result<DynamicImage, AppError> {
+    let (width, height) = img.dimensions();
+    let side = width.min(height);
+    let square = img.crop_imm((width - side) / 2, (height - side) / 2, side, side);
+    let mut avatar = resize_image_fast(&square, size, size)?.to_rgba8();
+
+    let radius = size as f32 / 2.0;
+    let ring_inner = radius - ring_width as f32;
+    for (x, y, pixel) in avatar.enumerate_pixels_mut() {
+        let dx = x as f32 + 0.5 - radius;
+        let dy = y as f32 + 0.5 - radius;
+        let distance = (dx * dx + dy * dy).sqrt();
+
+        // Fraction of the pixel inside the circle / inside the ring
+        let coverage = (radius - distance + 0.5).clamp(0.0, 1.0);
+        if ring_width > 0 {
+            let ring = (distance - ring_inner + 0.5).clamp(0.0, 1.0) * ring_color[3] as f32 / 255.0;
+            for c in 0..3 {
+                pixel[c] = (pixel[c] as f32 * (1.0 - ring) + ring_color[c] as f32 * ring).round() as u8;
+            }
+            let alpha = pixel[3] as f32 / 255.0;
+            pixel[3] = ((alpha + (1.0 - alpha) * ring) * 255.0).round() as u8;
+        }
+        pixel[3] = (pixel[3] as f32 * coverage).round() as u8;
+    }
+
+    Ok(DynamicImage::ImageRgba8(avatar))
+}
+
+// One .ico holding a PNG-compressed entry per size. Each entry is the image
+// fitted into a transparent square, so non-square logos aren't cropped.
+pub fn encode_ico(img: &DynamicImage, sizes: &[u32]) -> Result<Vec<u8>, AppError> {
+    use image::codecs::ico::{IcoEncoder, IcoFrame};
+
+    let encode_error = |e: ImageError| AppError::ImageProcessingError(format!("Failed to encode ICO: {}", e));
+    let (width, height) = img.dimensions();
+    let frames = sizes
+        .iter()
+        .map(|&size| {
+            let scale = size as f32 / width.max(height) as f32;
+            let fitted = resize_image_fast(img, (width as f32 * scale).round() as u32, (height as f32 * scale).round() as u32)?;
+            let square = pad_to_size(&fitted, size, size, PadMode::Color, image::Rgba([0, 0, 0, 0])).to_rgba8();
+            IcoFrame::as_png(square.as_raw(), size, size, image::ExtendedColorType::Rgba8).map_err(encode_error)
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    let mut output = Vec::new();
+    IcoEncoder::new(&mut output).encode_images(&frames).map_err(encode_error)?;
+    Ok(output)
+}
+
+// Animated GIF or WebP from equally sized frames, each paired with how long
+// it shows in milliseconds, looping forever. WebP frames are lossless so
+// pixel art keeps hard edges; GIF frames get their own 256-color palette.
+pub fn encode_animation(frames: &[(RgbaImage, u32)], format: ImageFormat) -> Result<Vec<u8>, AppError> {
+    let Some((first, _)) = frames.first() else {
+        return Err(AppError::InvalidFieldValue("An animation needs at least one frame".to_string()));
+    };
+    let (width, height) = first.dimensions();
+
+    match format {
+        ImageFormat::Gif => {
+            use image::codecs::gif::{GifEncoder, Repeat};
+            use image::{Delay, Frame};
+
+            let encode_error = |e: ImageError| AppError::ImageProcessingError(format!("Failed to encode GIF: {}", e));
+            let mut output = Vec::new();
+            {
+                let mut encoder = GifEncoder::new_with_speed(&mut output, 10);
+                encoder.set_repeat(Repeat::Infinite).map_err(encode_error)?;
+                encoder
+                    .encode_frames(frames.iter().map(|(frame, delay_ms)| {
+                        Frame::from_parts(frame.clone(), 0, 0, Delay::from_numer_denom_ms(*delay_ms, 1))
+                    }))
+                    .map_err(encode_error)?;
+            }
+            Ok(output)
+        }
+        ImageFormat::Webp => {
+            let encode_error = || AppError::ImageProcessingError("Failed to encode animated WebP".to_string());
+            let mut config = webp::WebPConfig::new().map_err(|_| encode_error())?;
+            config.lossless = 1;
+            let mut encoder = webp::AnimEncoder::new(width, height, &config);
+            encoder.set_loop_count(0);
+            let mut timestamp = 0u64;
+            for (frame, delay_ms) in frames {
+                let start = i32::try_from(timestamp).map_err(|_| encode_error())?;
+                encoder.add_frame(webp::AnimFrame::from_rgba(frame.as_raw(), width, height, start));
+                timestamp += *delay_ms as u64;
+            }
+            Ok(encoder.try_encode().map_err(|_| encode_error())?.to_vec())
+        }
+        _ => Err(AppError::InvalidFieldValue(format!(
+            "Invalid format '{}'. Expected gif or webp",
+            format.extension()
+        ))),
+    }
+}
+
+// Every frame of an animated GIF, composited onto the full canvas, with its
+// delay in milliseconds
+pub fn decode_gif_frames(data: &[u8]) -> Result<Vec<(RgbaImage, u32)>, AppError> {
+    use image::codecs::gif::GifDecoder;
+    use image::{AnimationDecoder, ImageDecoder};
+
+    let decoder = GifDecoder::new(Cursor::new(data)).map_err(decode_error)?;
+    let (width, height) = decoder.dimensions();
+    validate_dimensions(width, height)?;
+
+    let mut frames = Vec::new();
+    for frame in decoder.into_frames() {
+        let frame = frame.map_err(decode_error)?;
+        validate_frame_count(width, height, frames.len() as u64 + 1)?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        frames.push((frame.into_buffer(), numer / denom.max(1)));
+    }
+    Ok(frames)
+}
+
+// Keep `target` evenly spaced frames. Each kept frame stays up for the
+// frames it replaces as well, so the loop runs as long as before.
+pub fn sample_frames(frames: Vec<(RgbaImage, u32)>, target: usize) -> Vec<(RgbaImage, u32)> {
+    let count = frames.len();
+    if target == 0 || target >= count {
+        return frames;
+    }
+
+    let starts: Vec<usize> = (0..target).map(|k| k * count / target).collect();
+    let mut delays = vec![0u32; target];
+    let mut kept = 0;
+    for (i, (_, delay)) in frames.iter().enumerate() {
+        if kept + 1 < target && i >= starts[kept + 1] {
+            kept += 1;
+        }
+        delays[kept] = delays[kept].saturating_add(*delay);
+    }
+
+    frames
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| starts.binary_search(i).is_ok())
+        .map(|(_, (frame, _))| frame)
+        .zip(delays)
+        .collect()
+}
+
+// Snap a frame to at most `colors` colors so the GIF encoder can store it
+// with a palette that small. Fully transparent pixels are left alone; the
+// encoder maps them all to one transparent index.
+pub fn reduce_frame_colors(frame: &mut RgbaImage, colors: u32) {
+    let max_colors = colors.clamp(2, 256) as usize;
+    if exact_palette(frame, max_colors).is_some() {
+        return;
+    }
+
+    let quantizer = NeuQuant::new(10, max_colors, frame.as_raw());
+    let palette = quantizer.color_map_rgba();
+    for pixel in frame.pixels_mut() {
+        if pixel[3] == 0 {
+            continue;
+        }
+        let index = quantizer.index_of(&pixel.0) * 4;
+        pixel.0.copy_from_slice(&palette[index..index + 4]);
+        pixel[3] = 255;
+    }
+}
+
+// Before/after composite for review. `after` is scaled to `before`'s size
+// first; overlay mixes every channel (alpha too) by `alpha`, so 0 gives
+// `before` and 1 gives `after`.
+pub fn compare_visual(before: &DynamicImage, after: &DynamicImage, mode: CompareMode, alpha: f32) -> Result<DynamicImage, AppError> {
+    let (width, height) = before.dimensions();
+    let after = if after.dimensions() == (width, height) {
+        after.to_rgba8()
+    } else {
+        resize_image_fast(after, width, height)?.to_rgba8()
+    };
+    let before = before.to_rgba8();
+
+    Ok(DynamicImage::ImageRgba8(match mode {
+        CompareMode::Overlay => {
+            let mut blended = before;
+            for (pixel, over) in blended.pixels_mut().zip(after.pixels()) {
+                for c in 0..4 {
+                    pixel[c] = (pixel[c] as f32 * (1.0 - alpha) + over[c] as f32 * alpha).round() as u8;
+                }
+            }
+            blended
+        }
+        CompareMode::SideBySide => {
+            let mut canvas = RgbaImage::new(width * 2, height);
+            image::imageops::replace(&mut canvas, &before, 0, 0);
+            image::imageops::replace(&mut canvas, &after, width as i64, 0);
+            canvas
+        }
+    }))
+}
+
+// Reinhard color transfer: shift and scale each Lab channel of `source` so
+// its mean and standard deviation match `reference`'s. Alpha is kept.
+pub fn color_transfer(source: &DynamicImage, reference: &DynamicImage) -> DynamicImage {
+    // Per-channel Lab mean and standard deviation, from running sums so no
+    // Lab copy of the image is kept
+    let stats = |img: &image::RgbaImage| {
+        let mut sum = [0f64; 3];
+        let mut sum_sq = [0f64; 3];
+        for p in img.pixels() {
+            let lab = rgb_to_lab([p[0], p[1], p[2]]);
+            for c in 0..3 {
+                sum[c] += lab[c] as f64;
+                sum_sq[c] += (lab[c] as f64).powi(2);
+            }
+        }
+        let n = img.pixels().len().max(1) as f64;
+        let mean = sum.map(|s| s / n);
+        let std: [f64; 3] = std::array::from_fn(|c| (sum_sq[c] / n - mean[c].powi(2)).max(0.0).sqrt());
+        (mean.map(|m| m as f32), std.map(|s| s as f32))
+    };
+
+    let mut output = source.to_rgba8();
+    let (source_mean, source_std) = stats(&output);
+    let (reference_mean, reference_std) = stats(&reference.to_rgba8());
+
+    // A flat source channel has nothing to scale, so it is only shifted
+    let gain: [f32; 3] = std::array::from_fn(|c| {
+        if source_std[c] > 1e-3 { reference_std[c] / source_std[c] } else { 1.0 }
+    });
+    for pixel in output.pixels_mut() {
+        let lab = rgb_to_lab([pixel[0], pixel[1], pixel[2]]);
+        let shifted: [f32; 3] = std::array::from_fn(|c| (lab[c] - source_mean[c]) * gain[c] + reference_mean[c]);
+        let [r, g, b] = lab_to_rgb([shifted[0].clamp(0.0, 100.0), shifted[1], shifted[2]]);
+        *pixel = image::Rgba([r, g, b, pixel[3]]);
+    }
+    DynamicImage::ImageRgba8(output)
+}
+
+// Center `img` on a `width` x `height` canvas and fill the bars around it
+pub fn pad_to_size(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    mode: PadMode,
+    color: image::Rgba<u8>,
+) -> DynamicImage {
+    let source = img.to_rgba8();
+    let (img_width, img_height) = source.dimensions();
+    let left = width.saturating_sub(img_width) / 2;
+    let top = height.saturating_sub(img_height) / 2;
+
+    // Map a canvas coordinate, relative to the image, back into the image
+    let reflect = |pos: i64, len: u32| {
+        let period = 2 * len as i64;
+        let m = pos.rem_euclid(period);
+        (if m < len as i64 { m } else { period - 1 - m }) as u32
+    };
+    let edge = |pos: i64, len: u32| pos.clamp(0, len as i64 - 1) as u32;
+
+    let mut canvas = match mode {
+        PadMode::Color => RgbaImage::from_pixel(width, height, color),
+        PadMode::Reflect | PadMode::Edge => {
+            let map = if mode == PadMode::Reflect { &reflect as &dyn Fn(i64, u32) -> u32 } else { &edge };
+            RgbaImage::from_fn(width, height, |x, y| {
+                *source.get_pixel(
+                    map(x as i64 - left as i64, img_width),
+                    map(y as i64 - top as i64, img_height),
+                )
+            })
+        }
+        PadMode::Blur => {
+            // Blurred enough that only the overall colors carry into the bars
+            let cover = img.resize_to_fill(width, height, image::imageops::FilterType::Triangle).to_rgba8();
+            let sigma = (width.max(height) as f32 / 30.0).max(2.0);
+            image::imageops::fast_blur(&cover, sigma)
+        }
+    };
+
+    image::imageops::overlay(&mut canvas, &source, left as i64, top as i64);
+    DynamicImage::ImageRgba8(canvas)
+}
+
+const FONT_DATA: &[u8] = include_bytes!("../assets/DejaVuSans.ttf");
+
+fn load_font() -> Result<ab_glyph::FontRef<'static>, AppError> {
+    ab_glyph::FontRef::try_from_slice(FONT_DATA)
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to load font: {}", e)))
+}
+
+// Render `text` at `font_size` px in one corner (or the center), blended at
+// `opacity`
+pub fn draw_watermark_text(
+    img: &DynamicImage,
+    text: &str,
+    font_size: f32,
+    color: image::Rgba<u8>,
+    opacity: f32,
+    position: &str,
+) -> Result<DynamicImage, AppError> {
+    let font = load_font()?;
+    let (width, height) = img.dimensions();
+    let (text_width, text_height) = imageproc::drawing::text_size(font_size, &font, text);
+    // A long enough string is wider than any image, so check before allocating
+    validate_dimensions(text_width, text_height)?;
+    let margin = (font_size / 2.0) as i64;
+
+    let right = width as i64 - text_width as i64 - margin;
+    let bottom = height as i64 - text_height as i64 - margin;
+    let (x, y) = match position {
+        "top-left" => (margin, margin),
+        "top-right" => (right, margin),
+        "bottom-left" => (margin, bottom),
+        "bottom-right" => (right, bottom),
+        "center" => ((width as i64 - text_width as i64) / 2, (height as i64 - text_height as i64) / 2),
+        _ => return Err(AppError::InvalidFieldValue(format!("Invalid position: {}", position))),
+    };
+
+    // Draw onto a transparent layer so opacity applies to the glyph coverage
+    let [r, g, b, a] = color.0;
+    let mut layer = RgbaImage::from_pixel(text_width.max(1), text_height.max(1), image::Rgba([r, g, b, 0]));
+    imageproc::drawing::draw_text_mut(&mut layer, image::Rgba([r, g, b, 255]), 0, 0, font_size, &font, text);
+    let alpha_scale = opacity.clamp(0.0, 1.0) * a as f32 / 255.0;
+    for pixel in layer.pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * alpha_scale).round() as u8;
+    }
+
+    let mut canvas = img.to_rgba8();
+    image::imageops::overlay(&mut canvas, &layer, x, y);
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+// Fill the part of a rectangle that lands on the canvas. Edges are worked
+// out in i64 so far-off coordinates can't overflow.
+fn fill_clipped_rect(canvas: &mut RgbaImage, x: i64, y: i64, w: i64, h: i64, color: image::Rgba<u8>) {
+    use imageproc::drawing::draw_filled_rect_mut;
+    use imageproc::rect::Rect;
+
+    let (left, top) = (x.max(0), y.max(0));
+    let right = (x + w).min(canvas.width() as i64);
+    let bottom = (y + h).min(canvas.height() as i64);
+    if left < right && top < bottom {
+        let rect = Rect::at(left as i32, top as i32).of_size((right - left) as u32, (bottom - top) as u32);
+        draw_filled_rect_mut(canvas, rect, color);
+    }
+}
+
+// Draw rectangles, lines and text on top of the image, in order. Text size
+// and stroke width are capped at the image's longer side; anything bigger
+// would only cost time and memory to draw off the canvas.
+pub fn draw_shapes(img: &DynamicImage, shapes: &[Shape]) -> Result<DynamicImage, AppError> {
+    use imageproc::drawing::{draw_filled_circle_mut, draw_line_segment_mut, draw_polygon_mut, draw_text_mut};
+    use imageproc::point::Point;
+
+    let mut canvas = img.to_rgba8();
+    let mut font = None;
+    let longest_side = canvas.width().max(canvas.height()).max(1);
+
+    for shape in shapes {
+        match shape {
+            Shape::Rect { x, y, w, h, color, fill, stroke_width } => {
+                if *w == 0 || *h == 0 {
+                    return Err(AppError::InvalidFieldValue("Rectangle width and height must be at least 1".to_string()));
+                }
+                let color = parse_color(color)?;
+                let (x, y, w, h) = (*x as i64, *y as i64, *w as i64, *h as i64);
+                if *fill {
+                    fill_clipped_rect(&mut canvas, x, y, w, h, color);
+                } else {
+                    // Outline as four bands so the stroke grows inward
+                    let stroke = (*stroke_width as i64).clamp(1, w.min(h));
+                    fill_clipped_rect(&mut canvas, x, y, w, stroke, color);
+                    fill_clipped_rect(&mut canvas, x, y + h - stroke, w, stroke, color);
+                    fill_clipped_rect(&mut canvas, x, y, stroke, h, color);
+                    fill_clipped_rect(&mut canvas, x + w - stroke, y, stroke, h, color);
+                }
+            }
+            Shape::Line { x1, y1, x2, y2, color, stroke_width } => {
+                let color = parse_color(color)?;
+                let stroke_width = (*stroke_width).min(longest_side);
+                let half = stroke_width as f32 / 2.0;
+                let (dx, dy) = (x2 - x1, y2 - y1);
+                let length = (dx * dx + dy * dy).sqrt();
+                if stroke_width <= 1 {
+                    draw_line_segment_mut(&mut canvas, (*x1, *y1), (*x2, *y2), color);
+                } else if length < 1.0 {
+                    draw_filled_circle_mut(&mut canvas, (*x1 as i32, *y1 as i32), half as i32, color);
+                } else {
+                    // Thick lines are the quad around the segment
+                    let (nx, ny) = (-dy / length * half, dx / length * half);
+                    let corners = [(x1 + nx, y1 + ny), (x2 + nx, y2 + ny), (x2 - nx, y2 - ny), (x1 - nx, y1 - ny)];
+                    let mut poly: Vec<Point<i32>> = Vec::with_capacity(corners.len());
+                    for (px, py) in corners {
+                        let point = Point::new(px.round() as i32, py.round() as i32);
+                        if poly.last() != Some(&point) {
+                            poly.push(point);
+                        }
+                    }
+                    if poly.len() > 2 && poly.first() != poly.last() {
+                        draw_polygon_mut(&mut canvas, &poly, color);
+                    } else {
+                        draw_line_segment_mut(&mut canvas, (*x1, *y1), (*x2, *y2), color);
+                    }
+                }
+            }
+            Shape::Text { x, y, text, color, size } => {
+                let color = parse_color(color)?;
+                if font.is_none() {
+                    font = Some(load_font()?);
+                }
+                if let Some(font) = &font {
+                    draw_text_mut(&mut canvas, color, *x, *y, size.clamp(1.0, longest_side as f32), font, text);
+                }
+            }
+        }
+    }
+
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+// Shelf-pack `sprites` onto a transparent sheet, tallest first, with
+// `padding` pixels around and between them. The sheet is roughly square.
+// Returns the sheet and each sprite's top-left corner, in input order.
+pub fn pack_sprites(sprites: &[DynamicImage], padding: u32) -> Result<(DynamicImage, Vec<(u32, u32)>), AppError> {
+    let padding = padding as u64;
+    let area: u64 = sprites
+        .iter()
+        .map(|s| (s.width() as u64 + padding) * (s.height() as u64 + padding))
+        .sum();
+    let widest = sprites.iter().map(|s| s.width() as u64).max().unwrap_or(0);
+    let sheet_width = ((area as f64).sqrt().ceil() as u64 + padding).max(widest + 2 * padding);
+
+    let mut order: Vec<usize> = (0..sprites.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sprites[i].height()));
+
+    let mut positions = vec![(0u64, 0u64); sprites.len()];
+    let (mut x, mut y, mut shelf_height, mut used_width) = (padding, padding, 0u64, 0u64);
+    for &i in &order {
+        let (width, height) = (sprites[i].width() as u64, sprites[i].height() as u64);
+        if x + width + padding > sheet_width && x > padding {
+            x = padding;
+            y += shelf_height + padding;
+            shelf_height = 0;
+        }
+        positions[i] = (x, y);
+        x += width + padding;
+        used_width = used_width.max(x);
+        shelf_height = shelf_height.max(height);
+    }
+    let sheet_height = y + shelf_height + padding;
+
+    if used_width > u32::MAX as u64 || sheet_height > u32::MAX as u64 {
+        return Err(AppError::DimensionsTooLarge(format!("{}x{} sprite sheet", used_width, sheet_height)));
+    }
+    validate_dimensions(used_width as u32, sheet_height as u32)?;
+
+    let mut sheet = RgbaImage::new(used_width as u32, sheet_height as u32);
+    for (sprite, &(x, y)) in sprites.iter().zip(&positions) {
+        image::imageops::replace(&mut sheet, &sprite.to_rgba8(), x as i64, y as i64);
+    }
+
+    let positions = positions.into_iter().map(|(x, y)| (x as u32, y as u32)).collect();
+    Ok((DynamicImage::ImageRgba8(sheet), positions))
+}
+
+// Tile `images` into a grid of `columns` cells, each `thumb_size` square and
+// separated by `gap`. Thumbnails keep their aspect ratio and are centered in
+// their cell. With `labels`, each cell gets its file name underneath.
+pub fn contact_sheet(
+    images: &[(String, DynamicImage)],
+    columns: u32,
+    thumb_size: u32,
+    gap: u32,
+    background: image::Rgba<u8>,
+    labels: bool,
+) -> Result<DynamicImage, AppError> {
+    let columns = columns.clamp(1, images.len().max(1) as u32);
+    let rows = (images.len() as u32).div_ceil(columns);
+    let font_size = (thumb_size as f32 / 12.0).max(10.0);
+    let label_height = if labels { (font_size * 1.5).ceil() as u32 } else { 0 };
+    let cell_height = thumb_size + label_height;
+
+    let sheet_width = columns as u64 * thumb_size as u64 + (columns as u64 + 1) * gap as u64;
+    let sheet_height = rows as u64 * cell_height as u64 + (rows as u64 + 1) * gap as u64;
+    if sheet_width > u32::MAX as u64 || sheet_height > u32::MAX as u64 {
+        return Err(AppError::DimensionsTooLarge(format!("{}x{} contact sheet", sheet_width, sheet_height)));
+    }
+    let (sheet_width, sheet_height) = (sheet_width as u32, sheet_height as u32);
+    validate_dimensions(sheet_width, sheet_height)?;
+
+    let mut canvas = RgbaImage::from_pixel(sheet_width, sheet_height, background);
+    let font = if labels { Some(load_font()?) } else { None };
+    // Dark text on light backgrounds, light text on dark ones
+    let luma = (background[0] as u32 * 299 + background[1] as u32 * 587 + background[2] as u32 * 114) / 1000;
+    let label_color = if luma > 127 { image::Rgba([0, 0, 0, 255]) } else { image::Rgba([255, 255, 255, 255]) };
+
+    for (index, (name, img)) in images.iter().enumerate() {
+        let column = index as u32 % columns;
+        let row = index as u32 / columns;
+        let cell_x = gap + column * (thumb_size + gap);
+        let cell_y = gap + row * (cell_height + gap);
+
+        let (width, height) = img.dimensions();
+        let scale = thumb_size as f32 / width.max(height) as f32;
+        let thumb_width = ((width as f32 * scale).round() as u32).max(1);
+        let thumb_height = ((height as f32 * scale).round() as u32).max(1);
+        let thumb = resize_image_fast(img, thumb_width, thumb_height)?.to_rgba8();
+        image::imageops::overlay(
+            &mut canvas,
+            &thumb,
+            (cell_x + (thumb_size - thumb_width) / 2) as i64,
+            (cell_y + (thumb_size - thumb_height) / 2) as i64,
+        );
+
+        if let Some(font) = &font {
+            // Drop characters from the end until the name fits the cell
+            let mut label = name.clone();
+            while !label.is_empty() && imageproc::drawing::text_size(font_size, font, &label).0 > thumb_size {
+                label.pop();
+            }
+            let (text_width, _) = imageproc::drawing::text_size(font_size, font, &label);
+            imageproc::drawing::draw_text_mut(
+                &mut canvas,
+                label_color,
+                (cell_x + (thumb_size - text_width) / 2) as i32,
+                (cell_y + thumb_size + (label_height - font_size as u32) / 2) as i32,
+                font_size,
+                font,
+                &label,
+            );
+        }
+    }
+
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+// Convert to the requested channel layout. Modes without alpha are composited
+// onto FLATTEN_BACKGROUND first. Bilevel uses a hard `threshold` when given,
+// otherwise `dither` (Floyd-Steinberg by default).
+pub fn apply_color_mode(
+    img: &DynamicImage,
+    mode: ColorMode,
+    threshold: Option<u8>,
+    dither: Option<Dither>,
+) -> DynamicImage {
+    let opaque = || {
+        if has_transparency(img) {
+            DynamicImage::ImageRgb8(flatten_alpha(img, FLATTEN_BACKGROUND))
+        } else {
+            img.clone()
+        }
+    };
+
+    match mode {
+        ColorMode::Rgb => DynamicImage::ImageRgb8(opaque().to_rgb8()),
+        ColorMode::Rgba => DynamicImage::ImageRgba8(img.to_rgba8()),
+        ColorMode::Grayscale => DynamicImage::ImageLuma8(opaque().to_luma8()),
+        ColorMode::Bilevel => {
+            let gray = opaque().to_luma8();
+            let level = threshold.unwrap_or(128);
+            let dither = if threshold.is_some() { Dither::None } else { dither.unwrap_or(Dither::FloydSteinberg) };
+
+            let rgba = DynamicImage::ImageLuma8(gray.clone()).to_rgba8();
+            let palette = [[0, 0, 0, 255], [255, 255, 255, 255]];
+            let indices = dither_indices(&rgba, &palette, dither, 255.0, |p| (p[0] >= level) as u8);
+            let values = indices.into_iter().map(|i| i * 255).collect();
+            DynamicImage::ImageLuma8(image::GrayImage::from_raw(gray.width(), gray.height(), values).unwrap())
+        }
+    }
+}
+
+// BlurHash with `components_x` x `components_y` (1-9 each) frequencies.
+// Only the lowest frequencies survive, so a small copy hashes the same.
+pub fn encode_blurhash(img: &DynamicImage, components_x: u32, components_y: u32) -> Result<String, AppError> {
+    let small = img.thumbnail(64, 64).to_rgba8();
+    blurhash::encode(components_x, components_y, small.width(), small.height(), small.as_raw())
+        .map_err(|e| AppError::InvalidFieldValue(e.to_string()))
+}
+
+// A few-pixel, slightly blurred PNG to inline while the real image loads
+pub fn placeholder_thumbnail(img: &DynamicImage, size: u32) -> Result<Vec<u8>, AppError> {
+    let thumb = img.thumbnail(size, size).blur(0.5);
+    Ok(encode_image_to_bytes(&thumb, ImageFormat::Png, &EncodeOptions { compression_level: Some(9), ..EncodeOptions::default() })?.data)
+}
+
+// Number of distinct RGBA colors, giving up once there are more than `cap`
+pub fn count_unique_colors(img: &DynamicImage, cap: usize) -> ColorCount {
+    let mut seen = std::collections::HashSet::new();
+    for pixel in img.to_rgba8().pixels() {
+        if seen.insert(pixel.0) && seen.len() > cap {
+            return ColorCount::MoreThan(cap);
+        }
+    }
+    ColorCount::Exact(seen.len())
+}
+
+// Otsu's threshold on the luma channel (after flattening transparency), the
+// level that best separates dark and light pixels
+pub fn otsu_threshold(img: &DynamicImage) -> u8 {
+    let gray = if has_transparency(img) {
+        DynamicImage::ImageRgb8(flatten_alpha(img, FLATTEN_BACKGROUND)).to_luma8()
+    } else {
+        img.to_luma8()
+    };
+    imageproc::contrast::otsu_level(&gray)
+}
+
+const BAYER_4X4: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+// Map every pixel to a palette index with `nearest`, handling the
+// quantization error per `dither`. `spread` is the ordered-dither amplitude
+// and should roughly match the gap between palette levels.
+fn dither_indices(
+    rgba: &RgbaImage,
+    palette: &[[u8; 4]],
+    dither: Dither,
+    spread: f32,
+    nearest: impl Fn([u8; 4]) -> u8,
+) -> Vec<u8> {
+    let (width, height) = rgba.dimensions();
+    let (width, height) = (width as usize, height as usize);
+    let clamp = |values: [f32; 4]| values.map(|v| v.round().clamp(0.0, 255.0) as u8);
+
+    match dither {
+        Dither::None => rgba.pixels().map(|p| nearest(p.0)).collect(),
+        Dither::Ordered => rgba
+            .enumerate_pixels()
+            .map(|(x, y, p)| {
+                let offset = ((BAYER_4X4[y as usize % 4][x as usize % 4] + 0.5) / 16.0 - 0.5) * spread;
+                let mut values = p.0.map(|v| v as f32);
+                for value in &mut values[..3] {
+                    *value += offset;
+                }
+                nearest(clamp(values))
+            })
+            .collect(),
+        Dither::FloydSteinberg => {
+            let mut values: Vec<[f32; 4]> = rgba.pixels().map(|p| p.0.map(|v| v as f32)).collect();
+            let mut indices = Vec::with_capacity(values.len());
+
+            for y in 0..height {
+                for x in 0..width {
+                    let current = values[y * width + x];
+                    let index = nearest(clamp(current));
+                    indices.push(index);
+
+                    let chosen = palette[index as usize];
+                    let error: [f32; 4] = std::array::from_fn(|c| current[c] - chosen[c] as f32);
+                    let mut spread_error = |dx: isize, dy: usize, weight: f32| {
+                        let nx = x as isize + dx;
+                        if nx >= 0 && (nx as usize) < width && y + dy < height {
+                            let target = &mut values[(y + dy) * width + nx as usize];
+                            for c in 0..4 {
+                                target[c] += error[c] * weight;
+                            }
+                        }
+                    };
+                    spread_error(1, 0, 7.0 / 16.0);
+                    spread_error(-1, 1, 3.0 / 16.0);
+                    spread_error(0, 1, 5.0 / 16.0);
+                    spread_error(1, 1, 1.0 / 16.0);
+                }
+            }
+            indices
+        }
+    }
+}
+
+// Encode as an indexed (paletted) PNG with at most `colors` entries.
+// Flat graphics that already fit in the palette keep their exact colors;
+// anything else is quantized with NeuQuant and mapped using `dither`.
+pub fn encode_indexed_png(img: &DynamicImage, colors: u32, dither: Dither) -> Result<Vec<u8>, AppError> {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    if !(2..=256).contains(&colors) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid colors: {} (expected 2-256)", colors)));
+    }
+    let max_colors = colors as usize;
+
+    let (palette, indices) = match exact_palette(&rgba, max_colors) {
+        Some(result) => result,
+        None => {
+            let quantizer = NeuQuant::new(10, max_colors, rgba.as_raw());
+            let palette: Vec<[u8; 4]> = quantizer
+                .color_map_rgba()
+                .chunks_exact(4)
+                .map(|c| [c[0], c[1], c[2], c[3]])
+                .collect();
+            let spread = 255.0 / (max_colors as f32).cbrt();
+            let indices = dither_indices(&rgba, &palette, dither, spread, |p| quantizer.index_of(&p) as u8);
+            (palette, indices)
+        }
+    };
+
+    write_indexed_png(width, height, &palette, &indices)
+}
+
+// Re-encode as PNG, staying indexed when the source was a paletted PNG and
+// the result still fits in that many colors (crops, right-angle rotations,
+// flips). Anything else falls back to truecolor.
+pub fn encode_png_like_source(img: &DynamicImage, source: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, AppError> {
+    if let Some(palette_size) = png_palette_size(source) {
+        let rgba = img.to_rgba8();
+        if let Some((palette, indices)) = exact_palette(&rgba, palette_size.max(2)) {
+            return write_indexed_png(rgba.width(), rgba.height(), &palette, &indices);
+        }
+    }
+
+    Ok(output_image(img, ImageFormat::Png, options)?.data)
+}
+
+// Identify a file from its leading bytes, beyond the formats we decode:
+// HEIF/AVIF by their ISO-BMFF brands, JPEG XL, JPEG 2000, PSD, PDF, SVG,
+// and whatever else image::guess_format knows
+pub fn detect_format(data: &[u8]) -> Option<FormatDetection> {
+    let detection = |mime, extension, confidence| FormatDetection { mime, extension, confidence, decodable: false };
+
+    // ISO-BMFF: a box size, then "ftyp", the major brand, a minor version
+    // and the compatible brands
+    if data.get(4..8) == Some(b"ftyp") {
+        let end = (u32::from_be_bytes(data[..4].try_into().unwrap()) as usize).min(data.len());
+        let compatible = data.get(16..end).unwrap_or_default();
+        let brands: Vec<&[u8]> = data.get(8..12).into_iter().chain(compatible.chunks_exact(4)).collect();
+        if brands.iter().any(|b| matches!(*b, b"avif" | b"avis")) {
+            return Some(detection("image/avif", "avif", "high"));
+        }
+        if brands.iter().any(|b| matches!(*b, b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" | b"hevm" | b"hevs")) {
+            return Some(detection("image/heic", "heic", "high"));
+        }
+        if brands.iter().any(|b| matches!(*b, b"mif1" | b"msf1")) {
+            return Some(detection("image/heif", "heif", "high"));
+        }
+    }
+
+    if let Some(format) = ImageFormat::from_magic_bytes(data) {
+        return Some(FormatDetection {
+            mime: format.mime_type(),
+            extension: format.extension(),
+            confidence: "high",
+            decodable: validate_upload(data).is_ok(),
+        });
+    }
+
+    match data {
+        [0xFF, 0x0A, ..] | [0, 0, 0, 0x0C, b'J', b'X', b'L', b' ', ..] => return Some(detection("image/jxl", "jxl", "high")),
+        [0, 0, 0, 0x0C, b'j', b'P', b' ', b' ', ..] => return Some(detection("image/jp2", "jp2", "high")),
+        [b'8', b'B', b'P', b'S', ..] => return Some(detection("image/vnd.adobe.photoshop", "psd", "high")),
+        [b'%', b'P', b'D', b'F', b'-', ..] => return Some(detection("application/pdf", "pdf", "high")),
+        _ => {}
+    }
+
+    if let Ok(format) = image::guess_format(data) {
+        // PNM is recognized from just "P1".."P7"
+        let confidence = if format == ImageFormatEnum::Pnm { "medium" } else { "high" };
+        return Some(detection(format.to_mime_type(), format.extensions_str().first().copied().unwrap_or(""), confidence));
+    }
+
+    // SVG is XML text: look for the root element near the start, past any
+    // prolog, doctype or comments
+    let head = String::from_utf8_lossy(&data[..data.len().min(4096)]);
+    let head = head.trim_start_matches('\u{FEFF}').trim_start();
+    if head.starts_with('<') && head.contains("<svg") {
+        return Some(detection("image/svg+xml", "svg", "medium"));
+    }
+    None
+}
+
+// Whether the file's compression is lossless. WebP says so in its bitstream
+// chunk: VP8L is lossless, VP8 lossy. None for TIFF and ICO, which can
+// hold either.
+pub fn is_lossless(data: &[u8]) -> Option<bool> {
+    match ImageFormat::from_magic_bytes(data)? {
+        ImageFormat::Png | ImageFormat::Gif | ImageFormat::Bmp => Some(true),
+        ImageFormat::Jpeg => Some(false),
+        ImageFormat::Webp => {
+            let mut pos = 12;
+            while let Some(header) = data.get(pos..pos + 8) {
+                match &header[..4] {
+                    b"VP8L" => return Some(true),
+                    b"VP8 " => return Some(false),
+                    _ => {}
+                }
+                let length = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+                pos += 8 + length + length % 2;
+            }
+            None
+        }
+        ImageFormat::Tiff | ImageFormat::Ico | ImageFormat::Raw => None,
+    }
+}
+
+// Number of palette entries when `data` is an indexed-color PNG. Only the
+// header chunks are read.
+pub fn png_palette_size(data: &[u8]) -> Option<usize> {
+    let reader = png::Decoder::new(Cursor::new(data)).read_info().ok()?;
+    let info = reader.info();
+    if info.color_type != png::ColorType::Indexed {
+        return None;
+    }
+    info.palette.as_ref().map(|palette| palette.len() / 3)
+}
+
+fn write_indexed_png(width: u32, height: u32, palette: &[[u8; 4]], indices: &[u8]) -> Result<Vec<u8>, AppError> {
+    let bit_depth = match palette.len() {
+        0..=2 => png::BitDepth::One,
+        3..=4 => png::BitDepth::Two,
+        5..=16 => png::BitDepth::Four,
+        _ => png::BitDepth::Eight,
+    };
+    let bits = bit_depth as usize;
+    let row_bytes = (width as usize * bits).div_ceil(8);
+    let mut packed = vec![0u8; row_bytes * height as usize];
+    for (i, &index) in indices.iter().enumerate() {
+        let (x, y) = (i % width as usize, i / width as usize);
+        let bit_offset = x * bits;
+        let shift = 8 - bits - (bit_offset % 8);
+        packed[y * row_bytes + bit_offset / 8] |= index << shift;
+    }
+
+    let rgb_palette: Vec<u8> = palette.iter().flat_map(|c| [c[0], c[1], c[2]]).collect();
+    let alphas: Vec<u8> = palette.iter().map(|c| c[3]).collect();
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(bit_depth);
+    encoder.set_palette(rgb_palette);
+    if alphas.iter().any(|&a| a < 255) {
+        encoder.set_trns(alphas);
+    }
+
+    let mut writer = encoder.write_header()
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to encode PNG: {}", e)))?;
+    writer.write_image_data(&packed)
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to encode PNG: {}", e)))?;
+    writer.finish()
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to encode PNG: {}", e)))?;
+
+    Ok(output)
+}
+
+// Build a palette from the image's distinct colors, or None if there are
+// more than `max_colors` of them.
+fn exact_palette(rgba: &RgbaImage, max_colors: usize) -> Option<(Vec<[u8; 4]>, Vec<u8>)> {
+    let mut lookup: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity((rgba.width() * rgba.height()) as usize);
+
+    for pixel in rgba.pixels() {
+        let index = match lookup.get(&pixel.0) {
+            Some(&index) => index,
+            None => {
+                if palette.len() == max_colors {
+                    return None;
+                }
+                let index = palette.len() as u8;
+                palette.push(pixel.0);
+                lookup.insert(pixel.0, index);
+                index
+            }
+        };
+        indices.push(index);
+    }
+
+    Some((palette, indices))
+}
+
+// Number of bits used for the message length header in LSB steganography
+const STEGO_HEADER_BITS: usize = 32;
+
+// Hide `message` in the least significant bits of the RGB channels.
+// The first 32 bits hold the message length in bytes (big-endian).
+pub fn embed_lsb_message(img: &DynamicImage, message: &[u8]) -> Result<DynamicImage, AppError> {
+    let mut rgba = img.to_rgba8();
+    let capacity = stego_capacity(&rgba);
+    if message.len() > capacity {
+        return Err(AppError::InvalidFieldValue(format!(
+            "Message too large for image: {} bytes (capacity {} bytes)",
+            message.len(),
+            capacity
+        )));
+    }
+
+    let length = (message.len() as u32).to_be_bytes();
+    let bits = length
+        .iter()
+        .chain(message.iter())
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+
+    let channels = rgba.pixels_mut().flat_map(|p| p.0[..3].iter_mut());
+    for (channel, bit) in channels.zip(bits) {
+        *channel = (*channel & !1) | bit;
+    }
+
+    Ok(DynamicImage::ImageRgba8(rgba))
+}
+
+// Recover a message hidden by `embed_lsb_message`.
+pub fn extract_lsb_message(img: &DynamicImage) -> Result<Vec<u8>, AppError> {
+    let rgba = img.to_rgba8();
+    let capacity = stego_capacity(&rgba);
+    let mut bits = rgba.pixels().flat_map(|p| p.0[..3].iter().map(|c| c & 1));
+
+    let mut read_byte = || (0..8).fold(0u8, |acc, _| (acc << 1) | bits.next().unwrap_or(0));
+
+    let length = u32::from_be_bytes([read_byte(), read_byte(), read_byte(), read_byte()]) as usize;
+    if length > capacity {
+        return Err(AppError::InvalidFieldValue("No hidden message found in image".to_string()));
+    }
+
+    Ok((0..length).map(|_| read_byte()).collect())
+}
+
+fn stego_capacity(rgba: &RgbaImage) -> usize {
+    let bits = rgba.width() as usize * rgba.height() as usize * 3;
+    bits.saturating_sub(STEGO_HEADER_BITS) / 8
+}
+
+// Color parsing utility
+pub fn parse_color(color_str: &str) -> Result<image::Rgba<u8>, AppError> {
+    match color_str.to_lowercase().as_str() {
+        "white" => Ok(image::Rgba([255, 255, 255, 255])),
+        "black" => Ok(image::Rgba([0, 0, 0, 255])),
+        "red" => Ok(image::Rgba([255, 0, 0, 255])),
+        "green" => Ok(image::Rgba([0, 255, 0, 255])),
+        "blue" => Ok(image::Rgba([0, 0, 255, 255])),
+        "yellow" => Ok(image::Rgba([255, 255, 0, 255])),
+        "cyan" => Ok(image::Rgba([0, 255, 255, 255])),
+        "magenta" => Ok(image::Rgba([255, 0, 255, 255])),
+        _ => {
+            // Hex: #RRGGBB, or #RRGGBBAA with alpha
+            let hex = color_str
+                .strip_prefix('#')
+                .filter(|hex| matches!(hex.len(), 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                .ok_or_else(|| AppError::InvalidFieldValue(format!("Unsupported color: {}", color_str)))?;
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(255);
+            let alpha = if hex.len() == 8 { channel(6) } else { 255 };
+            Ok(image::Rgba([channel(0), channel(2), channel(4), alpha]))
+        }
+    }
+}
+
+// sRGB (D65) to CIE Lab
+pub fn rgb_to_lab(rgb: [u8; 3]) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(rgb[0]), linear(rgb[1]), linear(rgb[2]));
+
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+pub fn lab_to_rgb(lab: [f32; 3]) -> [u8; 3] {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+    let inverse = |t: f32| if t.powi(3) > 0.008856 { t.powi(3) } else { (t - 16.0 / 116.0) / 7.787 };
+    let (x, y, z) = (inverse(fx) * 0.95047, inverse(fy), inverse(fz) * 1.08883);
+
+    let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+    let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+    let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        let c = if c <= 0.0031308 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+        (c * 255.0).round() as u8
+    };
+    [encode(r), encode(g), encode(b)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexed_png_of_flat_graphic_is_smaller_and_identical() {
+        let colors = [[230, 30, 40, 255], [20, 120, 220, 255], [255, 255, 255, 255], [25, 25, 25, 255]];
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(96, 64, |x, y| {
+            image::Rgba(colors[((x / 3) ^ (y / 5)) as usize % 4])
+        }));
+
+        let truecolor = encode_image_to_bytes(&img, ImageFormat::Png, &EncodeOptions::default()).unwrap().data;
+        let indexed = encode_indexed_png(&img, 16, Dither::None).unwrap();
+
+        assert!(indexed.len() < truecolor.len(), "indexed {} >= truecolor {}", indexed.len(), truecolor.len());
+        assert_eq!(load_image_from_bytes(&indexed).unwrap().to_rgba8(), img.to_rgba8());
+    }
+
+    #[test]
+    fn stego_message_round_trips_with_at_most_one_level_of_change() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 30, |x, y| {
+            image::Rgba([(x * 6) as u8, (y * 8) as u8, ((x + y) * 3) as u8, 255])
+        }));
+        let message = "meet at the old mill, 9pm \u{2713}";
+
+        let carrier = embed_lsb_message(&img, message.as_bytes()).unwrap();
+        // Through PNG, as the endpoint returns it
+        let png = encode_image_to_bytes(&carrier, ImageFormat::Png, &EncodeOptions::default()).unwrap().data;
+        let decoded = load_image_from_bytes(&png).unwrap();
+        assert_eq!(extract_lsb_message(&decoded).unwrap(), message.as_bytes());
+
+        let max_delta = img.to_rgba8().as_raw().iter()
+            .zip(decoded.to_rgba8().as_raw())
+            .map(|(&a, &b)| a.abs_diff(b))
+            .max()
+            .unwrap();
+        assert!(max_delta <= 1, "max channel delta {}", max_delta);
+    }
+
+    #[test]
+    fn dominant_crop_finds_off_center_saturated_object() {
+        // Gray field with a saturated orange block near the right edge
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(300, 200, |x, y| {
+            if (230..270).contains(&x) && (20..60).contains(&y) {
+                image::Rgb([250, 120, 10])
+            } else {
+                image::Rgb([128, 128, 128])
+            }
+        }));
+
+        let (x, y) = find_most_colorful_region(&img, 100, 100);
+        assert!(x <= 230 && x + 100 >= 270, "crop x {} misses the object", x);
+        assert!(y <= 20 && y + 100 >= 60, "crop y {} misses the object", y);
+    }
+
+    #[test]
+    fn drop_shadow_darkens_lower_right_outside_the_original() {
+        let square = DynamicImage::ImageRgba8(RgbaImage::from_pixel(20, 20, image::Rgba([250, 250, 250, 255])));
+        let shadowed = add_drop_shadow(&square, image::Rgba([0, 0, 0, 180]), 2.0, 6, 6).unwrap().to_rgba8();
+
+        // 6px blur margin on every side, plus the offset on the right/bottom
+        assert_eq!(shadowed.dimensions(), (20 + 12 + 6, 20 + 12 + 6));
+        let (left, top) = (6, 6);
+        let below_right = shadowed.get_pixel(left + 22, top + 22);
+        assert!(below_right[0] < 50 && below_right[1] < 50 && below_right[2] < 50, "{:?}", below_right);
+        assert!(below_right[3] > 0 && below_right[3] < 255, "{:?}", below_right);
+        // Nothing above and to the left of the original
+        assert_eq!(shadowed.get_pixel(left - 3, top - 3)[3], 0);
+        // The square itself is untouched
+        assert_eq!(*shadowed.get_pixel(left + 10, top + 10), image::Rgba([250, 250, 250, 255]));
+    }
+
+    #[test]
+    fn drop_shadow_rejects_canvas_blowing_parameters() {
+        let img = DynamicImage::new_rgba8(10, 10);
+        let black = image::Rgba([0, 0, 0, 255]);
+        assert!(add_drop_shadow(&img, black, 1e9, 1, 1).is_err());
+        assert!(add_drop_shadow(&img, black, 1.0, i32::MAX, 0).is_err());
+        assert!(add_drop_shadow(&img, black, 1.0, 0, i32::MIN).is_err());
+    }
+
+    #[test]
+    fn filled_red_rect_paints_its_pixels_red() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(50, 40, image::Rgba([255, 255, 255, 255])));
+        let shapes: Vec<Shape> = serde_json::from_str(
+            r##"[{"type":"rect","x":10,"y":5,"w":20,"h":15,"color":"#ff0000","fill":true}]"##,
+        ).unwrap();
+        let drawn = draw_shapes(&img, &shapes).unwrap().to_rgba8();
+
+        for (x, y, pixel) in drawn.enumerate_pixels() {
+            let inside = (10..30).contains(&x) && (5..20).contains(&y);
+            let expected = if inside { [255, 0, 0, 255] } else { [255, 255, 255, 255] };
+            assert_eq!(pixel.0, expected, "pixel {},{}", x, y);
+        }
+    }
+
+    #[test]
+    fn oversized_shapes_are_clipped_instead_of_overflowing() {
+        let img = DynamicImage::new_rgba8(20, 20);
+        let shapes: Vec<Shape> = serde_json::from_str(r#"[
+            {"type":"rect","x":2147483000,"y":0,"w":4000000000,"h":5,"stroke_width":3},
+            {"type":"rect","x":-5,"y":-5,"w":4000000000,"h":4000000000,"fill":true},
+            {"type":"line","x1":0,"y1":0,"x2":0,"y2":0,"stroke_width":4000000000},
+            {"type":"text","x":0,"y":0,"text":"W","size":1e9}
+        ]"#).unwrap();
+        let drawn = draw_shapes(&img, &shapes).unwrap();
+        assert_eq!(drawn.dimensions(), (20, 20));
+    }
+
+    #[test]
+    fn face_crop_region_keeps_an_off_center_face_inside() {
+        let face = FaceBox { x: 300, y: 40, width: 60, height: 80, confidence: 0.9 };
+        let (x, y, width, height) = face_crop_region(&[face], 400, 300, 0.25).unwrap();
+
+        assert!(x <= 300 && y <= 40);
+        assert!(x + width >= 360 && y + height >= 120);
+        assert!(x + width <= 400 && y + height <= 300);
+        // The padding reaches past the face on the open sides only
+        assert_eq!((x, y), (285, 20));
+    }
+
+    #[test]
+    fn checkerboard_shows_through_transparent_pixels_only() {
+        let mut img = RgbaImage::new(8, 8);
+        img.put_pixel(7, 7, image::Rgba([10, 20, 30, 255]));
+        let preview = checkerboard_preview(&DynamicImage::ImageRgba8(img), 4).to_rgb8();
+
+        assert_eq!(preview.get_pixel(0, 0).0, CHECKER_LIGHT);
+        assert_eq!(preview.get_pixel(4, 0).0, CHECKER_DARK);
+        assert_eq!(preview.get_pixel(0, 4).0, CHECKER_DARK);
+        assert_eq!(preview.get_pixel(5, 5).0, CHECKER_LIGHT);
+        assert_eq!(preview.get_pixel(7, 7).0, [10, 20, 30]);
+    }
+
+    #[test]
+    fn iterative_upscale_keeps_edges_sharper_than_one_jump() {
+        // A hard vertical edge; sharpness is the steepest step across it
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, _| {
+            if x < 8 { image::Rgb([30, 30, 30]) } else { image::Rgb([220, 220, 220]) }
+        }));
+        let steepest = |img: &DynamicImage| {
+            let luma = img.to_luma8();
+            let y = luma.height() / 2;
+            (1..luma.width()).map(|x| luma.get_pixel(x, y)[0].abs_diff(luma.get_pixel(x - 1, y)[0])).max().unwrap()
+        };
+
+        let single = resize_image_with_alpha(&img, 64, 64, ResizeFilter::Lanczos3, true).unwrap();
+        let iterative = upscale_iterative(&img, 64, 64, ResizeFilter::Lanczos3).unwrap();
+        assert_eq!(iterative.dimensions(), (64, 64));
+        assert!(steepest(&iterative) > steepest(&single), "iterative {} vs single {}", steepest(&iterative), steepest(&single));
+    }
+
+    #[test]
+    fn equalization_spreads_a_low_contrast_histogram() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            let v = 100 + ((x + y) * 40 / 126) as u8;
+            image::Rgb([v, v, v])
+        }));
+        let spread = |img: &DynamicImage| {
+            let luma = img.to_luma8();
+            let (min, max) = luma.pixels().fold((255, 0), |(min, max), p| (p[0].min(min), p[0].max(max)));
+            max - min
+        };
+
+        assert_eq!(spread(&img), 40);
+        for per_channel in [false, true] {
+            let equalized = equalize_histogram(&img, per_channel);
+            assert!(spread(&equalized) > 200, "per_channel={} spread {}", per_channel, spread(&equalized));
+        }
+    }
+
+    #[test]
+    fn box_blur_approximates_gaussian() {
+        // Box is the one to pick for large sigma, where Gaussian's cost grows
+        // with the kernel; this checks the approximation stays close
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(96, 96, |x, y| {
+            if (x / 12 + y / 12) % 2 == 0 { image::Rgb([240, 40, 40]) } else { image::Rgb([20, 20, 200]) }
+        }));
+        for sigma in [2.0, 6.0] {
+            let gaussian = blur_image(&img, sigma, BlurAlgorithm::Gaussian);
+            let boxed = blur_image(&img, sigma, BlurAlgorithm::Box);
+            let similarity = psnr(&gaussian, &boxed);
+            assert!(similarity > 30.0, "sigma {}: {:.1} dB", sigma, similarity);
+        }
+    }
+
+    #[test]
+    fn nearest_rotation_of_a_label_mask_adds_no_new_values() {
+        let labels = [[0, 0, 0, 255], [100, 100, 100, 255], [200, 200, 200, 255]];
+        let mask = DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 30, |x, y| image::Rgba(labels[((x / 7 + y / 5) % 3) as usize])));
+
+        let rotated = rotate_arbitrary(&mask, 30.0, Interpolation::Nearest).to_rgba8();
+        // Uncovered corners are fully transparent; everything else is a label
+        assert!(rotated.pixels().all(|p| p.0 == [0, 0, 0, 0] || labels.contains(&p.0)));
+
+        let bilinear = rotate_arbitrary(&mask, 30.0, Interpolation::Bilinear).to_rgba8();
+        assert!(bilinear.pixels().any(|p| p[3] == 255 && !labels.contains(&p.0)));
+    }
+
+    #[test]
+    fn lzw_tiff_is_smaller_than_uncompressed_for_flat_areas() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(128, 128, |x, _| {
+            if x < 64 { image::Rgb([200, 30, 30]) } else { image::Rgb([30, 30, 200]) }
+        }));
+        let tiff = |compression| {
+            let options = EncodeOptions { tiff_compression: compression, ..EncodeOptions::default() };
+            encode_image_to_bytes(&img, ImageFormat::Tiff, &options).unwrap().data
+        };
+
+        let (lzw, uncompressed) = (tiff(TiffCompression::Lzw), tiff(TiffCompression::None));
+        assert!(lzw.len() < uncompressed.len() / 4, "lzw {} vs none {}", lzw.len(), uncompressed.len());
+        assert_eq!(load_image_from_bytes(&lzw).unwrap().to_rgb8(), img.to_rgb8());
+    }
+
+    #[test]
+    fn dithered_bilevel_gradient_tracks_the_local_brightness() {
+        let gradient = DynamicImage::ImageLuma8(image::GrayImage::from_fn(128, 32, |x, _| image::Luma([(x * 2) as u8])));
+
+        // Mean error between 8x8 block averages of the bilevel output and the gradient
+        let block_error = |bilevel: &DynamicImage| {
+            let (gray, out) = (gradient.to_luma8(), bilevel.to_luma8());
+            let mut total = 0.0;
+            for by in 0..4 {
+                for bx in 0..16 {
+                    let mean = |img: &image::GrayImage| {
+                        (0..64).map(|i| img.get_pixel(bx * 8 + i % 8, by * 8 + i / 8)[0] as f64).sum::<f64>() / 64.0
+                    };
+                    total += (mean(&gray) - mean(&out)).abs();
+                }
+            }
+            total / 64.0
+        };
+
+        let hard = apply_color_mode(&gradient, ColorMode::Bilevel, None, Some(Dither::None));
+        let dithered = apply_color_mode(&gradient, ColorMode::Bilevel, None, None);
+        let (hard_error, dithered_error) = (block_error(&hard), block_error(&dithered));
+        assert!(dithered_error * 2.0 < hard_error, "dithered {} vs hard {}", dithered_error, hard_error);
+    }
+
+    #[test]
+    fn oversized_png_is_rejected_from_its_header() {
+        let mut png = Vec::new();
+        image::GrayImage::new(20_000, 4).write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        assert!(png.len() < 10_000);
+        // Garbage pixel data would fail a decode with a different error
+        let idat = png.windows(4).position(|w| w == b"IDAT").unwrap();
+        png[idat + 4..idat + 20].fill(0xFF);
+
+        assert!(matches!(load_image_from_bytes(&png), Err(AppError::DimensionsTooLarge(_))));
+    }
+
+    #[test]
+    fn premultiplied_resize_keeps_a_white_logo_edge_white() {
+        // White disc on transparent black, the usual export from design tools
+        let logo = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            let (dx, dy) = (x as f32 - 31.5, y as f32 - 31.5);
+            if dx * dx + dy * dy < 24.0 * 24.0 { image::Rgba([255, 255, 255, 255]) } else { image::Rgba([0, 0, 0, 0]) }
+        }));
+
+        let darkest_edge = |img: &DynamicImage| {
+            img.to_rgba8().pixels().filter(|p| p[3] > 16 && p[3] < 240).map(|p| p[0]).min().unwrap()
+        };
+        let premultiplied = resize_image_with_alpha(&logo, 21, 21, ResizeFilter::Lanczos3, true).unwrap();
+        let straight = resize_image_with_alpha(&logo, 21, 21, ResizeFilter::Lanczos3, false).unwrap();
+        assert!(darkest_edge(&premultiplied) >= 250, "premultiplied edge {}", darkest_edge(&premultiplied));
+        assert!(darkest_edge(&straight) < 200, "straight edge {}", darkest_edge(&straight));
+    }
+
+    #[test]
+    fn warm_temperature_raises_red_and_lowers_blue() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([100 + x as u8, 120, 140 + y as u8])));
+        let channel_mean = |img: &DynamicImage, c: usize| {
+            let rgb = img.to_rgb8();
+            rgb.pixels().map(|p| p[c] as f64).sum::<f64>() / (rgb.width() * rgb.height()) as f64
+        };
+
+        let warm = apply_white_balance(&img, 50.0, 0.0);
+        assert!(channel_mean(&warm, 0) > channel_mean(&img, 0));
+        assert!(channel_mean(&warm, 2) < channel_mean(&img, 2));
+    }
+
+    #[test]
+    fn median_denoise_removes_salt_and_pepper() {
+        let clean = DynamicImage::ImageRgb8(image::RgbImage::from_fn(48, 48, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 128])));
+        let mut noisy = clean.to_rgb8();
+        for i in 0..noisy.width() * noisy.height() {
+            if i % 13 == 0 {
+                let value = if i % 2 == 0 { 0 } else { 255 };
+                noisy.put_pixel(i % 48, i / 48, image::Rgb([value; 3]));
+            }
+        }
+        let noisy = DynamicImage::ImageRgb8(noisy);
+
+        let error = |img: &DynamicImage| {
+            img.to_rgb8().as_raw().iter().zip(clean.to_rgb8().as_raw()).map(|(&a, &b)| a.abs_diff(b) as u64).sum::<u64>()
+        };
+        let denoised = denoise(&noisy, DenoiseMethod::Median, 1, 0.0);
+        assert!(error(&denoised) * 4 < error(&noisy), "denoised {} vs noisy {}", error(&denoised), error(&noisy));
+    }
+
+    #[test]
+    fn otsu_level_falls_between_two_clusters() {
+        // Dark text (30-69) on a light page (180-219)
+        let img = DynamicImage::ImageLuma8(image::GrayImage::from_fn(40, 40, |x, y| {
+            let jitter = ((x * 7 + y * 13) % 40) as u8;
+            image::Luma([if (x / 8 + y / 8) % 3 == 0 { 30 + jitter } else { 180 + jitter }])
+        }));
+        // Pixels above the level are light
+        let level = otsu_threshold(&img);
+        assert!((69..180).contains(&level), "level {}", level);
+    }
+
+    #[test]
+    fn duotone_maps_a_gray_ramp_between_the_two_colors() {
+        let ramp = DynamicImage::ImageLuma8(image::GrayImage::from_fn(256, 1, |x, _| image::Luma([x as u8])));
+        let (shadow, highlight) = (image::Rgba([20, 0, 80, 255]), image::Rgba([255, 200, 40, 255]));
+        let mapped = apply_duotone(&ramp, shadow, highlight).to_rgba8();
+
+        assert_eq!(*mapped.get_pixel(0, 0), shadow);
+        assert_eq!(*mapped.get_pixel(255, 0), highlight);
+        // Each channel moves monotonically, one small step at a time
+        for x in 1..256 {
+            let (prev, next) = (mapped.get_pixel(x - 1, 0), mapped.get_pixel(x, 0));
+            for c in 0..3 {
+                let rising = highlight[c] >= shadow[c];
+                assert!(if rising { next[c] >= prev[c] } else { next[c] <= prev[c] });
+                assert!(next[c].abs_diff(prev[c]) <= 2);
+            }
+        }
+    }
+
+    #[test]
+    fn indexed_png_stays_indexed_when_resaved() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| {
+            let i = ((x / 8) + (y / 8) * 4) as u8;
+            image::Rgb([i * 16, 255 - i * 16, (i % 4) * 60])
+        }));
+        let indexed = encode_indexed_png(&img, 16, Dither::None).unwrap();
+        assert_eq!(png_palette_size(&indexed), Some(16));
+
+        let decoded = load_image_from_bytes(&indexed).unwrap();
+        let resaved = encode_png_like_source(&decoded.fliph(), &indexed, &EncodeOptions::default()).unwrap();
+        assert!(png_palette_size(&resaved).is_some_and(|size| size <= 16));
+        assert_eq!(load_image_from_bytes(&resaved).unwrap().to_rgba8(), decoded.fliph().to_rgba8());
+    }
+
+    #[test]
+    fn perspective_warp_straightens_a_trapezoid() {
+        use imageproc::drawing::draw_polygon_mut;
+        use imageproc::point::Point;
+
+        // A white document photographed at an angle, on a black desk
+        let corners = [(30.0, 10.0), (70.0, 10.0), (90.0, 90.0), (10.0, 90.0)];
+        let mut photo = RgbaImage::from_pixel(100, 100, image::Rgba([0, 0, 0, 255]));
+        let outline: Vec<_> = corners.iter().map(|&(x, y)| Point::new(x as i32, y as i32)).collect();
+        draw_polygon_mut(&mut photo, &outline, image::Rgba([255, 255, 255, 255]));
+
+        let warped = perspective_warp(&DynamicImage::ImageRgba8(photo), corners, Some((60, 60)), Interpolation::Bilinear)
+            .unwrap()
+            .to_luma8();
+        // Every edge of the page lands on the output's edge, so whole rows
+        // and columns inside it are white rather than slanting into black
+        for i in 2..58 {
+            for j in 2..58 {
+                assert!(warped.get_pixel(i, j)[0] > 200, "black at {},{}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn temp_file_and_memory_encoding_match() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(24, 16, |x, y| image::Rgba([(x * 10) as u8, (y * 15) as u8, 90, 200])));
+        for format in [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::Webp, ImageFormat::Tiff, ImageFormat::Gif] {
+            let options = EncodeOptions::default();
+            let in_memory = encode_image_to_bytes(&img, format, &options).unwrap();
+            let via_temp = encode_via_temp_file(&img, format, &options).unwrap();
+            assert_eq!(in_memory.data, via_temp.data, "{:?}", format);
+            assert_eq!(in_memory.alpha_flattened, via_temp.alpha_flattened);
+        }
+    }
+
+    #[test]
+    fn info_reports_a_reduced_aspect_ratio() {
+        let info = get_image_info(&DynamicImage::new_rgb8(1920, 1080), "wide.png", 0);
+        assert_eq!(info.aspect_ratio_string.as_deref(), Some("16:9"));
+        assert!((info.aspect_ratio.unwrap() - 16.0 / 9.0).abs() < 1e-9);
+        assert_eq!(aspect_ratio(0, 1080), None);
+    }
+
+    #[test]
+    fn optimized_huffman_tables_shrink_a_jpeg() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(96, 64, |x, y| image::Rgb([(x * 2) as u8, (y * 3) as u8, ((x ^ y) * 4) as u8])));
+        let jpeg = |optimize| {
+            let options = EncodeOptions { quality: 85, optimize, effort: 0, ..EncodeOptions::default() };
+            encode_image_to_bytes(&img, ImageFormat::Jpeg, &options).unwrap().data
+        };
+        let (optimized, default) = (jpeg(true), jpeg(false));
+        assert!(optimized.len() < default.len(), "optimized {} vs default {}", optimized.len(), default.len());
+    }
+
+    #[test]
+    fn blurhash_round_trips_the_average_color() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(80, 60, |x, y| {
+            if (x / 10 + y / 10) % 2 == 0 { image::Rgb([200, 120, 40]) } else { image::Rgb([160, 80, 20]) }
+        }));
+        let hash = encode_blurhash(&img, 4, 3).unwrap();
+        let decoded = blurhash::decode(&hash, 16, 12, 1.0).unwrap();
+
+        let mean = |pixels: &[u8], stride: usize, c: usize| {
+            pixels.chunks(stride).map(|p| p[c] as f64).sum::<f64>() / (pixels.len() / stride) as f64
+        };
+        let original = img.to_rgb8();
+        for c in 0..3 {
+            let (expected, actual) = (mean(original.as_raw(), 3, c), mean(&decoded, 4, c));
+            assert!((expected - actual).abs() < 8.0, "channel {}: {} vs {}", c, expected, actual);
+        }
+    }
+
+    #[test]
+    fn blur_padding_fills_the_bars_with_image_colors() {
+        // A vertical frame: red on top, blue below
+        let frame = DynamicImage::ImageRgb8(image::RgbImage::from_fn(40, 60, |_, y| {
+            if y < 30 { image::Rgb([220, 30, 30]) } else { image::Rgb([30, 30, 220]) }
+        }));
+        let bar_pixels = |img: &DynamicImage| {
+            let rgba = img.to_rgba8();
+            (0..60).map(|y| *rgba.get_pixel(10, y)).collect::<Vec<_>>()
+        };
+
+        let solid = pad_to_size(&frame, 120, 60, PadMode::Color, image::Rgba([0, 0, 0, 255]));
+        assert!(bar_pixels(&solid).windows(2).all(|w| w[0] == w[1]));
+
+        let blurred = bar_pixels(&pad_to_size(&frame, 120, 60, PadMode::Blur, image::Rgba([0, 0, 0, 255])));
+        assert!(blurred[5][0] > blurred[5][2], "top of the bar should be reddish");
+        assert!(blurred[55][2] > blurred[55][0], "bottom of the bar should be bluish");
+    }
+
+    #[test]
+    fn ellipse_pixelate_leaves_the_box_corners_alone() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(80, 80, |x, y| image::Rgba([(x * 3) as u8, (y * 3) as u8, ((x + y) * 2) as u8, 255])));
+        let censored = censor_region(&img, (10, 10, 60, 60), CensorStyle::Pixelate, CensorShape::Ellipse, 20, BlurAlgorithm::Gaussian, image::Rgba([0, 0, 0, 255]))
+            .unwrap()
+            .to_rgba8();
+        let original = img.to_rgba8();
+
+        for (x, y) in [(10, 10), (69, 10), (10, 69), (69, 69), (13, 13)] {
+            assert_eq!(censored.get_pixel(x, y), original.get_pixel(x, y), "corner {},{} changed", x, y);
+        }
+        assert_ne!(censored.get_pixel(38, 38), original.get_pixel(38, 38));
+    }
+
+    #[test]
+    fn mixed_resize_uses_each_axis_filter() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(20, 10, |x, y| image::Rgb([(x * 12) as u8, (y * 25) as u8, 0])));
+        let (resized, horizontal, vertical) =
+            resize_directional(&img, 40, 5, ResizeFilter::Bilinear, ResizeFilter::Nearest, true).unwrap();
+        assert_eq!(resized.dimensions(), (40, 5));
+        assert_eq!((horizontal, vertical), (ResizeFilter::Nearest, ResizeFilter::Bilinear));
+
+        // Nearest widening duplicates each column exactly
+        let rgb = resized.to_rgb8();
+        for y in 0..5 {
+            for x in (0..40).step_by(2) {
+                assert_eq!(rgb.get_pixel(x, y), rgb.get_pixel(x + 1, y));
+            }
+        }
+    }
+
+    #[test]
+    fn higher_png_effort_is_never_larger() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(128, 96, |x, y| image::Rgb([(x * 2) as u8, ((x * y) % 251) as u8, (y * 2) as u8])));
+        let png = |effort| {
+            let options = EncodeOptions { effort, ..EncodeOptions::default() };
+            encode_image_to_bytes(&img, ImageFormat::Png, &options).unwrap().data.len()
+        };
+        let sizes: Vec<usize> = [0, 4, 9].into_iter().map(png).collect();
+        assert!(sizes[1] <= sizes[0] && sizes[2] <= sizes[1], "{:?}", sizes);
+        assert!(sizes[2] < sizes[0]);
+    }
+}
@@ -1,20 +1,42 @@
 use axum::{
-    http::StatusCode,
+    body::Body,
+    error_handling::HandleErrorLayer,
+    http::{Request, StatusCode},
     response::{IntoResponse, Json},
     routing::{get, post},
     Router,
 };
 use serde::Serialize;
-use std::{env, net::SocketAddr};
-use tower::ServiceBuilder;
+use std::{env, net::SocketAddr, sync::OnceLock, time::Instant};
+use tower::{limit::GlobalConcurrencyLimitLayer, BoxError, ServiceBuilder};
 use tower_http::{
+    compression::{
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+        CompressionLayer,
+    },
     cors::CorsLayer,
     limit::RequestBodyLimitLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+    trace::{DefaultOnResponse, TraceLayer},
 };
-use tracing::info;
+use tracing::{info, Level, Span};
+use tracing_subscriber::util::SubscriberInitExt;
 
+mod camera_raw;
+#[cfg(feature = "colorize")]
+mod colorization;
+#[cfg(feature = "faces")]
+mod face_detection;
+mod fetch;
+mod fields;
 mod handlers;
 mod image_utils;
+mod metadata;
+#[cfg(feature = "esrgan")]
+mod super_resolution;
+#[cfg(test)]
+mod test_support;
+mod tools;
 mod types;
 mod validation;
 
@@ -23,82 +45,208 @@ use handlers::*;
 #[derive(Serialize)]
 struct HealthResponse {
     status: String,
+    version: &'static str,
+    // Commit the binary was built from, when GIT_SHA was set at build time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git_sha: Option<&'static str>,
+    uptime_secs: u64,
 }
 
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+
 #[derive(Serialize)]
-struct Tool {
-    name: String,
-    description: String,
-}
-
-fn get_tools() -> Vec<Tool> {
-    vec![
-        Tool { name: "compress".to_string(), description: "Compress images by reducing quality".to_string() },
-        Tool { name: "resize".to_string(), description: "Resize images by dimensions or percentage".to_string() },
-        Tool { name: "crop".to_string(), description: "Crop images by coordinates or aspect ratio".to_string() },
-        Tool { name: "rotate".to_string(), description: "Rotate images by degrees or auto-orient".to_string() },
-        Tool { name: "convert".to_string(), description: "Convert images between formats".to_string() },
-        Tool { name: "info".to_string(), description: "Get image info (dimensions, format, size, mode)".to_string() },
-        Tool { name: "metadata".to_string(), description: "View or strip EXIF metadata".to_string() },
-        Tool { name: "watermark".to_string(), description: "Add text or image watermarks".to_string() },
-        Tool { name: "blur-face".to_string(), description: "Detect and blur faces".to_string() },
-        Tool { name: "remove-bg".to_string(), description: "Remove image background".to_string() },
-        Tool { name: "upscale".to_string(), description: "Upscale images with LANCZOS resampling".to_string() },
-        Tool { name: "meme".to_string(), description: "Add meme text (top/bottom)".to_string() },
-        Tool { name: "edit".to_string(), description: "Photo editor: brightness, contrast, filters, borders, etc.".to_string() },
-        Tool { name: "html-to-img".to_string(), description: "Screenshot a URL (not available in web mode)".to_string() },
-    ]
+struct ReadinessResponse {
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
 }
 
 async fn health() -> Json<HealthResponse> {
     Json(HealthResponse {
         status: "ok".to_string(),
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: option_env!("GIT_SHA").filter(|sha| !sha.is_empty()),
+        uptime_secs: STARTED_AT.get().map_or(0, |started| started.elapsed().as_secs()),
     })
 }
 
-async fn tools() -> Json<Vec<Tool>> {
-    Json(get_tools())
+// Readiness probe: make sure we can still encode an image and write the
+// temp files the handlers rely on
+async fn ready() -> impl IntoResponse {
+    let check = || -> Result<(), types::AppError> {
+        let img = image::DynamicImage::new_rgb8(1, 1);
+        let temp_path = image_utils::create_temp_file("png");
+        image_utils::save_image(&img, &temp_path, types::ImageFormat::Png)?;
+        let result = image_utils::read_file_bytes(&temp_path);
+        image_utils::delete_temp_file(&temp_path);
+        result.map(|_| ())
+    };
+
+    match check() {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ReadinessResponse { status: "ready".to_string(), error: None }),
+        ),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ReadinessResponse { status: "not ready".to_string(), error: Some(e.to_string()) }),
+        ),
+    }
+}
+
+async fn tools() -> Json<Vec<tools::Tool>> {
+    Json(tools::get_tools())
 }
 
 async fn not_implemented() -> impl IntoResponse {
     (StatusCode::NOT_IMPLEMENTED, "Not Implemented")
 }
 
-#[tokio::main]
-async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt()
+async fn handle_overload(_: BoxError) -> impl IntoResponse {
+    (StatusCode::SERVICE_UNAVAILABLE, "Server is busy, try again later")
+}
+
+// Answer 503 straight away once `max_concurrency` requests are in flight
+// across all of `router`'s routes
+fn shed_load(router: Router, max_concurrency: usize) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_overload))
+            .load_shed()
+            // Router::layer wraps each route separately, so the
+            // semaphore has to be shared explicitly
+            .layer(GlobalConcurrencyLimitLayer::new(max_concurrency))
+    )
+}
+
+// Request ids, tracing, body limit, CORS and compression for every /api route
+fn with_api_layers(router: Router) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            // Keep the caller's X-Request-Id or assign a UUID, and echo it
+            // back so client reports can be matched to log lines
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            // One span per request, logged when the response goes out
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(request_span)
+                    .on_response(DefaultOnResponse::new().level(Level::INFO)),
+            )
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(RequestBodyLimitLayer::new(20 * 1024 * 1024)) // 20MB limit
+            .layer(CorsLayer::permissive()) // Allow all origins
+            // DefaultPredicate already skips image/*; ZIPs are deflated
+            // too, and NDJSON lines must not wait on the compressor
+            .layer(CompressionLayer::new().compress_when(
+                DefaultPredicate::new()
+                    .and(NotForContentType::const_new("application/zip"))
+                    .and(NotForContentType::const_new("application/x-ndjson")),
+            ))
+    )
+}
+
+// Human-readable logs by default; LOG_FORMAT=json writes one JSON object per
+// line, with the request span's fields on every event
+fn init_tracing() {
+    log_subscriber(env::var("LOG_FORMAT").ok().as_deref()).init();
+}
+
+fn log_subscriber(format: Option<&str>) -> Box<dyn tracing::Subscriber + Send + Sync> {
+    let subscriber = tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
-        )
-        .init();
+        );
+
+    match format {
+        Some(format) if format.eq_ignore_ascii_case("json") => {
+            Box::new(subscriber.json().with_current_span(true).with_span_list(false).finish())
+        }
+        _ => Box::new(subscriber.finish()),
+    }
+}
+
+fn request_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!("request", method = %request.method(), uri = %request.uri(), request_id)
+}
+
+#[tokio::main]
+async fn main() {
+    STARTED_AT.get_or_init(Instant::now);
+    init_tracing();
 
     eprintln!("[gimg-rust-api] Starting up...");
 
-    // Build the API routes
-    let api_router = Router::new()
-        .route("/health", get(health))
-        .route("/tools", get(tools))
+    info!("Default output quality: {}", types::default_quality());
+
+    let max_concurrency = env::var("MAX_CONCURRENCY")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|&value| value > 0)
+        .unwrap_or(64);
+
+    // Build the API routes. Image processing is load-shed past
+    // MAX_CONCURRENCY in-flight requests; health checks are not.
+    let processing_router = Router::new()
         .route("/compress", post(compress_handler))
         .route("/resize", post(resize_handler))
         .route("/crop", post(crop_handler))
+        .route("/trim", post(trim_handler))
         .route("/rotate", post(rotate_handler))
         .route("/convert", post(convert_handler))
         .route("/info", post(info_handler))
+        .route("/detect-format", post(detect_format_handler))
+        .route("/info-batch", post(info_batch_handler))
         .route("/metadata", post(metadata_handler))
+        .route("/strip-all", post(strip_all_handler))
+        .route("/exif-edit", post(exif_edit_handler))
         .route("/watermark", post(watermark_handler))
         .route("/blur-face", post(blur_face_handler))
+        .route("/face-count", post(face_count_handler))
+        .route("/face-crop", post(face_crop_handler))
         .route("/remove-bg", post(not_implemented))
         .route("/upscale", post(upscale_handler))
+        .route("/colorize", post(colorize_handler))
         .route("/meme", post(meme_handler))
         .route("/edit", post(edit_handler))
         .route("/html-to-img", post(not_implemented))
-        .layer(
-            ServiceBuilder::new()
-                .layer(RequestBodyLimitLayer::new(20 * 1024 * 1024)) // 20MB limit
-                .layer(CorsLayer::permissive()) // Allow all origins
-        );
+        .route("/compare-formats", post(compare_formats_handler))
+        .route("/compare-visual", post(compare_visual_handler))
+        .route("/color-transfer", post(color_transfer_handler))
+        .route("/preview-alpha", post(preview_alpha_handler))
+        .route("/mirror-tile", post(mirror_tile_handler))
+        .route("/fit-to-bytes", post(fit_to_bytes_handler))
+        .route("/annotate", post(annotate_handler))
+        .route("/contact-sheet", post(contact_sheet_handler))
+        .route("/sprite-sheet", post(sprite_sheet_handler))
+        .route("/spritesheet-to-gif", post(spritesheet_to_gif_handler))
+        .route("/optimize-gif", post(optimize_gif_handler))
+        .route("/denoise", post(denoise_handler))
+        .route("/threshold", post(threshold_handler))
+        .route("/split", post(split_handler))
+        .route("/avatar", post(avatar_handler))
+        .route("/favicon", post(favicon_handler))
+        .route("/scale-to-print", post(scale_to_print_handler))
+        .route("/perspective", post(perspective_handler))
+        .route("/placeholder", post(placeholder_handler))
+        .route("/embedded-thumb", post(embedded_thumb_handler))
+        .route("/raw", post(raw_handler))
+        .route("/dominant-crop", post(dominant_crop_handler))
+        .route("/stego-embed", post(stego_embed_handler))
+        .route("/stego-extract", post(stego_extract_handler));
+    let processing_router = shed_load(processing_router, max_concurrency);
+
+    let api_router = Router::new()
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .route("/tools", get(tools))
+        .merge(processing_router);
+    let api_router = with_api_layers(api_router);
 
     // Main app router
     let app = Router::new()
@@ -121,7 +269,165 @@ async fn main() {
 
     eprintln!("[gimg-rust-api] Listening on {}", addr);
 
-    axum::serve(listener, app)
+    serve(listener, app, shutdown_signal())
         .await
         .expect("Failed to start server");
-}
\ No newline at end of file
+
+    info!("Shutdown complete");
+}
+
+// Serve until `shutdown` resolves, then stop accepting connections and let
+// in-flight requests finish
+async fn serve(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    axum::serve(listener, app).with_graceful_shutdown(shutdown).await
+}
+
+// Resolves on SIGINT or SIGTERM; axum then stops accepting connections and
+// waits for in-flight requests to finish
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutdown signal received, draining in-flight requests");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::{oneshot, Notify};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn ready_reports_ok_when_encoding_and_temp_files_work() {
+        let response = ready().await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn requests_past_the_concurrency_limit_are_shed() {
+        let (started, release) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+        let slow = {
+            let (started, release) = (started.clone(), release.clone());
+            move || async move {
+                started.notify_one();
+                release.notified().await;
+                "done"
+            }
+        };
+        let app = shed_load(Router::new().route("/slow", get(slow)), 1);
+        let request = || Request::get("/slow").body(Body::empty()).unwrap();
+
+        let first = tokio::spawn(app.clone().oneshot(request()));
+        started.notified().await;
+        let second = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        release.notify_one();
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn shutdown_lets_an_in_flight_request_finish() {
+        let (started, release) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+        let slow = {
+            let (started, release) = (started.clone(), release.clone());
+            move || async move {
+                started.notify_one();
+                release.notified().await;
+                "done"
+            }
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, Router::new().route("/slow", get(slow)), async {
+            shutdown_rx.await.ok();
+        }));
+
+        let client = tokio::spawn(async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"GET /slow HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        });
+        started.notified().await;
+        shutdown_tx.send(()).unwrap();
+        // Give the server time to start draining before the handler returns
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!server.is_finished());
+        release.notify_one();
+
+        let response = client.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("done"));
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn tools_json_is_gzipped_when_the_client_accepts_it() {
+        let app = with_api_layers(Router::new().route("/tools", get(tools)));
+        let request = Request::get("/tools").header("Accept-Encoding", "gzip").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Encoding"], "gzip");
+    }
+
+    #[test]
+    fn log_subscriber_builds_in_both_formats() {
+        for format in [None, Some("pretty"), Some("JSON")] {
+            tracing::subscriber::with_default(log_subscriber(format), || {
+                tracing::info_span!("request", request_id = "test").in_scope(|| info!("logged"));
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed_or_generated() {
+        let app = with_api_layers(Router::new().route("/health", get(health)));
+
+        let request = Request::get("/health").header("X-Request-Id", "client-42").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["X-Request-Id"], "client-42");
+
+        let response = app.oneshot(Request::get("/health").body(Body::empty()).unwrap()).await.unwrap();
+        let generated = response.headers()["X-Request-Id"].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(generated).is_ok(), "{}", generated);
+    }
+
+    #[tokio::test]
+    async fn health_reports_the_crate_version() {
+        let Json(health) = health().await;
+        assert_eq!(health.status, "ok");
+        assert_eq!(health.version, env!("CARGO_PKG_VERSION"));
+
+        let json = serde_json::to_value(&health).unwrap();
+        assert!(json["uptime_secs"].is_u64());
+    }
+}
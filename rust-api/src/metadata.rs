@@ -0,0 +1,718 @@
+use crate::types::{AppError, XmpInfo};
+use exif::{Field, In, Tag, Value};
+use flate2::read::ZlibDecoder;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Write};
+
+const JPEG_XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+// Compressed PNG text is untrusted; a few KB of deflate can expand to
+// gigabytes, so anything inflating past this is skipped
+const MAX_INFLATED_TEXT: u64 = 4 * 1024 * 1024;
+
+// Parse the EXIF block of any container kamadak-exif understands
+pub fn read_exif(data: &[u8]) -> Option<exif::Exif> {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()
+}
+
+// EXIF orientation (1-8) from the raw bytes, read without decoding pixels
+pub fn exif_orientation(data: &[u8]) -> Option<u8> {
+    let exif = read_exif(data)?;
+    let field = exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?;
+    field.value.get_uint(0).and_then(|value| u8::try_from(value).ok())
+}
+
+pub fn has_gps(exif: &exif::Exif) -> bool {
+    exif.fields().any(|field| field.tag.context() == exif::Context::Gps)
+}
+
+// Primary-image tags as display strings, keyed by tag name. MakerNote is an
+// opaque vendor blob, so it is left out.
+pub fn exif_tags(exif: &exif::Exif) -> BTreeMap<String, String> {
+    exif.fields()
+        .filter(|field| field.ifd_num == In::PRIMARY && field.tag != Tag::MakerNote)
+        .map(|field| {
+            let value = match &field.value {
+                Value::Ascii(parts) => parts
+                    .iter()
+                    .map(|part| String::from_utf8_lossy(part).trim_end_matches('\0').to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                _ => field.display_value().with_unit(exif).to_string(),
+            };
+            (field.tag.to_string(), value)
+        })
+        .collect()
+}
+
+// Tags exif-edit may set, all ASCII text
+const WRITABLE_EXIF_TAGS: &[Tag] = &[
+    Tag::Artist,
+    Tag::Copyright,
+    Tag::ImageDescription,
+    Tag::Software,
+    Tag::DateTime,
+    Tag::DateTimeOriginal,
+];
+
+// Look up a writable tag by its EXIF name, ignoring case
+pub fn writable_exif_tag(name: &str) -> Option<Tag> {
+    WRITABLE_EXIF_TAGS
+        .iter()
+        .copied()
+        .find(|tag| tag.to_string().eq_ignore_ascii_case(name.trim()))
+}
+
+pub fn writable_exif_tag_names() -> Vec<String> {
+    WRITABLE_EXIF_TAGS.iter().map(|tag| tag.to_string()).collect()
+}
+
+// The JPEG thumbnail stored in IFD1, if any. Its offset is relative to the
+// TIFF header, which is where kamadak-exif's buffer starts.
+pub fn exif_thumbnail(exif: &exif::Exif) -> Option<&[u8]> {
+    let offset = exif.get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    let length = exif.get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    exif.buf()
+        .get(offset..offset.checked_add(length)?)
+        .filter(|thumbnail| thumbnail.starts_with(&[0xFF, 0xD8]))
+}
+
+// Set text tags in the EXIF block of a JPEG or PNG. Only the metadata is
+// rewritten; the compressed image data is copied through untouched.
+pub fn write_exif_tags(data: &[u8], tags: &[(Tag, String)]) -> Result<Vec<u8>, AppError> {
+    let invalid = |message: String| AppError::InvalidFieldValue(message);
+    let mut fields = Vec::new();
+    for (tag, value) in tags {
+        if matches!(*tag, Tag::DateTime | Tag::DateTimeOriginal) && !is_exif_datetime(value) {
+            return Err(invalid(format!("Invalid {}: '{}'. Expected 'YYYY:MM:DD HH:MM:SS'", tag, value)));
+        }
+        if value.contains('\0') {
+            return Err(invalid(format!("Invalid {}: NUL characters are not allowed", tag)));
+        }
+        fields.push(Field { tag: *tag, ifd_num: In::PRIMARY, value: Value::Ascii(vec![value.clone().into_bytes()]) });
+    }
+
+    // Keep every existing field the writer can re-encode, plus the
+    // thumbnail, so only the requested tags change
+    let existing = read_exif(data);
+    let mut thumbnail = None;
+    let mut little_endian = false;
+    if let Some(exif) = &existing {
+        little_endian = exif.little_endian();
+        fields.extend(
+            exif.fields()
+                .filter(|field| !matches!(field.value, Value::Unknown(..)))
+                .filter(|field| !(field.ifd_num == In::PRIMARY && tags.iter().any(|(tag, _)| *tag == field.tag)))
+                .cloned(),
+        );
+
+        thumbnail = exif_thumbnail(exif);
+    }
+
+    let mut writer = exif::experimental::Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    if let Some(thumbnail) = thumbnail {
+        writer.set_jpeg(thumbnail, In::THUMBNAIL);
+    }
+    let mut tiff = Cursor::new(Vec::new());
+    writer
+        .write(&mut tiff, little_endian)
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to write EXIF: {}", e)))?;
+    let tiff = tiff.into_inner();
+
+    match data {
+        [0xFF, 0xD8, ..] => replace_jpeg_exif(data, &tiff),
+        [0x89, b'P', b'N', b'G', ..] => replace_png_exif(data, &tiff),
+        _ => Err(invalid("EXIF tags can only be written to JPEG and PNG".to_string())),
+    }
+}
+
+fn is_exif_datetime(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.len() == 19
+        && bytes.iter().enumerate().all(|(i, &b)| match i {
+            4 | 7 => b == b':',
+            10 => b == b' ',
+            13 | 16 => b == b':',
+            _ => b.is_ascii_digit(),
+        })
+}
+
+const JPEG_EXIF_SIGNATURE: &[u8] = b"Exif\0\0";
+
+// Drop any Exif APP1 segments and write the new one right after SOI (after
+// a JFIF APP0, which must come first)
+fn replace_jpeg_exif(data: &[u8], tiff: &[u8]) -> Result<Vec<u8>, AppError> {
+    let segment_length = 2 + JPEG_EXIF_SIGNATURE.len() + tiff.len();
+    if segment_length > u16::MAX as usize {
+        return Err(AppError::InvalidFieldValue("EXIF data too large for a JPEG segment".to_string()));
+    }
+    let corrupt = || AppError::CorruptImage("Truncated JPEG segment".to_string());
+
+    let mut output = data[..2].to_vec();
+    let mut insert_at = 2;
+    let mut pos = 2;
+    loop {
+        let marker = *data.get(pos + 1).ok_or_else(corrupt)?;
+        // Entropy-coded data follows SOS; copy the rest verbatim
+        if data[pos] != 0xFF || marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let length = u16::from_be_bytes([*data.get(pos + 2).ok_or_else(corrupt)?, *data.get(pos + 3).ok_or_else(corrupt)?]) as usize;
+        let segment = data.get(pos..pos + 2 + length).ok_or_else(corrupt)?;
+
+        let is_exif = marker == 0xE1 && segment.get(4..).is_some_and(|body| body.starts_with(JPEG_EXIF_SIGNATURE));
+        if !is_exif {
+            output.extend_from_slice(segment);
+        }
+        if pos == 2 && marker == 0xE0 {
+            insert_at = output.len();
+        }
+        pos += 2 + length;
+    }
+
+    let mut exif_segment = vec![0xFF, 0xE1];
+    exif_segment.extend_from_slice(&(segment_length as u16).to_be_bytes());
+    exif_segment.extend_from_slice(JPEG_EXIF_SIGNATURE);
+    exif_segment.extend_from_slice(tiff);
+
+    output.splice(insert_at..insert_at, exif_segment);
+    output.extend_from_slice(&data[pos..]);
+    Ok(output)
+}
+
+// Drop any eXIf chunk and write the new one before the first IDAT
+fn replace_png_exif(data: &[u8], tiff: &[u8]) -> Result<Vec<u8>, AppError> {
+    let corrupt = || AppError::CorruptImage("Truncated PNG chunk".to_string());
+
+    let mut output = data[..8].to_vec();
+    let mut pos = 8;
+    let mut written = false;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let chunk_type = &data[pos + 4..pos + 8];
+        let chunk = data.get(pos..pos + 12 + length).ok_or_else(corrupt)?;
+
+        if chunk_type == b"IDAT" && !written {
+            write_png_chunk(&mut output, b"eXIf", tiff);
+            written = true;
+        }
+        if chunk_type != b"eXIf" {
+            output.extend_from_slice(chunk);
+        }
+        pos += 12 + length;
+    }
+
+    if !written {
+        return Err(corrupt());
+    }
+    Ok(output)
+}
+
+const JPEG_ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+// Profile bytes per APP2 segment: the 16-bit length minus itself, the
+// signature and the sequence/count bytes
+const JPEG_ICC_CHUNK: usize = 65_535 - 2 - 12 - 2;
+
+// Carry the EXIF block, ICC profile and XMP packet of a JPEG, PNG or WebP
+// `source` over to a freshly encoded JPEG or PNG. When `oriented`, the
+// pixels were already turned upright, so the copied Orientation is reset to
+// 1. Other output formats come back unchanged.
+pub fn copy_metadata(source: &[u8], output: Vec<u8>, oriented: bool) -> Result<Vec<u8>, AppError> {
+    let is_jpeg = output.starts_with(&[0xFF, 0xD8]);
+    let is_png = output.starts_with(b"\x89PNG");
+    // A TIFF's "EXIF block" is the whole file, pixels included
+    let source_supported = source.starts_with(&[0xFF, 0xD8])
+        || source.starts_with(b"\x89PNG")
+        || (source.starts_with(b"RIFF") && source.get(8..12) == Some(b"WEBP"));
+    if !(is_jpeg || is_png) || !source_supported {
+        return Ok(output);
+    }
+
+    let mut output = output;
+    if let Some(exif) = read_exif(source) {
+        let mut tiff = exif.buf().to_vec();
+        if oriented {
+            reset_tiff_orientation(&mut tiff);
+        }
+        output = if is_jpeg { replace_jpeg_exif(&output, &tiff)? } else { replace_png_exif(&output, &tiff)? };
+    }
+
+    let icc = icc_profile(source);
+    let xmp = find_xmp_packet(source);
+    if is_jpeg {
+        let mut segments = Vec::new();
+        if let Some(xmp) = &xmp {
+            segments.push((0xE1, [JPEG_XMP_SIGNATURE, xmp.as_bytes()].concat()));
+        }
+        if let Some(icc) = &icc {
+            let count = icc.len().div_ceil(JPEG_ICC_CHUNK);
+            if count > u8::MAX as usize {
+                return Err(AppError::InvalidFieldValue("ICC profile too large for JPEG".to_string()));
+            }
+            for (i, chunk) in icc.chunks(JPEG_ICC_CHUNK).enumerate() {
+                segments.push((0xE2, [JPEG_ICC_SIGNATURE, &[i as u8 + 1, count as u8], chunk].concat()));
+            }
+        }
+        insert_jpeg_segments(&output, &segments)
+    } else {
+        add_png_icc_xmp(&output, icc.as_deref(), xmp.as_deref())
+    }
+}
+
+fn icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    use image::ImageDecoder;
+    let reader = image::ImageReader::new(Cursor::new(data)).with_guessed_format().ok()?;
+    reader.into_decoder().ok()?.icc_profile().ok()?.filter(|icc| !icc.is_empty())
+}
+
+// Set an IFD0 Orientation tag to 1 (upright) in place
+fn reset_tiff_orientation(tiff: &mut [u8]) {
+    let little_endian = tiff.starts_with(b"II");
+    let read_u16 = |b: &[u8]| if little_endian { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) };
+    let Some(offset) = tiff.get(4..8).map(|b| {
+        let b = [b[0], b[1], b[2], b[3]];
+        (if little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) }) as usize
+    }) else { return };
+    let Some(count) = tiff.get(offset..offset + 2).map(read_u16) else { return };
+
+    for i in 0..count as usize {
+        let entry = offset + 2 + 12 * i;
+        let Some(header) = tiff.get(entry..entry + 4) else { return };
+        // Orientation, stored as a SHORT in the first two value bytes
+        if read_u16(&header[..2]) == 0x0112 && read_u16(&header[2..]) == 3 {
+            let one = if little_endian { 1u16.to_le_bytes() } else { 1u16.to_be_bytes() };
+            if let Some(value) = tiff.get_mut(entry + 8..entry + 10) {
+                value.copy_from_slice(&one);
+            }
+            return;
+        }
+    }
+}
+
+// Insert APPn segments (marker, body) after the JPEG's existing APPn run,
+// so JFIF and Exif stay first
+fn insert_jpeg_segments(data: &[u8], segments: &[(u8, Vec<u8>)]) -> Result<Vec<u8>, AppError> {
+    let mut pos = 2;
+    while data.get(pos) == Some(&0xFF) && data.get(pos + 1).is_some_and(|marker| (0xE0..=0xEF).contains(marker)) {
+        let length = data.get(pos + 2..pos + 4).ok_or_else(|| AppError::CorruptImage("Truncated JPEG segment".to_string()))?;
+        pos += 2 + u16::from_be_bytes([length[0], length[1]]) as usize;
+    }
+
+    let mut inserted = Vec::new();
+    for (marker, body) in segments {
+        let length = body.len() + 2;
+        if length > u16::MAX as usize {
+            return Err(AppError::InvalidFieldValue("Metadata too large for a JPEG segment".to_string()));
+        }
+        inserted.extend_from_slice(&[0xFF, *marker]);
+        inserted.extend_from_slice(&(length as u16).to_be_bytes());
+        inserted.extend_from_slice(body);
+    }
+
+    let mut output = data.to_vec();
+    output.splice(pos.min(data.len())..pos.min(data.len()), inserted);
+    Ok(output)
+}
+
+// Write an iCCP chunk after IHDR and an XMP iTXt chunk before the image
+// data, replacing any existing ones
+fn add_png_icc_xmp(data: &[u8], icc: Option<&[u8]>, xmp: Option<&str>) -> Result<Vec<u8>, AppError> {
+    let corrupt = || AppError::CorruptImage("Truncated PNG chunk".to_string());
+    let iccp = icc.map(|icc| {
+        let mut encoder = flate2::write::ZlibEncoder::new(b"ICC Profile\0\0".to_vec(), flate2::Compression::default());
+        encoder.write_all(icc).and_then(|_| encoder.finish())
+    }).transpose().map_err(|e| AppError::ImageProcessingError(format!("Failed to compress ICC profile: {}", e)))?;
+
+    let mut output = data[..8].to_vec();
+    let mut pos = 8;
+    let mut xmp_written = xmp.is_none();
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let chunk_type = &data[pos + 4..pos + 8];
+        let chunk = data.get(pos..pos + 12 + length).ok_or_else(corrupt)?;
+
+        if chunk_type == b"IDAT" && !xmp_written {
+            if let Some(xmp) = xmp {
+                write_png_chunk(&mut output, b"iTXt", &[PNG_XMP_KEYWORD, b"\0\0\0\0\0", xmp.as_bytes()].concat());
+            }
+            xmp_written = true;
+        }
+        let replaced = (iccp.is_some() && (chunk_type == b"iCCP" || chunk_type == b"sRGB"))
+            || (xmp.is_some() && chunk_type == b"iTXt" && chunk[8..].starts_with(PNG_XMP_KEYWORD));
+        if !replaced {
+            output.extend_from_slice(chunk);
+        }
+        if chunk_type == b"IHDR" {
+            if let Some(iccp) = &iccp {
+                write_png_chunk(&mut output, b"iCCP", iccp);
+            }
+        }
+        pos += 12 + length;
+    }
+    Ok(output)
+}
+
+// Drop any pHYs chunk and record `dpi` (as pixels per meter, PNG's only
+// physical unit) right after IHDR
+pub fn set_png_density(data: &[u8], dpi: u16) -> Result<Vec<u8>, AppError> {
+    let corrupt = || AppError::CorruptImage("Truncated PNG chunk".to_string());
+    let pixels_per_meter = (dpi as f64 / 0.0254).round() as u32;
+    let mut phys = Vec::with_capacity(9);
+    phys.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    phys.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    phys.push(1);
+
+    let mut output = data[..8].to_vec();
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let chunk_type = &data[pos + 4..pos + 8];
+        let chunk = data.get(pos..pos + 12 + length).ok_or_else(corrupt)?;
+
+        if chunk_type != b"pHYs" {
+            output.extend_from_slice(chunk);
+        }
+        if chunk_type == b"IHDR" {
+            write_png_chunk(&mut output, b"pHYs", &phys);
+        }
+        pos += 12 + length;
+    }
+    Ok(output)
+}
+
+// Keyword/value pairs from tEXt, zTXt and iTXt chunks. XMP is left out;
+// it's reported separately.
+pub fn png_text_chunks(data: &[u8]) -> BTreeMap<String, String> {
+    let mut text = BTreeMap::new();
+    if !data.starts_with(b"\x89PNG") {
+        return text;
+    }
+
+    let mut pos = 8;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let length = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let Some(chunk) = data.get(pos + 8..pos + 8 + length) else { break };
+        if let Some((keyword, value)) = parse_png_text(&header[4..], chunk) {
+            if keyword.as_bytes() != PNG_XMP_KEYWORD {
+                text.insert(keyword, value);
+            }
+        }
+        pos += 12 + length;
+    }
+    text
+}
+
+fn parse_png_text(chunk_type: &[u8], chunk: &[u8]) -> Option<(String, String)> {
+    let split = chunk.iter().position(|&b| b == 0)?;
+    let keyword = latin1(&chunk[..split]);
+    let rest = &chunk[split + 1..];
+
+    let value = match chunk_type {
+        b"tEXt" => latin1(rest),
+        // compression method, then deflated Latin-1
+        b"zTXt" => latin1(&inflate_text(rest.get(1..)?)?),
+        // compression flag and method, language\0, translated keyword\0, UTF-8
+        b"iTXt" => {
+            let compressed = *rest.first()? == 1;
+            let rest = rest.get(2..)?;
+            let rest = &rest[rest.iter().position(|&b| b == 0)? + 1..];
+            let text = &rest[rest.iter().position(|&b| b == 0)? + 1..];
+            let bytes = if compressed { inflate_text(text)? } else { text.to_vec() };
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        _ => return None,
+    };
+    Some((keyword, value))
+}
+
+// Inflate a compressed text payload, or None when it is corrupt or would
+// exceed MAX_INFLATED_TEXT
+fn inflate_text(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(bytes).take(MAX_INFLATED_TEXT + 1).read_to_end(&mut inflated).ok()?;
+    (inflated.len() as u64 <= MAX_INFLATED_TEXT).then_some(inflated)
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+// Set one PNG text entry, replacing any chunk with the same keyword.
+// ASCII values go in tEXt, anything else in UTF-8 iTXt.
+pub fn write_png_text(data: &[u8], keyword: &str, value: &str) -> Result<Vec<u8>, AppError> {
+    if !data.starts_with(b"\x89PNG") {
+        return Err(AppError::InvalidFieldValue("Text chunks can only be written to PNG".to_string()));
+    }
+    let valid_keyword = (1..=79).contains(&keyword.len())
+        && keyword.bytes().all(|b| (0x20..=0x7E).contains(&b))
+        && !keyword.starts_with(' ')
+        && !keyword.ends_with(' ');
+    if !valid_keyword {
+        return Err(AppError::InvalidFieldValue(format!(
+            "Invalid PNG text keyword '{}' (1-79 printable ASCII characters)",
+            keyword
+        )));
+    }
+    let corrupt = || AppError::CorruptImage("Truncated PNG chunk".to_string());
+
+    let mut body = keyword.as_bytes().to_vec();
+    body.push(0);
+    let chunk_type = if value.is_ascii() {
+        b"tEXt"
+    } else {
+        body.extend_from_slice(&[0, 0, 0, 0]);
+        b"iTXt"
+    };
+    body.extend_from_slice(value.as_bytes());
+
+    let mut output = data[..8].to_vec();
+    let mut pos = 8;
+    let mut written = false;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let chunk_type_here = &data[pos + 4..pos + 8];
+        let chunk = data.get(pos..pos + 12 + length).ok_or_else(corrupt)?;
+
+        if chunk_type_here == b"IDAT" && !written {
+            write_png_chunk(&mut output, chunk_type, &body);
+            written = true;
+        }
+        let same_keyword = parse_png_text(chunk_type_here, &chunk[8..8 + length])
+            .is_some_and(|(existing, _)| existing == keyword);
+        if !same_keyword {
+            output.extend_from_slice(chunk);
+        }
+        pos += 12 + length;
+    }
+
+    if !written {
+        return Err(corrupt());
+    }
+    Ok(output)
+}
+
+fn write_png_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], body: &[u8]) {
+    let mut crc = flate2::Crc::new();
+    crc.update(chunk_type);
+    crc.update(body);
+    output.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(body);
+    output.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+// Locate the raw XMP packet in a JPEG, PNG or WebP container
+pub fn find_xmp_packet(data: &[u8]) -> Option<String> {
+    let packet = match data {
+        [0xFF, 0xD8, ..] => jpeg_xmp(data),
+        [0x89, b'P', b'N', b'G', ..] => png_xmp(data),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => webp_xmp(data),
+        _ => None,
+    }?;
+
+    String::from_utf8(packet).ok()
+}
+
+fn jpeg_xmp(data: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // Start of scan: no more metadata segments follow
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + length)?;
+        if marker == 0xE1 && segment.starts_with(JPEG_XMP_SIGNATURE) {
+            return Some(segment[JPEG_XMP_SIGNATURE.len()..].to_vec());
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+fn png_xmp(data: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        let chunk_type = &data[pos + 4..pos + 8];
+        let chunk = data.get(pos + 8..pos + 8 + length)?;
+
+        if chunk_type == b"iTXt" && chunk.starts_with(PNG_XMP_KEYWORD) {
+            // keyword\0 compression_flag compression_method language\0 translated\0 text
+            let rest = chunk.get(PNG_XMP_KEYWORD.len() + 1..)?;
+            let compressed = *rest.first()? == 1;
+            let rest = rest.get(2..)?;
+            let rest = &rest[rest.iter().position(|&b| b == 0)? + 1..];
+            let text = &rest[rest.iter().position(|&b| b == 0)? + 1..];
+
+            return if compressed { inflate_text(text) } else { Some(text.to_vec()) };
+        }
+        if chunk_type == b"IEND" {
+            return None;
+        }
+        pos += 12 + length;
+    }
+    None
+}
+
+fn webp_xmp(data: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let fourcc = &data[pos..pos + 4];
+        let length = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().ok()?) as usize;
+        let chunk = data.get(pos + 8..pos + 8 + length)?;
+        if fourcc == b"XMP " {
+            return Some(chunk.to_vec());
+        }
+        // Chunks are padded to an even size
+        pos += 8 + length + (length & 1);
+    }
+    None
+}
+
+// Pull the commonly used Dublin Core / XMP fields out of an XMP packet
+pub fn parse_xmp(packet: &str) -> XmpInfo {
+    XmpInfo {
+        title: xmp_values(packet, "dc:title").into_iter().next(),
+        creator: xmp_values(packet, "dc:creator"),
+        rating: xmp_values(packet, "xmp:Rating").into_iter().next(),
+        copyright: xmp_values(packet, "dc:rights").into_iter().next(),
+    }
+}
+
+// Values of a property written either as an attribute (`name="value"`) or as
+// an element, where the element may wrap an rdf:Alt/Seq/Bag list of rdf:li
+fn xmp_values(packet: &str, name: &str) -> Vec<String> {
+    let attribute = format!("{}=\"", name);
+    if let Some(start) = packet.find(&attribute) {
+        let value = &packet[start + attribute.len()..];
+        if let Some(end) = value.find('"') {
+            return vec![unescape_xml(&value[..end])];
+        }
+    }
+
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let Some(start) = packet.find(&open) else {
+        return Vec::new();
+    };
+    let Some(content_start) = packet[start..].find('>').map(|i| start + i + 1) else {
+        return Vec::new();
+    };
+    let Some(content_end) = packet[content_start..].find(&close).map(|i| content_start + i) else {
+        return Vec::new();
+    };
+    let content = &packet[content_start..content_end];
+
+    let mut values = Vec::new();
+    let mut rest = content;
+    while let Some(li) = rest.find("<rdf:li") {
+        let after = &rest[li..];
+        let (Some(gt), Some(end)) = (after.find('>'), after.find("</rdf:li>")) else {
+            break;
+        };
+        if gt < end {
+            values.push(unescape_xml(after[gt + 1..end].trim()));
+        }
+        rest = &after[end + "</rdf:li>".len()..];
+    }
+
+    if values.is_empty() && !content.trim().is_empty() {
+        values.push(unescape_xml(content.trim()));
+    }
+    values
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description>
+<dc:creator><rdf:Seq><rdf:li>Ana Lima</rdf:li><rdf:li>Tom &amp; Co</rdf:li></rdf:Seq></dc:creator>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+    #[test]
+    fn xmp_creator_is_read_from_png_and_webp() {
+        // PNG: signature, then an uncompressed iTXt chunk before IEND
+        let mut itxt = PNG_XMP_KEYWORD.to_vec();
+        itxt.extend_from_slice(b"\0\0\0\0\0");
+        itxt.extend_from_slice(PACKET.as_bytes());
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_png_chunk(&mut png, b"iTXt", &itxt);
+        write_png_chunk(&mut png, b"IEND", &[]);
+
+        // WebP: RIFF header, then the XMP chunk
+        let mut webp = b"RIFF\0\0\0\0WEBPXMP ".to_vec();
+        webp.extend_from_slice(&(PACKET.len() as u32).to_le_bytes());
+        webp.extend_from_slice(PACKET.as_bytes());
+
+        for data in [png, webp] {
+            let xmp = parse_xmp(&find_xmp_packet(&data).unwrap());
+            assert_eq!(xmp.creator, ["Ana Lima", "Tom & Co"]);
+        }
+    }
+
+    #[test]
+    fn oversized_ztxt_is_skipped_instead_of_inflated() {
+        let deflate = |bytes: &[u8]| {
+            let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(bytes).unwrap();
+            encoder.finish().unwrap()
+        };
+        let ztxt = |keyword: &str, value: &[u8]| {
+            let mut body = keyword.as_bytes().to_vec();
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(&deflate(value));
+            body
+        };
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_png_chunk(&mut png, b"zTXt", &ztxt("Comment", b"small"));
+        write_png_chunk(&mut png, b"zTXt", &ztxt("Bomb", &vec![b'a'; MAX_INFLATED_TEXT as usize + 1]));
+        write_png_chunk(&mut png, b"IEND", &[]);
+
+        let text = png_text_chunks(&png);
+        assert_eq!(text.get("Comment").map(String::as_str), Some("small"));
+        assert!(!text.contains_key("Bomb"));
+    }
+
+    #[test]
+    fn compressed_xmp_is_read_up_to_the_inflate_cap() {
+        let png_with_xmp = |packet: &[u8]| {
+            let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(packet).unwrap();
+            let mut itxt = PNG_XMP_KEYWORD.to_vec();
+            itxt.extend_from_slice(b"\0\x01\0\0\0");
+            itxt.extend_from_slice(&encoder.finish().unwrap());
+            let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+            write_png_chunk(&mut png, b"iTXt", &itxt);
+            write_png_chunk(&mut png, b"IEND", &[]);
+            png
+        };
+
+        let xmp = find_xmp_packet(&png_with_xmp(PACKET.as_bytes())).unwrap();
+        assert_eq!(parse_xmp(&xmp).creator, ["Ana Lima", "Tom & Co"]);
+
+        let mut bomb = PACKET.as_bytes().to_vec();
+        bomb.resize(MAX_INFLATED_TEXT as usize + 1, b' ');
+        assert!(find_xmp_packet(&png_with_xmp(&bomb)).is_none());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::types::AppError;
+use crate::validation::validate_upscale;
+use image::{imageops, DynamicImage, RgbImage};
+use ort::{session::Session, value::Tensor};
+use std::{env, path::Path, sync::{Mutex, OnceLock}};
+
+// Default location of the bundled Real-ESRGAN model (override with ESRGAN_MODEL_PATH)
+const DEFAULT_MODEL_PATH: &str = "assets/realesrgan-x4.onnx";
+
+// Tiles keep memory bounded on large inputs; the overlap hides seams
+const TILE_SIZE: u32 = 128;
+const TILE_OVERLAP: u32 = 8;
+
+static SESSION: OnceLock<Option<Mutex<Session>>> = OnceLock::new();
+
+fn session() -> Result<Option<&'static Mutex<Session>>, AppError> {
+    let path = env::var("ESRGAN_MODEL_PATH").unwrap_or_else(|_| DEFAULT_MODEL_PATH.to_string());
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+
+    if let Some(session) = SESSION.get() {
+        return Ok(session.as_ref());
+    }
+
+    let session = Session::builder()
+        .and_then(|builder| builder.commit_from_file(&path))
+        .map_err(|e| AppError::ImageProcessingError(format!("Failed to load ESRGAN model: {}", e)))?;
+
+    Ok(SESSION.get_or_init(|| Some(Mutex::new(session))).as_ref())
+}
+
+// Run the ESRGAN model over the image tile by tile. Returns None when the
+// model file is not available so callers can fall back to Lanczos.
+pub fn upscale(img: &DynamicImage) -> Result<Option<DynamicImage>, AppError> {
+    let Some(session) = session()? else {
+        return Ok(None);
+    };
+    let mut session = session
+        .lock()
+        .map_err(|_| AppError::ImageProcessingError("ESRGAN session poisoned".to_string()))?;
+
+    let rgb = img.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let mut output: Option<RgbImage> = None;
+    let mut scale = 0;
+
+    for tile_y in (0..height).step_by(TILE_SIZE as usize) {
+        for tile_x in (0..width).step_by(TILE_SIZE as usize) {
+            let x0 = tile_x.saturating_sub(TILE_OVERLAP);
+            let y0 = tile_y.saturating_sub(TILE_OVERLAP);
+            let x1 = (tile_x + TILE_SIZE + TILE_OVERLAP).min(width);
+            let y1 = (tile_y + TILE_SIZE + TILE_OVERLAP).min(height);
+
+            let tile = imageops::crop_imm(&rgb, x0, y0, x1 - x0, y1 - y0).to_image();
+            let result = run_tile(&mut session, &tile)?;
+
+            if output.is_none() {
+                scale = result.width() / tile.width();
+                output = Some(model_canvas(width, height, scale)?);
+            }
+            let canvas = output.as_mut().unwrap();
+
+            // Copy only the non-overlapping core of the tile
+            let core_w = TILE_SIZE.min(width - tile_x);
+            let core_h = TILE_SIZE.min(height - tile_y);
+            for y in 0..core_h * scale {
+                for x in 0..core_w * scale {
+                    let src_x = (tile_x - x0) * scale + x;
+                    let src_y = (tile_y - y0) * scale + y;
+                    canvas.put_pixel(tile_x * scale + x, tile_y * scale + y, *result.get_pixel(src_x, src_y));
+                }
+            }
+        }
+    }
+
+    Ok(output.map(DynamicImage::ImageRgb8))
+}
+
+// The canvas for the model's full output. The model always runs at its own
+// factor, which can be larger than the scale the request asked for, so the
+// size limits are checked again before allocating it.
+fn model_canvas(width: u32, height: u32, scale: u32) -> Result<RgbImage, AppError> {
+    validate_upscale(width, height, scale).map_err(|e| match e {
+        AppError::DimensionsTooLarge(message) => {
+            AppError::DimensionsTooLarge(format!("ESRGAN upscales {}x before resizing: {}", scale, message))
+        }
+        other => other,
+    })?;
+    Ok(RgbImage::new(width * scale, height * scale))
+}
+
+fn run_tile(session: &mut Session, tile: &RgbImage) -> Result<RgbImage, AppError> {
+    let (width, height) = tile.dimensions();
+    let plane = (width * height) as usize;
+
+    // NCHW float input in [0, 1]
+    let mut input = vec![0f32; plane * 3];
+    for (i, pixel) in tile.pixels().enumerate() {
+        for c in 0..3 {
+            input[c * plane + i] = pixel[c] as f32 / 255.0;
+        }
+    }
+
+    let tensor = Tensor::from_array(([1usize, 3, height as usize, width as usize], input))
+        .map_err(|e| AppError::ImageProcessingError(format!("ESRGAN input error: {}", e)))?;
+    let outputs = session
+        .run(ort::inputs![tensor])
+        .map_err(|e| AppError::ImageProcessingError(format!("ESRGAN inference failed: {}", e)))?;
+    let (shape, data) = outputs[0]
+        .try_extract_tensor::<f32>()
+        .map_err(|e| AppError::ImageProcessingError(format!("ESRGAN output error: {}", e)))?;
+
+    let (out_h, out_w) = (shape[2] as u32, shape[3] as u32);
+    let out_plane = (out_w * out_h) as usize;
+    Ok(RgbImage::from_fn(out_w, out_h, |x, y| {
+        let i = (y * out_w + x) as usize;
+        let channel = |c: usize| (data[c * out_plane + i].clamp(0.0, 1.0) * 255.0).round() as u8;
+        image::Rgb([channel(0), channel(1), channel(2)])
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_utils::upscale_with_model;
+    use image::GenericImageView;
+
+    #[test]
+    fn model_upscale_lands_on_the_requested_size() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(24, 16, |x, y| image::Rgb([(x * 10) as u8, (y * 15) as u8, 128])));
+        for factor in [2, 4] {
+            let upscaled = upscale_with_model(&img, 24 * factor, 16 * factor).unwrap();
+            assert_eq!(upscaled.dimensions(), (24 * factor, 16 * factor));
+        }
+
+        // Without the model file there is nothing more to check
+        if let Some(raw) = upscale(&img).unwrap() {
+            let scale = raw.width() / 24;
+            assert!(scale >= 2);
+            assert_eq!(raw.dimensions(), (24 * scale, 16 * scale));
+        }
+    }
+
+    #[test]
+    fn model_canvas_is_checked_at_the_model_factor() {
+        assert_eq!(model_canvas(24, 16, 4).unwrap().dimensions(), (96, 64));
+        // Fine at 2x, but the x4 model would need 144MP
+        assert!(validate_upscale(3000, 3000, 2).is_ok());
+        assert!(matches!(model_canvas(3000, 3000, 4), Err(AppError::DimensionsTooLarge(_))));
+    }
+}
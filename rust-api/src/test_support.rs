@@ -0,0 +1,110 @@
+// Helpers for calling the multipart handlers directly from unit tests
+use axum::{
+    body::{to_bytes, Body},
+    extract::FromRequest,
+    http::{header, HeaderMap, Request, StatusCode},
+    response::IntoResponse,
+};
+use axum_extra::extract::Multipart;
+use exif::Field;
+use image::DynamicImage;
+use std::future::Future;
+use std::io::{Cursor, Read};
+
+use crate::image_utils::encode_image_to_bytes;
+use crate::types::{EncodeOptions, ImageFormat};
+
+const BOUNDARY: &str = "gimg-test-boundary";
+
+// One form part: a text field, or an upload with its file name
+pub enum Part<'a> {
+    Text(&'a str, &'a str),
+    File(&'a str, &'a str, &'a [u8]),
+}
+
+pub async fn form(parts: &[Part<'_>]) -> Multipart {
+    let mut body = Vec::new();
+    for part in parts {
+        body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+        match part {
+            Part::Text(name, value) => {
+                body.extend_from_slice(format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n{}", name, value).as_bytes());
+            }
+            Part::File(name, file_name, data) => {
+                body.extend_from_slice(format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                    name, file_name
+                ).as_bytes());
+                body.extend_from_slice(data);
+            }
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+
+    let request = Request::builder()
+        .method("POST")
+        .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+        .body(Body::from(body))
+        .unwrap();
+    Multipart::from_request(request, &()).await.unwrap()
+}
+
+// Run a handler on a form and collect the status, headers and whole body
+pub async fn post<H, F, R>(handler: H, parts: &[Part<'_>]) -> (StatusCode, HeaderMap, Vec<u8>)
+where
+    H: FnOnce(Multipart) -> F,
+    F: Future<Output = R>,
+    R: IntoResponse,
+{
+    let (head, body) = handler(form(parts).await).await.into_response().into_parts();
+    (head.status, head.headers, to_bytes(body, usize::MAX).await.unwrap().to_vec())
+}
+
+pub fn encode(img: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+    encode_image_to_bytes(img, format, &EncodeOptions::default()).unwrap().data
+}
+
+// Give a freshly encoded JPEG (no APP1 yet) an EXIF segment holding `fields`
+pub fn with_exif(jpeg: &[u8], fields: &[Field]) -> Vec<u8> {
+    insert_exif(jpeg, fields, None)
+}
+
+// Like `with_exif`, plus `thumbnail` as the IFD1 JPEG thumbnail
+pub fn with_exif_thumbnail(jpeg: &[u8], fields: &[Field], thumbnail: &[u8]) -> Vec<u8> {
+    insert_exif(jpeg, fields, Some(thumbnail))
+}
+
+fn insert_exif(jpeg: &[u8], fields: &[Field], thumbnail: Option<&[u8]>) -> Vec<u8> {
+    let mut writer = exif::experimental::Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    if let Some(thumbnail) = thumbnail {
+        writer.set_jpeg(thumbnail, exif::In::THUMBNAIL);
+    }
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, false).unwrap();
+    let tiff = tiff.into_inner();
+
+    let mut output = jpeg[..2].to_vec();
+    output.extend_from_slice(&[0xFF, 0xE1]);
+    output.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    output.extend_from_slice(b"Exif\0\0");
+    output.extend_from_slice(&tiff);
+    output.extend_from_slice(&jpeg[2..]);
+    output
+}
+
+// Every entry of a ZIP response, in archive order
+pub fn unzip(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+    (0..archive.len())
+        .map(|i| {
+            let mut entry = archive.by_index(i).unwrap();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            (entry.name().to_string(), contents)
+        })
+        .collect()
+}
@@ -0,0 +1,448 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::types::{default_quality, default_resize_filter, DEFAULT_EFFORT};
+
+#[derive(Serialize)]
+pub struct Tool {
+    name: String,
+    description: String,
+    parameters: Vec<ToolParam>,
+}
+
+// One multipart field a tool accepts, so frontends can build forms from /tools
+#[derive(Clone, Serialize)]
+pub struct ToolParam {
+    name: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    values: Option<Vec<&'static str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<Value>,
+}
+
+impl ToolParam {
+    fn new(name: &'static str, kind: &'static str) -> Self {
+        ToolParam { name, kind, required: false, values: None, min: None, max: None, default: None }
+    }
+
+    fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    fn values(mut self, values: &[&'static str]) -> Self {
+        self.values = Some(values.to_vec());
+        self
+    }
+
+    fn min(mut self, min: impl Into<Value>) -> Self {
+        self.min = Some(min.into());
+        self
+    }
+
+    fn range(mut self, min: impl Into<Value>, max: impl Into<Value>) -> Self {
+        self.min = Some(min.into());
+        self.max = Some(max.into());
+        self
+    }
+
+    fn default(mut self, default: Value) -> Self {
+        self.default = Some(default);
+        self
+    }
+}
+
+fn string(name: &'static str) -> ToolParam {
+    ToolParam::new(name, "string")
+}
+
+fn int(name: &'static str) -> ToolParam {
+    ToolParam::new(name, "int")
+}
+
+fn float(name: &'static str) -> ToolParam {
+    ToolParam::new(name, "float")
+}
+
+fn boolean(name: &'static str) -> ToolParam {
+    ToolParam::new(name, "bool").default(json!(false))
+}
+
+fn color(name: &'static str, default: &str) -> ToolParam {
+    ToolParam::new(name, "color").default(json!(default))
+}
+
+const FORMATS: &[&str] = &["jpg", "png", "webp", "bmp", "tiff", "gif", "ico"];
+
+// Fields every image endpoint takes: `file` or `url` for the input
+fn input_params() -> Vec<ToolParam> {
+    vec![ToolParam::new("file", "file").required(), string("url")]
+}
+
+// Input fields plus `orient` and `disposition` for endpoints that return an
+// image
+fn image_params(params: Vec<ToolParam>) -> Vec<ToolParam> {
+    let mut all = input_params();
+    all.extend(params);
+    all.push(orient());
+    all.push(string("disposition").values(&["inline", "attachment"]).default(json!("attachment")));
+    all
+}
+
+// Fields parsed by EncodeOptions
+fn encode_params() -> Vec<ToolParam> {
+    vec![
+        int("quality").range(1, 100).default(json!(default_quality())),
+        string("subsampling").values(&["444", "422", "420"]).default(json!("420")),
+        int("compression_level").range(0, 9),
+        boolean("progressive"),
+        boolean("optimize"),
+        int("restart_interval").range(0, 65535).default(json!(0)),
+        boolean("lossless"),
+        string("tiff_compression").values(&["none", "lzw", "deflate", "packbits"]).default(json!("lzw")),
+        int("effort").range(0, 9).default(json!(DEFAULT_EFFORT)),
+    ]
+}
+
+// Apply the EXIF orientation before processing
+fn orient() -> ToolParam {
+    ToolParam::new("orient", "bool").default(json!(true))
+}
+
+fn tool(name: &str, description: &str, parameters: Vec<ToolParam>) -> Tool {
+    Tool { name: name.to_string(), description: description.to_string(), parameters }
+}
+
+pub fn get_tools() -> Vec<Tool> {
+    let dither = || string("dither").values(&["none", "floyd-steinberg", "ordered"]);
+    let blur_algo = || string("blur_algo").values(&["gaussian", "box"]).default(json!("gaussian"));
+    let resize_filter = || {
+        string("filter")
+            .values(&["nearest", "bilinear", "bicubic", "gaussian", "lanczos3"])
+            .default(json!(default_resize_filter().as_str()))
+    };
+
+    vec![
+        tool("compress", "Compress images by reducing quality", image_params([
+            encode_params(),
+            vec![boolean("palette"), int("colors").range(2, 256), dither().default(json!("none")), boolean("preserve_metadata")],
+        ].concat())),
+        tool("resize", "Resize images by dimensions or percentage", image_params(vec![
+            int("width").min(1),
+            int("height").min(1),
+            float("percentage"),
+            int("max_size").min(1),
+            boolean("lock_aspect"),
+            ToolParam::new("premultiply", "bool").default(json!(true)),
+            resize_filter(),
+            string("downscale_filter").values(&["nearest", "bilinear", "bicubic", "gaussian", "lanczos3"]),
+            string("upscale_filter").values(&["nearest", "bilinear", "bicubic", "gaussian", "lanczos3"]),
+            string("pad_mode").values(&["color", "reflect", "edge", "blur"]),
+            color("pad_color", "black"),
+            boolean("preserve_metadata"),
+        ])),
+        tool("crop", "Crop images by coordinates or aspect ratio", image_params(vec![
+            int("x").default(json!(0)),
+            int("y").default(json!(0)),
+            int("width"),
+            int("height"),
+            string("ratio"),
+            boolean("preview"),
+            boolean("preserve_metadata"),
+        ])),
+        tool("trim", "Crop away a solid-color or transparent border", image_params(vec![
+            boolean("alpha"),
+            int("tolerance").range(0, 255).default(json!(0)),
+        ])),
+        tool("rotate", "Rotate images by degrees or auto-orient", image_params(vec![
+            float("degrees"),
+            boolean("auto"),
+            boolean("auto_crop"),
+            string("interpolation").values(&["nearest", "bilinear", "bicubic"]).default(json!("bilinear")),
+            boolean("preserve_metadata"),
+        ])),
+        tool("convert", "Convert images between formats", image_params([
+            vec![string("format").required().values(FORMATS)],
+            encode_params(),
+            vec![
+                boolean("palette"),
+                int("colors").range(2, 256),
+                string("color_mode").values(&["rgb", "rgba", "grayscale", "bilevel"]),
+                int("threshold").range(0, 255),
+                dither(),
+                boolean("force_reencode"),
+                boolean("preserve_metadata"),
+            ],
+        ].concat())),
+        tool("info", "Get image info (dimensions, format, size, mode)", [
+            input_params(),
+            vec![string("fields").values(&["dimensions"]), boolean("count_colors")],
+        ].concat()),
+        tool("detect-format", "Identify a file's format (MIME, extension, confidence), including ones that can't be decoded", input_params()),
+        tool("info-batch", "Stream info for several files as NDJSON, one line per file", vec![
+            ToolParam::new("file", "file").required(),
+        ]),
+        tool("metadata", "View or strip EXIF metadata, or set a PNG text chunk", image_params(vec![
+            boolean("strip"),
+            string("set_text"),
+        ])),
+        tool("exif-edit", "Write EXIF text tags into a JPEG or PNG without re-encoding it", [
+            input_params(),
+            vec![
+                string("Artist"),
+                string("Copyright"),
+                string("ImageDescription"),
+                string("Software"),
+                string("DateTime"),
+                string("DateTimeOriginal"),
+                string("disposition").values(&["inline", "attachment"]).default(json!("attachment")),
+            ],
+        ].concat()),
+        tool("strip-all", "Strip EXIF, GPS and XMP from several files and download them as a ZIP", [
+            vec![ToolParam::new("file", "file").required()],
+            encode_params(),
+        ].concat()),
+        tool("watermark", "Add text or image watermarks", image_params(vec![
+            string("text").required(),
+            string("position")
+                .values(&["top-left", "top-right", "bottom-left", "bottom-right", "center"])
+                .default(json!("bottom-right")),
+            float("opacity").range(0.0, 1.0).default(json!(0.3)),
+            int("size"),
+            float("size_pct").range(0.0, 100.0).default(json!(5.0)),
+            color("color", "white"),
+            boolean("tile"),
+            float("angle").default(json!(0.0)),
+            boolean("preserve_metadata"),
+        ])),
+        tool("blur-face", "Detect and blur faces", image_params(vec![
+            int("strength").default(json!(25)),
+            string("region"),
+            blur_algo(),
+            string("style").values(&["gaussian", "pixelate", "fill"]).default(json!("gaussian")),
+            string("shape").values(&["box", "ellipse"]).default(json!("box")),
+            color("fill_color", "black"),
+        ])),
+        tool("face-count", "Count the faces in an image and return their bounding boxes", [
+            input_params(),
+            vec![orient()],
+        ].concat()),
+        tool("face-crop", "Crop to the detected faces plus padding, or a centered square when there are none or the server has no face detector", image_params(vec![
+            float("padding").range(0.0, 5.0).default(json!(0.25)),
+        ])),
+        tool("remove-bg", "Remove image background", vec![]),
+        tool("upscale", "Upscale images with LANCZOS resampling", image_params(vec![
+            int("scale").range(1, 8).default(json!(2)),
+            ToolParam::new("sharpen", "bool").default(json!(true)),
+            float("sharpen_amount").range(0.0, 10.0).default(json!(1.0)),
+            int("sharpen_threshold").range(0, 255).default(json!(1)),
+            string("mode").values(&["fast", "quality"]).default(json!("fast")),
+            string("model").values(&["esrgan"]),
+            resize_filter(),
+            ToolParam::new("antialias", "bool").default(json!(true)),
+        ])),
+        tool("colorize", "Add color to grayscale photos (needs the colorize build)", image_params(vec![])),
+        tool("meme", "Add meme text (top/bottom)", image_params(vec![
+            string("top"),
+            string("bottom"),
+            int("size"),
+        ])),
+        tool("edit", "Photo editor: brightness, contrast, filters, borders, etc.", image_params(vec![
+            float("brightness").range(-1.0, 1.0),
+            float("contrast"),
+            float("saturation"),
+            float("sharpness"),
+            string("filter").values(&["grayscale", "sepia", "invert", "blur"]),
+            int("border"),
+            color("border_color", "black"),
+            string("flip").values(&["horizontal", "vertical"]),
+            boolean("auto_enhance"),
+            int("thumbnail").min(1),
+            boolean("equalize"),
+            boolean("per_channel"),
+            blur_algo(),
+            boolean("shadow"),
+            color("shadow_color", "black"),
+            float("shadow_blur").range(0.0, 100.0).default(json!(8.0)),
+            int("shadow_offset_x").default(json!(10)),
+            int("shadow_offset_y").default(json!(10)),
+            float("temperature").range(-100.0, 100.0),
+            float("tint").range(-100.0, 100.0),
+            boolean("duotone"),
+            color("duotone_shadow", "black"),
+            color("duotone_highlight", "white"),
+            ToolParam::new("levels", "json"),
+            ToolParam::new("variants", "json"),
+            boolean("preserve_metadata"),
+        ])),
+        tool("html-to-img", "Screenshot a URL (not available in web mode)", vec![]),
+        tool("compare-formats", "Compare encoded size (and PSNR) across JPEG, PNG, WebP and AVIF", [
+            input_params(),
+            vec![string("formats").default(json!("jpg,png,webp,avif")), boolean("psnr"), orient()],
+            encode_params(),
+        ].concat()),
+        tool("embedded-thumb", "Extract the EXIF thumbnail without decoding the image", image_params(vec![
+            boolean("fallback"),
+            int("size").range(1, 1024).default(json!(160)),
+        ])),
+        tool("mirror-tile", "Kaleidoscope of mirrored wedges around the center", image_params(vec![
+            int("segments").range(2, 32).default(json!(6)),
+            int("size").range(1, 4096),
+        ])),
+        tool("preview-alpha", "Show transparency over a checkerboard, as an opaque PNG", image_params(vec![
+            int("checker_size").range(1, 256).default(json!(8)),
+        ])),
+        tool("compare-visual", "Before/after overlay (onion skin) or side-by-side composite", vec![
+            ToolParam::new("before", "file").required(),
+            ToolParam::new("after", "file").required(),
+            string("mode").values(&["overlay", "side-by-side"]).default(json!("overlay")),
+            float("alpha").range(0.0, 1.0).default(json!(0.5)),
+            orient(),
+            string("disposition").values(&["inline", "attachment"]).default(json!("attachment")),
+        ]),
+        tool("color-transfer", "Recolor an image to match a reference image's palette", vec![
+            ToolParam::new("source", "file").required(),
+            ToolParam::new("reference", "file").required(),
+            orient(),
+            string("disposition").values(&["inline", "attachment"]).default(json!("attachment")),
+        ]),
+        tool("fit-to-bytes", "Downscale until the encoded image fits a byte budget", image_params([
+            vec![
+                int("max_bytes").required(),
+                string("format").values(FORMATS).default(json!("jpg")),
+                int("min_quality").range(1, 100),
+            ],
+            encode_params(),
+        ].concat())),
+        tool("annotate", "Draw rectangles, lines and text onto an image", image_params(vec![
+            ToolParam::new("shapes", "json").required(),
+        ])),
+        tool("contact-sheet", "Tile several images into a labelled grid", vec![
+            ToolParam::new("file", "file").required(),
+            int("columns").min(1).default(json!(4)),
+            int("thumb_size").min(1).default(json!(200)),
+            int("gap").min(0).default(json!(10)),
+            color("bg_color", "white"),
+            boolean("labels"),
+            orient(),
+            string("disposition").values(&["inline", "attachment"]).default(json!("attachment")),
+        ]),
+        tool("sprite-sheet", "Pack images into one sprite sheet with a JSON atlas, as a ZIP", vec![
+            ToolParam::new("file", "file").required(),
+            int("padding").min(0).default(json!(0)),
+        ]),
+        tool("spritesheet-to-gif", "Slice a sprite sheet into frames and play them as an animated GIF or WebP", image_params(vec![
+            int("rows").min(1),
+            int("cols").min(1),
+            int("frame_width").min(1),
+            int("frame_height").min(1),
+            int("frames").min(1),
+            int("delay_ms").range(1, 60000).default(json!(100)),
+            string("format").values(&["gif", "webp"]).default(json!("gif")),
+        ])),
+        tool("optimize-gif", "Shrink an animated GIF by keeping fewer, evenly spaced frames and/or fewer colors; total duration is kept", [
+            input_params(),
+            vec![
+                int("frames").min(1),
+                int("colors").range(2, 256),
+                string("disposition").values(&["inline", "attachment"]).default(json!("attachment")),
+            ],
+        ].concat()),
+        tool("denoise", "Remove noise with a median or edge-preserving bilateral filter", image_params(vec![
+            string("method").values(&["median", "bilateral"]).default(json!("median")),
+            int("radius").range(1, 10).default(json!(2)),
+            float("strength").min(0.0).default(json!(30.0)),
+        ])),
+        tool("threshold", "Binarize to black and white at a fixed or Otsu threshold", image_params(vec![
+            int("threshold").range(0, 255).default(json!(128)),
+            string("auto").values(&["otsu"]),
+            boolean("invert"),
+        ])),
+        tool("split", "Slice an image into a grid of tiles, downloaded as a ZIP", [
+            input_params(),
+            vec![
+                int("rows").min(1),
+                int("cols").min(1),
+                int("tile_width").min(1),
+                int("tile_height").min(1),
+                orient(),
+            ],
+        ].concat()),
+        tool("avatar", "Square-crop, resize and cut out a circular avatar", image_params(vec![
+            int("size").range(1, 4096).default(json!(256)),
+            int("ring").min(0).default(json!(0)),
+            color("ring_color", "white"),
+            string("format").values(&["png", "webp"]).default(json!("png")),
+        ])),
+        tool("favicon", "Multi-size .ico favicon", image_params(vec![
+            string("sizes").default(json!("16,32,48")),
+        ])),
+        tool("scale-to-print", "Resize to a physical print size and record the DPI", image_params(vec![
+            float("width_in").range(0.01, 1000.0),
+            float("height_in").range(0.01, 1000.0),
+            float("width_cm").range(0.01, 1000.0),
+            float("height_cm").range(0.01, 1000.0),
+            int("dpi").range(1, 65535).default(json!(300)),
+            string("fit").values(&["fit", "fill"]).default(json!("fill")),
+            string("format").values(&["jpeg", "png"]).default(json!("jpeg")),
+        ])),
+        tool("perspective", "Straighten a photographed document from its four corners", image_params(vec![
+            string("points").required(),
+            int("width").min(1),
+            int("height").min(1),
+            string("interpolation").values(&["nearest", "bilinear", "bicubic"]).default(json!("bilinear")),
+        ])),
+        tool("raw", "Decoded pixels as raw RGBA8 bytes, with X-Width/X-Height/X-Channels headers", image_params(vec![
+            int("width").min(1),
+            int("height").min(1),
+            resize_filter(),
+        ])),
+        tool("placeholder", "BlurHash and tiny blurred thumbnail for progressive loading", [
+            input_params(),
+            vec![
+                int("components_x").range(1, 9).default(json!(4)),
+                int("components_y").range(1, 9).default(json!(3)),
+                ToolParam::new("thumbnail", "bool").default(json!(true)),
+                int("thumb_size").range(1, 64).default(json!(16)),
+                orient(),
+            ],
+        ].concat()),
+        tool("dominant-crop", "Crop to the most colorful region at a given aspect ratio", image_params(vec![
+            string("ratio").default(json!("1:1")),
+            float("scale").range(0.0, 1.0).default(json!(1.0)),
+        ])),
+        tool("stego-embed", "Hide a text message in image pixels (LSB)", image_params(vec![
+            string("message").required(),
+        ])),
+        tool("stego-extract", "Recover a text message hidden with stego-embed", [
+            input_params(),
+            vec![orient()],
+        ].concat()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_tool_lists_its_size_params() {
+        let tools = get_tools();
+        let resize = tools.iter().find(|tool| tool.name == "resize").unwrap();
+        let names: Vec<_> = resize.parameters.iter().map(|param| param.name).collect();
+        for name in ["width", "height", "percentage", "max_size"] {
+            assert!(names.contains(&name), "resize is missing {}", name);
+        }
+
+        let json = serde_json::to_value(resize).unwrap();
+        assert_eq!(json["name"], "resize");
+        assert!(json["description"].is_string());
+    }
+}
@@ -1,9 +1,10 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
     Json,
 };
+use std::sync::OnceLock;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -23,6 +24,12 @@ pub enum AppError {
     #[error("Invalid field value: {0}")]
     InvalidFieldValue(String),
     
+    #[error("Corrupt image: {0}")]
+    CorruptImage(String),
+    
+    #[error("Image dimensions too large: {0}")]
+    DimensionsTooLarge(String),
+    
     #[error("Image processing error: {0}")]
     ImageProcessingError(String),
     
@@ -39,17 +46,38 @@ impl IntoResponse for AppError {
             AppError::InvalidImageFormat | AppError::UnsupportedImageFormat => {
                 (StatusCode::UNSUPPORTED_MEDIA_TYPE, self.to_string())
             }
-            AppError::FileTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, self.to_string()),
+            AppError::FileTooLarge | AppError::DimensionsTooLarge(_) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, self.to_string())
+            }
             AppError::MissingField(_) | AppError::InvalidFieldValue(_) => {
                 (StatusCode::BAD_REQUEST, self.to_string())
             }
+            AppError::CorruptImage(_) => (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()),
             AppError::ImageProcessingError(_) | AppError::IoError(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
             }
             AppError::NotImplemented => (StatusCode::NOT_IMPLEMENTED, self.to_string()),
         };
 
-        (status, Json(serde_json::json!({ "error": message }))).into_response()
+        (status, Json(serde_json::json!({ "error": message, "code": self.code() }))).into_response()
+    }
+}
+
+impl AppError {
+    // Stable machine-readable error code for clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::InvalidImageFormat => "INVALID_IMAGE_FORMAT",
+            AppError::FileTooLarge => "FILE_TOO_LARGE",
+            AppError::UnsupportedImageFormat => "UNSUPPORTED_IMAGE_FORMAT",
+            AppError::MissingField(_) => "MISSING_FIELD",
+            AppError::InvalidFieldValue(_) => "INVALID_FIELD_VALUE",
+            AppError::CorruptImage(_) => "CORRUPT_IMAGE",
+            AppError::DimensionsTooLarge(_) => "DIMENSIONS_TOO_LARGE",
+            AppError::ImageProcessingError(_) => "IMAGE_PROCESSING_ERROR",
+            AppError::IoError(_) => "IO_ERROR",
+            AppError::NotImplemented => "NOT_IMPLEMENTED",
+        }
     }
 }
 
@@ -61,17 +89,106 @@ pub struct ImageInfo {
     pub width: u32,
     pub height: u32,
     pub mode: String,
+    // Entries in the PLTE chunk of an indexed PNG
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette_size: Option<usize>,
+    // Width over height, and reduced to lowest terms ("16:9"); omitted for
+    // zero-sized images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio_string: Option<String>,
+    // Only when requested with `count_colors`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unique_colors: Option<ColorCount>,
+    // From the container: PNG/GIF/BMP and VP8L WebP are lossless, JPEG and
+    // VP8 WebP lossy; omitted when the format can be either
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_lossless: Option<bool>,
+    pub file_size: u64,
+    pub file_size_human: String,
+}
+
+// Distinct RGBA colors: the exact number, or ">N" once counting stopped at
+// the cap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorCount {
+    Exact(usize),
+    MoreThan(usize),
+}
+
+impl Serialize for ColorCount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ColorCount::Exact(count) => serializer.serialize_u64(*count as u64),
+            ColorCount::MoreThan(cap) => serializer.serialize_str(&format!(">{}", cap)),
+        }
+    }
+}
+
+// What `/info` reports when only the header was read
+#[derive(Debug, Serialize)]
+pub struct DimensionsInfo {
+    pub file: String,
+    pub format: String,
+    pub dimensions: String,
+    pub width: u32,
+    pub height: u32,
     pub file_size: u64,
     pub file_size_human: String,
 }
 
+// What `/detect-format` reports, including formats no endpoint can decode
+#[derive(Debug, Serialize)]
+pub struct FormatDetection {
+    pub mime: &'static str,
+    pub extension: &'static str,
+    // "high" for a binary signature, "medium" for a short or textual match
+    pub confidence: &'static str,
+    // Whether the processing endpoints accept this file
+    pub decodable: bool,
+}
+
+// A detected face, in pixels of the (oriented) input
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FaceBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub confidence: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FaceCount {
+    pub count: usize,
+    pub boxes: Vec<FaceBox>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct MetadataInfo {
     pub exif: serde_json::Value,
 }
 
+#[derive(Debug, Serialize)]
+pub struct XmpInfo {
+    pub title: Option<String>,
+    pub creator: Vec<String>,
+    pub rating: Option<String>,
+    pub copyright: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FormatSize {
+    pub format: String,
+    pub size: u64,
+    pub size_human: String,
+    // None when the output can't be decoded for comparison (AVIF)
+    pub psnr: Option<f64>,
+}
+
 // Supported image formats
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ImageFormat {
     Jpeg,
     Png,
@@ -79,6 +196,9 @@ pub enum ImageFormat {
     Bmp,
     Tiff,
     Gif,
+    Ico,
+    // Camera RAW, input only
+    Raw,
 }
 
 impl ImageFormat {
@@ -88,12 +208,14 @@ impl ImageFormat {
         }
 
         match bytes {
+            _ if crate::camera_raw::is_camera_raw(bytes) => Some(ImageFormat::Raw),
             [0xFF, 0xD8, 0xFF, ..] => Some(ImageFormat::Jpeg),
             [0x89, 0x50, 0x4E, 0x47, ..] => Some(ImageFormat::Png),
             [0x52, 0x49, 0x46, 0x46, ..] if bytes.len() >= 12 && &bytes[8..12] == b"WEBP" => Some(ImageFormat::Webp),
             [0x42, 0x4D, ..] => Some(ImageFormat::Bmp),
             [0x49, 0x49, ..] | [0x4D, 0x4D, ..] => Some(ImageFormat::Tiff),
             [0x47, 0x49, 0x46, 0x38, ..] => Some(ImageFormat::Gif),
+            [0x00, 0x00, 0x01, 0x00, ..] => Some(ImageFormat::Ico),
             _ => None,
         }
     }
@@ -106,6 +228,8 @@ impl ImageFormat {
             ImageFormat::Bmp => "bmp",
             ImageFormat::Tiff => "tiff",
             ImageFormat::Gif => "gif",
+            ImageFormat::Ico => "ico",
+            ImageFormat::Raw => "raw",
         }
     }
 
@@ -117,6 +241,8 @@ impl ImageFormat {
             ImageFormat::Bmp => "image/bmp",
             ImageFormat::Tiff => "image/tiff",
             ImageFormat::Gif => "image/gif",
+            ImageFormat::Ico => "image/x-icon",
+            ImageFormat::Raw => "application/octet-stream",
         }
     }
 }
@@ -124,19 +250,580 @@ impl ImageFormat {
 impl std::str::FromStr for ImageFormat {
     type Err = AppError;
 
+    // Forgiving about case, surrounding whitespace and a leading dot, so
+    // " .JPG " and "jfif" both work
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
+        let name = s.trim();
+        match name.strip_prefix('.').unwrap_or(name).to_lowercase().as_str() {
+            "jpg" | "jpeg" | "jpe" | "jfif" => Ok(ImageFormat::Jpeg),
             "png" => Ok(ImageFormat::Png),
             "webp" => Ok(ImageFormat::Webp),
             "bmp" => Ok(ImageFormat::Bmp),
             "tiff" | "tif" => Ok(ImageFormat::Tiff),
             "gif" => Ok(ImageFormat::Gif),
+            "ico" => Ok(ImageFormat::Ico),
+            "raw" | "cr2" | "nef" | "arw" | "dng" | "orf" | "rw2" | "raf" => Ok(ImageFormat::Raw),
             _ => Err(AppError::UnsupportedImageFormat),
         }
     }
 }
 
+// Gaussian is exact but slows down with large sigma; box approximates it
+// with three box passes whose cost doesn't depend on the radius
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlurAlgorithm {
+    Gaussian,
+    Box,
+}
+
+impl std::str::FromStr for BlurAlgorithm {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "gaussian" => Ok(BlurAlgorithm::Gaussian),
+            "box" => Ok(BlurAlgorithm::Box),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid blur_algo '{}'. Expected 'gaussian' or 'box'",
+                s
+            ))),
+        }
+    }
+}
+
+// How blur-face hides a region: blurred, pixelated into blocks, or painted
+// over with a solid color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CensorStyle {
+    Gaussian,
+    Pixelate,
+    Fill,
+}
+
+impl std::str::FromStr for CensorStyle {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "gaussian" => Ok(CensorStyle::Gaussian),
+            "pixelate" => Ok(CensorStyle::Pixelate),
+            "fill" => Ok(CensorStyle::Fill),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid style '{}'. Expected 'gaussian', 'pixelate' or 'fill'",
+                s
+            ))),
+        }
+    }
+}
+
+// Censor the whole region, or only the ellipse inscribed in it so the mask
+// follows the oval of a face
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CensorShape {
+    Box,
+    Ellipse,
+}
+
+impl std::str::FromStr for CensorShape {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "box" => Ok(CensorShape::Box),
+            "ellipse" => Ok(CensorShape::Ellipse),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid shape '{}'. Expected 'box' or 'ellipse'",
+                s
+            ))),
+        }
+    }
+}
+
+// Median removes salt-and-pepper specks; bilateral smooths sensor noise
+// while keeping edges that differ by more than `strength`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DenoiseMethod {
+    Median,
+    Bilateral,
+}
+
+impl std::str::FromStr for DenoiseMethod {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "median" => Ok(DenoiseMethod::Median),
+            "bilateral" => Ok(DenoiseMethod::Bilateral),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid method '{}'. Expected 'median' or 'bilateral'",
+                s
+            ))),
+        }
+    }
+}
+
+// How resize fills the bars when letterboxing into a fixed box: a solid
+// color, the image mirrored or its edge pixels stretched outward, or a
+// blurred, enlarged copy of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PadMode {
+    Color,
+    Reflect,
+    Edge,
+    Blur,
+}
+
+impl std::str::FromStr for PadMode {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "color" => Ok(PadMode::Color),
+            "reflect" => Ok(PadMode::Reflect),
+            "edge" => Ok(PadMode::Edge),
+            "blur" => Ok(PadMode::Blur),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid pad_mode '{}'. Expected 'color', 'reflect', 'edge' or 'blur'",
+                s
+            ))),
+        }
+    }
+}
+
+// How scale-to-print matches the paper: `fill` covers it exactly, cropping
+// the overflow; `fit` keeps the whole image, leaving one side short
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrintFit {
+    Fit,
+    Fill,
+}
+
+impl std::str::FromStr for PrintFit {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "fit" => Ok(PrintFit::Fit),
+            "fill" => Ok(PrintFit::Fill),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid fit '{}'. Expected 'fit' or 'fill'",
+                s
+            ))),
+        }
+    }
+}
+
+// compare-visual output: `after` blended over `before`, or the two next to
+// each other
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareMode {
+    Overlay,
+    SideBySide,
+}
+
+impl std::str::FromStr for CompareMode {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "overlay" => Ok(CompareMode::Overlay),
+            "side-by-side" => Ok(CompareMode::SideBySide),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid mode '{}'. Expected 'overlay' or 'side-by-side'",
+                s
+            ))),
+        }
+    }
+}
+
+// Resampling for arbitrary-angle rotation. Nearest never invents new pixel
+// values, which matters for label/mask images.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Nearest,
+    Bilinear,
+    Bicubic,
+}
+
+impl std::str::FromStr for Interpolation {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "nearest" => Ok(Interpolation::Nearest),
+            "bilinear" => Ok(Interpolation::Bilinear),
+            "bicubic" => Ok(Interpolation::Bicubic),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid interpolation '{}'. Expected 'nearest', 'bilinear' or 'bicubic'",
+                s
+            ))),
+        }
+    }
+}
+
+// Resampling filter for resizes. Lanczos3 is sharpest; nearest keeps hard
+// pixel edges for pixel art.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResizeFilter {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Gaussian,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResizeFilter::Nearest => "nearest",
+            ResizeFilter::Bilinear => "bilinear",
+            ResizeFilter::Bicubic => "bicubic",
+            ResizeFilter::Gaussian => "gaussian",
+            ResizeFilter::Lanczos3 => "lanczos3",
+        }
+    }
+}
+
+impl std::str::FromStr for ResizeFilter {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "nearest" => Ok(ResizeFilter::Nearest),
+            "bilinear" | "triangle" => Ok(ResizeFilter::Bilinear),
+            "bicubic" | "catmullrom" => Ok(ResizeFilter::Bicubic),
+            "gaussian" => Ok(ResizeFilter::Gaussian),
+            "lanczos3" | "lanczos" => Ok(ResizeFilter::Lanczos3),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid filter '{}'. Expected nearest, bilinear, bicubic, gaussian or lanczos3",
+                s
+            ))),
+        }
+    }
+}
+
+static DEFAULT_RESIZE_FILTER: OnceLock<ResizeFilter> = OnceLock::new();
+
+// Filter used when a request doesn't send `filter`, from the RESIZE_FILTER
+// env var (default lanczos3)
+pub fn default_resize_filter() -> ResizeFilter {
+    *DEFAULT_RESIZE_FILTER.get_or_init(|| {
+        std::env::var("RESIZE_FILTER")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(ResizeFilter::Lanczos3)
+    })
+}
+
+// Chroma subsampling used for JPEG output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChromaSubsampling {
+    Yuv444,
+    Yuv422,
+    Yuv420,
+}
+
+impl std::str::FromStr for ChromaSubsampling {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "444" | "4:4:4" => Ok(ChromaSubsampling::Yuv444),
+            "422" | "4:2:2" => Ok(ChromaSubsampling::Yuv422),
+            "420" | "4:2:0" => Ok(ChromaSubsampling::Yuv420),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid subsampling '{}'. Expected 444, 422 or 420",
+                s
+            ))),
+        }
+    }
+}
+
+// Compression scheme used for TIFF output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+    Packbits,
+}
+
+impl std::str::FromStr for TiffCompression {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(TiffCompression::None),
+            "lzw" => Ok(TiffCompression::Lzw),
+            "deflate" => Ok(TiffCompression::Deflate),
+            "packbits" => Ok(TiffCompression::Packbits),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid tiff_compression '{}'. Expected none, lzw, deflate or packbits",
+                s
+            ))),
+        }
+    }
+}
+
+// Channel layout requested for converted output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    Rgb,
+    Rgba,
+    Grayscale,
+    Bilevel,
+}
+
+impl std::str::FromStr for ColorMode {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "rgb" => Ok(ColorMode::Rgb),
+            "rgba" => Ok(ColorMode::Rgba),
+            "grayscale" | "gray" => Ok(ColorMode::Grayscale),
+            "bilevel" => Ok(ColorMode::Bilevel),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid color_mode '{}'. Expected rgb, rgba, grayscale or bilevel",
+                s
+            ))),
+        }
+    }
+}
+
+// How quantization error is handled when reducing colors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dither {
+    None,
+    FloydSteinberg,
+    Ordered,
+}
+
+impl std::str::FromStr for Dither {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Dither::None),
+            "floyd-steinberg" | "floyd_steinberg" => Ok(Dither::FloydSteinberg),
+            "ordered" => Ok(Dither::Ordered),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid dither '{}'. Expected none, floyd-steinberg or ordered",
+                s
+            ))),
+        }
+    }
+}
+
+static DEFAULT_QUALITY: OnceLock<u8> = OnceLock::new();
+
+// Lossy quality used when a request doesn't send `quality`, from the
+// DEFAULT_QUALITY env var (1-100, default 80)
+pub fn default_quality() -> u8 {
+    *DEFAULT_QUALITY.get_or_init(|| parse_default_quality(std::env::var("DEFAULT_QUALITY").ok().as_deref()))
+}
+
+fn parse_default_quality(value: Option<&str>) -> u8 {
+    value
+        .and_then(|value| value.trim().parse::<u32>().ok())
+        .map(|value| value.clamp(1, 100) as u8)
+        .unwrap_or(80)
+}
+
+pub const DEFAULT_EFFORT: u8 = 4;
+
+// Encoder settings shared by every handler that writes an image
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    // JPEG/WebP quality, 1-100
+    pub quality: u8,
+    pub subsampling: ChromaSubsampling,
+    // PNG zlib level 0-9; None uses the encoder's fast default
+    pub compression_level: Option<u8>,
+    pub progressive: bool,
+    // JPEG only: build Huffman tables for this image (smaller, slower)
+    pub optimize: bool,
+    // JPEG only: restart marker every N MCUs, so a damaged stream can resync
+    pub restart_interval: Option<u16>,
+    // WebP only: lossless instead of lossy VP8
+    pub lossless: bool,
+    pub tiff_compression: TiffCompression,
+    // Speed vs size, 0 (fastest) to 9 (smallest), mapped onto each format's
+    // own knob. An explicit compression_level or optimize takes precedence.
+    pub effort: u8,
+    // JPEG/PNG only: resolution to record in the JFIF/pHYs header. Set by
+    // handlers rather than a form field.
+    pub dpi: Option<u16>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            quality: default_quality(),
+            subsampling: ChromaSubsampling::Yuv420,
+            compression_level: None,
+            progressive: false,
+            optimize: false,
+            restart_interval: None,
+            lossless: false,
+            tiff_compression: TiffCompression::Lzw,
+            effort: DEFAULT_EFFORT,
+            dpi: None,
+        }
+    }
+}
+
+impl EncodeOptions {
+    // Multipart field names understood by `set_field`
+    pub const FIELDS: &'static [&'static str] =
+        &["quality", "subsampling", "compression_level", "progressive", "optimize", "restart_interval", "lossless", "tiff_compression", "effort"];
+
+    // Update one option from its multipart field value
+    pub fn set_field(&mut self, name: &str, value: &str) -> Result<(), AppError> {
+        let value = value.trim();
+        let invalid = || AppError::InvalidFieldValue(format!("Invalid {}: {}", name, value));
+
+        match name {
+            "quality" => self.quality = value.parse::<u8>().map_err(|_| invalid())?.clamp(1, 100),
+            "subsampling" => self.subsampling = value.parse()?,
+            "compression_level" => {
+                self.compression_level = Some(value.parse::<u8>().map_err(|_| invalid())?.min(9))
+            }
+            "progressive" => self.progressive = value.parse().map_err(|_| invalid())?,
+            "optimize" => self.optimize = value.parse().map_err(|_| invalid())?,
+            "restart_interval" => {
+                self.restart_interval = Some(value.parse::<u16>().map_err(|_| invalid())?).filter(|&n| n > 0)
+            }
+            "lossless" => self.lossless = value.parse().map_err(|_| invalid())?,
+            "tiff_compression" => self.tiff_compression = value.parse()?,
+            "effort" => self.effort = value.parse::<u8>().map_err(|_| invalid())?.min(9),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn default_shape_color() -> String {
+    "red".to_string()
+}
+
+fn default_stroke_width() -> u32 {
+    2
+}
+
+fn default_text_size() -> f32 {
+    24.0
+}
+
+// One named output size for edit's `variants`. With only one side the other
+// follows the aspect ratio; with both the image fits inside the box.
+#[derive(Debug, Deserialize)]
+pub struct Variant {
+    pub name: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+// Edit's `levels`: per-channel black/white points, e.g.
+// {"r":{"in":[10,245],"out":[0,255]}}. Channels left out are unchanged.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Levels {
+    pub r: Option<ChannelLevels>,
+    pub g: Option<ChannelLevels>,
+    pub b: Option<ChannelLevels>,
+}
+
+// `in` is stretched linearly onto `out`; values outside `in` clip
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelLevels {
+    #[serde(rename = "in", default = "full_range")]
+    pub input: [u8; 2],
+    #[serde(default = "full_range")]
+    pub out: [u8; 2],
+}
+
+fn full_range() -> [u8; 2] {
+    [0, 255]
+}
+
+// A single drawing instruction for the annotate endpoint
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Shape {
+    Rect {
+        x: i32,
+        y: i32,
+        w: u32,
+        h: u32,
+        #[serde(default = "default_shape_color")]
+        color: String,
+        #[serde(default)]
+        fill: bool,
+        #[serde(default = "default_stroke_width")]
+        stroke_width: u32,
+    },
+    Line {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+        #[serde(default = "default_shape_color")]
+        color: String,
+        #[serde(default = "default_stroke_width")]
+        stroke_width: u32,
+    },
+    Text {
+        x: i32,
+        y: i32,
+        text: String,
+        #[serde(default = "default_shape_color")]
+        color: String,
+        #[serde(default = "default_text_size")]
+        size: f32,
+    },
+}
+
+// Encoded bytes plus side effects the client may want to know about
+#[derive(Debug)]
+pub struct EncodedImage {
+    pub data: Vec<u8>,
+    // Background color transparent pixels were composited onto, if any
+    pub alpha_flattened: Option<[u8; 3]>,
+}
+
+// How the client should treat a returned file
+#[derive(Debug, Clone, Copy)]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+impl Disposition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Disposition::Inline => "inline",
+            Disposition::Attachment => "attachment",
+        }
+    }
+}
+
+impl std::str::FromStr for Disposition {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "inline" => Ok(Disposition::Inline),
+            "attachment" => Ok(Disposition::Attachment),
+            _ => Err(AppError::InvalidFieldValue(format!(
+                "Invalid disposition '{}'. Expected 'inline' or 'attachment'",
+                s
+            ))),
+        }
+    }
+}
+
 pub fn format_file_size(size: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
     let mut size = size as f64;
@@ -152,4 +839,74 @@ pub fn format_file_size(size: u64) -> String {
     } else {
         format!("{:.1} {}", size, UNITS[unit_index])
     }
-}
\ No newline at end of file
+}
+// Width/height as a float and as a reduced "w:h" string, or None when either
+// side is zero
+pub fn aspect_ratio(width: u32, height: u32) -> Option<(f64, String)> {
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let (mut a, mut b) = (width, height);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    Some((width as f64 / height as f64, format!("{}:{}", width / a, height / a)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_options_default_and_parse_from_fields() {
+        let defaults = EncodeOptions::default();
+        assert_eq!(defaults.quality, default_quality());
+        assert_eq!(defaults.subsampling, ChromaSubsampling::Yuv420);
+        assert_eq!(defaults.tiff_compression, TiffCompression::Lzw);
+        assert_eq!((defaults.compression_level, defaults.restart_interval), (None, None));
+        assert!(!defaults.progressive && !defaults.optimize && !defaults.lossless);
+
+        let mut options = EncodeOptions::default();
+        for (name, value) in [
+            ("quality", " 250 "),
+            ("subsampling", "4:4:4"),
+            ("compression_level", "12"),
+            ("progressive", "true"),
+            ("restart_interval", "0"),
+            ("tiff_compression", "Deflate"),
+            ("effort", "7"),
+        ] {
+            options.set_field(name, value).unwrap();
+        }
+        // Out-of-range numbers clamp; a zero restart interval means none
+        assert_eq!(options.quality, 100);
+        assert_eq!(options.subsampling, ChromaSubsampling::Yuv444);
+        assert_eq!(options.compression_level, Some(9));
+        assert!(options.progressive);
+        assert_eq!(options.restart_interval, None);
+        assert_eq!(options.tiff_compression, TiffCompression::Deflate);
+        assert_eq!(options.effort, 7);
+
+        for (name, value) in [("quality", "high"), ("subsampling", "411"), ("lossless", "yes")] {
+            assert!(matches!(options.set_field(name, value), Err(AppError::InvalidFieldValue(_))), "{}={}", name, value);
+        }
+    }
+
+    #[test]
+    fn default_quality_env_is_parsed_and_clamped() {
+        assert_eq!(parse_default_quality(Some("50")), 50);
+        assert_eq!(parse_default_quality(Some(" 0 ")), 1);
+        assert_eq!(parse_default_quality(Some("250")), 100);
+        assert_eq!(parse_default_quality(Some("high")), 80);
+        assert_eq!(parse_default_quality(None), 80);
+    }
+
+    #[test]
+    fn format_names_are_normalized() {
+        assert_eq!(" .JPG ".parse::<ImageFormat>().unwrap(), ImageFormat::Jpeg);
+        assert_eq!("jfif".parse::<ImageFormat>().unwrap(), ImageFormat::Jpeg);
+        assert_eq!("tif".parse::<ImageFormat>().unwrap(), ImageFormat::Tiff);
+        assert!(matches!("jpgx".parse::<ImageFormat>(), Err(AppError::UnsupportedImageFormat)));
+    }
+}
@@ -1,18 +1,173 @@
 use crate::types::{AppError, ImageFormat};
+use std::ops::{Bound, RangeBounds};
+use std::sync::OnceLock;
 
-const MAX_UPLOAD_SIZE: usize = 20 * 1024 * 1024; // 20MB
+pub const MAX_UPLOAD_SIZE: usize = 20 * 1024 * 1024; // 20MB
+
+// Decoded-size limits, so a small but highly compressible file can't expand
+// into hundreds of megapixels. Configured via MAX_WIDTH, MAX_HEIGHT and
+// MAX_PIXELS.
+struct DimensionLimits {
+    max_width: u32,
+    max_height: u32,
+    max_pixels: u64,
+}
+
+static DIMENSION_LIMITS: OnceLock<DimensionLimits> = OnceLock::new();
+
+pub fn env_limit<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+fn dimension_limits() -> &'static DimensionLimits {
+    DIMENSION_LIMITS.get_or_init(|| DimensionLimits {
+        max_width: env_limit("MAX_WIDTH", 16_384),
+        max_height: env_limit("MAX_HEIGHT", 16_384),
+        max_pixels: env_limit("MAX_PIXELS", 100_000_000),
+    })
+}
+
+pub fn validate_dimensions(width: u32, height: u32) -> Result<(), AppError> {
+    let limits = dimension_limits();
+    if width > limits.max_width || height > limits.max_height {
+        return Err(AppError::DimensionsTooLarge(format!(
+            "{}x{} exceeds the {}x{} limit",
+            width, height, limits.max_width, limits.max_height
+        )));
+    }
+    if width as u64 * height as u64 > limits.max_pixels {
+        return Err(AppError::DimensionsTooLarge(format!(
+            "{}x{} exceeds the {} pixel limit",
+            width, height, limits.max_pixels
+        )));
+    }
+    Ok(())
+}
+
+// Decoded animations hold every frame in memory, so all of them together
+// have to fit in the pixel limit
+pub fn validate_frame_count(width: u32, height: u32, frames: u64) -> Result<(), AppError> {
+    let limits = dimension_limits();
+    if width as u64 * height as u64 * frames > limits.max_pixels {
+        return Err(AppError::DimensionsTooLarge(format!(
+            "{} frames of {}x{} exceed the {} pixel limit",
+            frames, width, height, limits.max_pixels
+        )));
+    }
+    Ok(())
+}
+
+// Check an integer upscale against the limits before allocating the output,
+// naming the largest scale that would still fit
+pub fn validate_upscale(width: u32, height: u32, scale: u32) -> Result<(), AppError> {
+    let limits = dimension_limits();
+    let fits = |s: u64| {
+        width as u64 * s <= limits.max_width as u64
+            && height as u64 * s <= limits.max_height as u64
+            && width as u64 * height as u64 * s * s <= limits.max_pixels
+    };
+    if fits(scale as u64) {
+        return Ok(());
+    }
+
+    let max_scale = (1..scale as u64).rev().find(|&s| fits(s)).unwrap_or(0);
+    Err(AppError::DimensionsTooLarge(format!(
+        "{}x upscale of {}x{} would be {}x{}, over the {}x{} / {} pixel limit; the largest allowed scale is {}x",
+        scale,
+        width,
+        height,
+        width as u64 * scale as u64,
+        height as u64 * scale as u64,
+        limits.max_width,
+        limits.max_height,
+        limits.max_pixels,
+        max_scale
+    )))
+}
+
+// Input formats from ALLOWED_INPUT_FORMATS (comma-separated, e.g.
+// "jpeg,png"), so a deployment can refuse TIFF/BMP bombs outright. None
+// when unset, which allows every supported format.
+static ALLOWED_INPUT_FORMATS: OnceLock<Option<Vec<ImageFormat>>> = OnceLock::new();
+
+fn allowed_input_formats() -> Option<&'static [ImageFormat]> {
+    ALLOWED_INPUT_FORMATS
+        .get_or_init(|| {
+            let value = std::env::var("ALLOWED_INPUT_FORMATS").ok().filter(|v| !v.trim().is_empty())?;
+            Some(parse_allowed_formats(&value))
+        })
+        .as_deref()
+}
+
+fn parse_allowed_formats(value: &str) -> Vec<ImageFormat> {
+    value
+        .split(',')
+        .filter(|name| !name.trim().is_empty())
+        .filter_map(|name| match name.parse() {
+            Ok(format) => Some(format),
+            Err(_) => {
+                tracing::warn!("Ignoring unknown format '{}' in ALLOWED_INPUT_FORMATS", name.trim());
+                None
+            }
+        })
+        .collect()
+}
 
 pub fn validate_upload(data: &[u8]) -> Result<ImageFormat, AppError> {
+    check_upload(data, allowed_input_formats())
+}
+
+fn check_upload(data: &[u8], allowed: Option<&[ImageFormat]>) -> Result<ImageFormat, AppError> {
     // Check file size
     if data.len() > MAX_UPLOAD_SIZE {
         return Err(AppError::FileTooLarge);
     }
 
     // Check magic bytes to determine format
-    match ImageFormat::from_magic_bytes(data) {
-        Some(format) => Ok(format),
-        None => Err(AppError::UnsupportedImageFormat),
+    let format = ImageFormat::from_magic_bytes(data).ok_or(AppError::UnsupportedImageFormat)?;
+    if allowed.is_some_and(|allowed| !allowed.contains(&format)) {
+        return Err(AppError::UnsupportedImageFormat);
+    }
+    Ok(format)
+}
+
+// Parse a float field. `f32::from_str` happily accepts "NaN" and "inf",
+// which then poison blending and geometry math, so only finite values inside
+// `range` pass.
+pub fn parse_finite_f32(name: &str, text: &str, range: impl RangeBounds<f32>) -> Result<f32, AppError> {
+    let expected = match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(min), Bound::Included(max)) => format!(" (expected {} to {})", min, max),
+        (Bound::Included(min), Bound::Unbounded) => format!(" (expected at least {})", min),
+        (Bound::Excluded(min), Bound::Included(max)) => format!(" (expected more than {}, up to {})", min, max),
+        (Bound::Unbounded, Bound::Included(max)) => format!(" (expected at most {})", max),
+        _ => String::new(),
+    };
+
+    text.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite() && range.contains(value))
+        .ok_or_else(|| AppError::InvalidFieldValue(format!("Invalid {}: {}{}", name, text.trim(), expected)))
+}
+
+// Parse an integer field. A value that doesn't parse is a client error, not
+// a reason to fall back to the default.
+pub fn parse_int<T: std::str::FromStr>(name: &str, text: &str) -> Result<T, AppError> {
+    text.trim()
+        .parse()
+        .map_err(|_| AppError::InvalidFieldValue(format!("Invalid {}: {}", name, text.trim())))
+}
+
+// Palette size for indexed PNG output
+pub fn parse_palette_colors(text: &str) -> Result<u32, AppError> {
+    let colors: u32 = parse_int("colors", text)?;
+    if !(2..=256).contains(&colors) {
+        return Err(AppError::InvalidFieldValue(format!("Invalid colors: {} (expected 2-256)", colors)));
     }
+    Ok(colors)
 }
 
 pub fn parse_crop_ratio(ratio: &str) -> Result<(u32, u32), AppError> {
@@ -73,4 +228,63 @@ pub fn parse_region(region: &str) -> Result<(u32, u32, u32, u32), AppError> {
     }
 
     Ok((x, y, w, h))
-}
\ No newline at end of file
+}
+
+// Four corners as "x1,y1,x2,y2,x3,y3,x4,y4", in the order top-left,
+// top-right, bottom-right, bottom-left
+pub fn parse_corner_points(points: &str) -> Result<[(f32, f32); 4], AppError> {
+    let values = points
+        .split(',')
+        .map(|part| part.trim().parse::<f32>().ok().filter(|v| v.is_finite()))
+        .collect::<Option<Vec<f32>>>()
+        .filter(|values| values.len() == 8)
+        .ok_or_else(|| AppError::InvalidFieldValue(format!(
+            "Invalid points format. Expected 'x1,y1,x2,y2,x3,y3,x4,y4', got '{}'",
+            points
+        )))?;
+
+    Ok([
+        (values[0], values[1]),
+        (values[2], values[3]),
+        (values[4], values[5]),
+        (values[6], values[7]),
+    ])
+}
+
+// Base name of a client-supplied filename without its extension, limited to
+// characters that are safe in headers and archive entries
+pub fn sanitize_file_stem(file_name: &str) -> Option<String> {
+    let base = file_name.rsplit(['/', '\\']).next().unwrap_or(file_name);
+    let stem = match base.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => base,
+    };
+
+    let sanitized: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .take(100)
+        .collect();
+    let trimmed = sanitized.trim_matches('_');
+
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::encode;
+    use image::DynamicImage;
+
+    #[test]
+    fn uploads_outside_the_allowed_formats_are_rejected() {
+        let allowed = parse_allowed_formats("jpeg, png,");
+        assert_eq!(allowed, [ImageFormat::Jpeg, ImageFormat::Png]);
+
+        let img = DynamicImage::new_rgb8(4, 4);
+        let bmp = encode(&img, ImageFormat::Bmp);
+        assert_eq!(check_upload(&bmp, None).unwrap(), ImageFormat::Bmp);
+        assert!(matches!(check_upload(&bmp, Some(&allowed)), Err(AppError::UnsupportedImageFormat)));
+        assert_eq!(check_upload(&encode(&img, ImageFormat::Png), Some(&allowed)).unwrap(), ImageFormat::Png);
+    }
+}